@@ -12,6 +12,8 @@ extends Control
 #		added to steam, or just change the button to "yeah yay added" and flash green?
 
 const DEFAULT_MAX_FPS = 30
+# Ongoing actions which get a cancel button next to them
+const CANCELLABLE_ACTIONS = ["install", "reinstall"]
 const UPDATE_FILE = "/tmp/decktricks_did_update"
 var dd = DecktricksDispatcher
 
@@ -58,10 +60,15 @@ func update_action_button(
 	is_available: bool,
 	is_ongoing: bool,
 	is_completed: bool,
+	unavailable_reason: String,
 ) -> void:
 	action_button.set_name(identifier)
 	action_button.set_text(display_text)
-	action_button.set_visible(is_available)
+	# Unavailable actions stay visible (but can't be pressed), so that the reason can be seen
+	if not action_button.button_known_ongoing_state:
+		action_button.disabled = not is_available
+	# Empty when the action is available, which also clears any old tooltip
+	action_button.tooltip_text = unavailable_reason
 
 	if is_completed:
 		action_button.modulate = Color.DARK_GRAY
@@ -84,14 +91,50 @@ func update_action_button(
 
 			action_button.button_tween = tween
 
+			if identifier in CANCELLABLE_ACTIONS:
+				add_cancel_button(action_button)
+
 	if not is_ongoing:
 		if action_button.button_known_ongoing_state:
 			action_button.button_known_ongoing_state = false
-			action_button.disabled = false
+			action_button.disabled = not is_available
 			action_button.set_modulate(action_button.button_original_color)
 
 			action_button.button_tween.kill()
 
+			if action_button.cancel_button:
+				action_button.cancel_button.queue_free()
+				action_button.cancel_button = null
+			if action_button.progress_bar:
+				action_button.progress_bar.queue_free()
+				action_button.progress_bar = null
+
+func update_action_progress(trick_id: String, percent: int, progress_text: String) -> void:
+	for action_button in get_tree().get_nodes_in_group("action_buttons"):
+		if not action_button.button_known_ongoing_state or action_button.get_trick_id() != trick_id:
+			continue
+
+		if not action_button.progress_bar:
+			var progress_bar := ProgressBar.new()
+			progress_bar.custom_minimum_size.x = 200
+			progress_bar.size_flags_vertical = Control.SIZE_SHRINK_CENTER
+			action_button.add_sibling(progress_bar)
+			action_button.progress_bar = progress_bar
+
+		# Without a percentage there's still something happening, just not a known amount of it
+		action_button.progress_bar.indeterminate = percent < 0
+		if percent >= 0:
+			action_button.progress_bar.value = percent
+		action_button.progress_bar.tooltip_text = progress_text
+
+func add_cancel_button(action_button: ActionButton):
+	var cancel_button := Button.new()
+	cancel_button.set_text("Cancel")
+	cancel_button.theme_type_variation = action_button.theme_type_variation
+	cancel_button.pressed.connect(action_button.cancel)
+	action_button.add_sibling(cancel_button)
+	action_button.cancel_button = cancel_button
+
 func popup_info_window(info: Dictionary):
 	var root = get_tree().root
 	var dialog: AcceptDialog = INFO_WINDOW.instantiate()
@@ -104,6 +147,24 @@ func popup_info_window(info: Dictionary):
 	root.add_child(dialog)
 	dialog.popup_centered_ratio(0.8)
 
+# Shown for actions with warnings from the config, and for remote scripts which have changed since
+# they were last run. on_confirm is only called if the user confirms.
+func popup_confirm_window(info: Dictionary, on_confirm: Callable):
+	var root = get_tree().root
+	var dialog := ConfirmationDialog.new()
+	dialog.theme = theme
+	dialog.get_ok_button().set_text("Continue")
+	dialog.get_cancel_button().set_text("Cancel")
+
+	dialog.set_title(info["title"])
+	dialog.set_text(info["text"])
+	dialog.confirmed.connect(on_confirm)
+	dialog.visibility_changed.connect(func(): if not dialog.visible: dialog.queue_free())
+
+	root.add_child(dialog)
+	dialog.popup_centered_ratio(0.6)
+	dialog.get_cancel_button().grab_focus()
+
 func _on_ui_refresh_timer_timeout() -> void:
 	dd.async_refresh_system_context()
 	dd.log(4, "UI refresh update sent.")
@@ -117,6 +178,15 @@ func _on_context_was_updated() -> void:
 func _on_show_info_window(info: Dictionary) -> void:
 	popup_info_window(info)
 
+func _on_confirm_action(action_button: ActionButton, info: Dictionary) -> void:
+	popup_confirm_window(info, action_button.run_confirmed)
+
+func review_script_changes(trick_id: String, action_id: String, info: Dictionary) -> void:
+	for action_button in get_tree().get_nodes_in_group("action_buttons"):
+		if action_button.get_trick_id() == trick_id and action_button.get_action_id() == action_id:
+			popup_confirm_window(info, action_button.run_accepting_changes)
+			return
+
 func _on_should_restart_decktricks_gui() -> void:
 	# NOTE: should maybe only exit if this file is actually removed:
 	DirAccess.remove_absolute(UPDATE_FILE)
@@ -163,6 +233,9 @@ func _ready():
 
 	# Hook up signals, most of which are sent from the Rust side:
 	dd.show_info_window.connect(_on_show_info_window)
+	dd.confirm_action.connect(_on_confirm_action)
+	dd.review_script_changes.connect(review_script_changes.call_deferred)
+	dd.action_progress.connect(update_action_progress.call_deferred)
 	dd.context_was_updated.connect(_on_context_was_updated)
 	dd.update_action_button.connect(update_action_button.call_deferred)
 	dd.initialize_action_button.connect(initialize_action_button.call_deferred)
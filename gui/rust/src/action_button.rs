@@ -4,6 +4,7 @@ use decktricks::prelude::*;
 use decktricks::rayon::spawn;
 use decktricks::tricks_status::ActionDisplayStatus;
 use decktricks::tricks_status::AllTricksStatus;
+use godot::classes::ProgressBar;
 use godot::classes::Tween;
 use std::time::Duration;
 
@@ -33,6 +34,12 @@ pub struct ActionButton {
     button_original_color: Color,
     #[var]
     override_text: GString,
+    // Shown next to this button by Main.gd while an install is ongoing
+    #[var]
+    cancel_button: Option<Gd<Button>>,
+    // Also shown by Main.gd while the action is ongoing, once it has reported any progress
+    #[var]
+    progress_bar: Option<Gd<ProgressBar>>,
 }
 
 #[godot_api]
@@ -55,6 +62,64 @@ impl IButton for ActionButton {
             return;
         }
 
+        // Main.gd calls run_confirmed once the user has agreed to the warnings
+        if !info.warnings.is_empty() {
+            let confirm_dict = dict! {
+                "title": info.trick.display_name.clone(),
+                "text": info.warnings.join("\n\n"),
+            };
+            DecktricksDispatcher::emit_confirm_action(self.to_gd(), confirm_dict);
+            return;
+        }
+
+        self.run_action(false);
+    }
+}
+
+#[godot_api]
+impl ActionButton {
+    #[func]
+    fn run_confirmed(&mut self) {
+        self.run_action(false);
+    }
+
+    // Main.gd calls this once the user has looked over a changed remote script and accepted it
+    #[func]
+    fn run_accepting_changes(&mut self) {
+        self.run_action(true);
+    }
+
+    #[func]
+    fn get_trick_id(&self) -> GString {
+        GString::from(self.info.trick.id.as_str())
+    }
+
+    #[func]
+    fn get_action_id(&self) -> GString {
+        GString::from(self.info.action_id.to_string())
+    }
+
+    // Kills whatever is being run for this trick, if it was started from this GUI
+    #[func]
+    fn cancel(&mut self) {
+        let trick_id = self.info.trick.id.clone();
+        let executor = DecktricksDispatcher::get_executor();
+        if executor.cancel(&trick_id) {
+            log!(get_ctx(), "Cancelling actions for \"{trick_id}\"...");
+        } else {
+            warn!(get_ctx(), "Nothing to cancel for \"{trick_id}\".");
+        }
+    }
+}
+
+impl ActionButton {
+    fn run_action(&self, accept_changes: bool) {
+        let info = &self.info;
+        let action = info.action_id.as_action(info.trick.id.clone());
+        let trick_id = info.trick.id.clone();
+        let action_id = info.action_id.to_string();
+        let display_name = info.trick.display_name.clone();
+
         if matches!(action, SpecificAction::AddToSteam { .. }) {
             DecktricksDispatcher::emit_added_to_steam();
         }
@@ -66,6 +131,7 @@ impl IButton for ActionButton {
             // NOTE: this log level override is very important, as it is used to make sure
             // we see output in the GUI logs
             cmd.log_level = Some(LogType::Info);
+            cmd.accept_changes = accept_changes;
 
             let executor = &DecktricksDispatcher::get_executor().clone();
             let (maybe_ctx, res) = executor.execute(&cmd);
@@ -86,6 +152,18 @@ impl IButton for ActionButton {
                         "Decktricks command {action:?} finished with success:\n\n{msg}"
                     );
                 }
+                Err(KnownError::RemoteScriptChanged(review)) => {
+                    warn!(
+                        &ctx,
+                        "Decktricks command {action:?} is waiting for changes to be accepted:\n\n{review}"
+                    );
+                    DecktricksDispatcher::emit_review_script_changes(
+                        &trick_id,
+                        &action_id,
+                        &display_name,
+                        review,
+                    );
+                }
                 Err(known_error) => {
                     error!(
                         &ctx,
@@ -115,6 +193,8 @@ impl ActionButton {
             button_tween: None,
             button_original_color: Default::default(),
             override_text: Default::default(),
+            cancel_button: None,
+            progress_bar: None,
         });
 
         DecktricksDispatcher::emit_initialize_action_button(action_button.clone());
@@ -129,6 +209,7 @@ impl ActionButton {
         let is_completed = info.is_completed;
         let display_text = info.action_id.get_display_name(is_ongoing, is_completed);
         let action_id = info.action_id.to_string();
+        let unavailable_reason = info.unavailable_reason.clone().unwrap_or_default();
 
         DecktricksDispatcher::emit_update_action_button(
             self.to_gd(),
@@ -137,6 +218,7 @@ impl ActionButton {
             is_available,
             is_ongoing,
             is_completed,
+            unavailable_reason,
         );
 
         //        let mut base = self.base_mut();
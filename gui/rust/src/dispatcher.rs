@@ -4,7 +4,9 @@ use crate::action_button::ActionButton;
 use crate::utils::{gderr, NodeExt};
 use crate::CRATE_DECKTRICKS_LOGGER;
 use decktricks::controller_layout::load_controller_config;
+use decktricks::progress::get_progress_line;
 use decktricks::rayon::spawn;
+use decktricks::script_review::ScriptReview;
 use decktricks::system_command_runners::SysCommandRunner;
 use decktricks::utils::get_decktricks_update_log_file_location;
 use decktricks::{inner_print, prelude::*};
@@ -26,12 +28,24 @@ use std::time::Instant;
 const NUM_EXECUTOR_READ_RETRIES: u8 = 10;
 
 static EXECUTOR_GUARD: LazyLock<RwLock<Arc<Executor>>> = LazyLock::new(|| {
-    RwLock::new(Arc::new(Executor::create_with_gather(
+    let mut executor = Executor::create_with_gather(
         ExecutorMode::Continuous,
         get_log_level(),
         CRATE_DECKTRICKS_LOGGER.clone(),
         None,
-    )))
+    );
+
+    // Progress from every action run by the GUI is passed on to Godot as it arrives. This runs
+    // for as long as the GUI does, so it gets its own thread instead of one from the rayon pool.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    executor.set_progress_sender(sender);
+    std::thread::spawn(move || {
+        for event in receiver {
+            DecktricksDispatcher::emit_action_progress(&event);
+        }
+    });
+
+    RwLock::new(Arc::new(executor))
 });
 
 pub(crate) fn get_ctx() -> GeneralExecutionContext {
@@ -54,9 +68,20 @@ impl DecktricksDispatcher {
     #[signal]
     fn show_info_window(info: Dictionary);
 
+    #[signal]
+    fn confirm_action(action_button: Gd<ActionButton>, info: Dictionary);
+
+    // Sent when a remote script has changed since it was last run, so it can be shown to the user
+    #[signal]
+    fn review_script_changes(trick_id: GString, action_id: GString, info: Dictionary);
+
     #[signal]
     fn context_was_updated();
 
+    // percent is -1 if it isn't known
+    #[signal]
+    fn action_progress(trick_id: GString, percent: i64, progress_text: GString);
+
     #[signal]
     fn initialize_action_button(action_button: Gd<ActionButton>);
 
@@ -67,6 +92,8 @@ impl DecktricksDispatcher {
         display_text: GString,
         is_available: bool,
         is_ongoing: bool,
+        is_completed: bool,
+        unavailable_reason: GString,
     );
 
     #[func]
@@ -280,6 +307,54 @@ impl DecktricksDispatcher {
         );
     }
 
+    pub fn emit_confirm_action(action_button: Gd<ActionButton>, info: Dictionary) {
+        let mut singleton = Self::get_singleton();
+        singleton.emit_signal(
+            &StringName::from("confirm_action"),
+            &[Variant::from(action_button), Variant::from(info)],
+        );
+    }
+
+    pub fn emit_review_script_changes(
+        trick_id: &str,
+        action_id: &str,
+        display_name: &str,
+        review: &ScriptReview,
+    ) {
+        let info = dict! {
+            "title": format!("{display_name}: Review Changes"),
+            "text": review.to_string(),
+        };
+
+        let mut singleton = Self::get_singleton();
+        singleton.emit_signal(
+            &StringName::from("review_script_changes"),
+            &[
+                Variant::from(GString::from(trick_id)),
+                Variant::from(GString::from(action_id)),
+                Variant::from(info),
+            ],
+        );
+    }
+
+    pub fn emit_action_progress(event: &ProgressEvent) {
+        // Progress for general actions (update-all) has nowhere to be shown yet
+        let Some(trick_id) = &event.trick_id else {
+            return;
+        };
+        let percent = event.update.get_percent().map_or(-1, i64::from);
+
+        let mut singleton = Self::get_singleton();
+        singleton.emit_signal(
+            &StringName::from("action_progress"),
+            &[
+                Variant::from(GString::from(trick_id.as_str())),
+                Variant::from(percent),
+                Variant::from(GString::from(get_progress_line(event))),
+            ],
+        );
+    }
+
     pub fn emit_initialize_action_button(action_button: Gd<ActionButton>) {
         let mut singleton = Self::get_singleton();
         singleton.emit_signal(
@@ -295,6 +370,7 @@ impl DecktricksDispatcher {
         is_available: bool,
         is_ongoing: bool,
         is_completed: bool,
+        unavailable_reason: String,
     ) {
         let mut singleton = Self::get_singleton();
         singleton.emit_signal(
@@ -306,6 +382,7 @@ impl DecktricksDispatcher {
                 Variant::from(is_available),
                 Variant::from(is_ongoing),
                 Variant::from(is_completed),
+                Variant::from(GString::from(unavailable_reason)),
             ],
         );
     }
@@ -0,0 +1,150 @@
+use crate::prelude::*;
+use crate::providers::registry::get_provider_registry;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct BatchAction {
+    pub action_id: SpecificActionID,
+    pub ids: Vec<TrickID>,
+    pub batch: BatchArgs,
+}
+
+impl BatchAction {
+    // Returns one result per trick, in the order they were given, followed by a summary.
+    pub(crate) fn do_with(
+        self,
+        executor: &Executor,
+        current_log_level: LogType,
+    ) -> (GeneralExecutionContext, Vec<DeckResult<ActionSuccess>>) {
+        let general_ctx = executor.get_new_general_execution_context(current_log_level);
+
+        if let Some(category) = &self.batch.category
+            && !executor
+                .get_loaded_config()
+                .get_all_categories()
+                .contains(category)
+        {
+            warn!(&general_ctx, "\"{category}\" is not a known category.");
+        }
+
+        let targets = self.get_targets(executor);
+        if targets.is_empty() {
+            return (
+                general_ctx,
+                vec![success!("No tricks matched, so nothing was done.")],
+            );
+        }
+
+        let mut results = self.run_all(executor, &general_ctx, &targets, current_log_level);
+        let summary = get_summary(&self.action_id, &targets, &results);
+        results.push(summary);
+        (general_ctx, results)
+    }
+
+    // Tricks named directly come first, then any picked by selectors (sorted by id)
+    pub(super) fn get_targets(&self, executor: &Executor) -> Vec<TrickID> {
+        let mut targets: Vec<TrickID> = vec![];
+        for id in &self.ids {
+            if !targets.contains(id) {
+                targets.push(id.clone());
+            }
+        }
+
+        if !self.batch.has_selectors() {
+            return targets;
+        }
+
+        let mut selected: Vec<TrickID> = executor
+            .get_all_providers()
+            .into_iter()
+            .filter(|provider| {
+                let trick = provider.get_trick();
+                let in_category = self
+                    .batch
+                    .category
+                    .as_ref()
+                    .is_none_or(|category| trick.categories.contains(category));
+                let installed_if_needed = !self.batch.all_installed || provider.is_installed();
+                in_category && installed_if_needed && provider.can_id(&self.action_id)
+            })
+            .map(|provider| provider.get_trick().id.clone())
+            .filter(|id| !targets.contains(id))
+            .collect();
+        selected.sort();
+
+        targets.extend(selected);
+        targets
+    }
+
+    pub(crate) fn run_all(
+        &self,
+        executor: &Executor,
+        ctx: &GeneralExecutionContext,
+        targets: &[TrickID],
+        current_log_level: LogType,
+    ) -> Vec<DeckResult<ActionSuccess>> {
+        let run_one = |(index, id): (usize, &TrickID)| {
+            let action = self.action_id.as_action(id.clone());
+            let (_ctx, res) = action.do_with(executor, current_log_level);
+            (index, res)
+        };
+
+        let (parallel, serial): (Vec<_>, Vec<_>) = targets
+            .iter()
+            .enumerate()
+            .partition(|(_, id)| allows_parallel_actions(executor, id));
+
+        let mut results: Vec<(usize, DeckResult<ActionSuccess>)> =
+            match rayon::ThreadPoolBuilder::new()
+                .num_threads(self.batch.jobs.max(1))
+                .build()
+            {
+                Ok(pool) => pool.install(|| parallel.into_par_iter().map(run_one).collect()),
+                Err(err) => {
+                    warn!(
+                        ctx,
+                        "Could not start threads for batch actions, running one at a time: {err}"
+                    );
+                    parallel.into_iter().map(run_one).collect()
+                }
+            };
+        results.extend(serial.into_iter().map(run_one));
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, res)| res).collect()
+    }
+}
+
+fn allows_parallel_actions(executor: &Executor, trick_id: &TrickID) -> bool {
+    // Unknown tricks fail straight away, so it doesn't matter where they run
+    executor
+        .get_loaded_config()
+        .get_trick(trick_id)
+        .ok()
+        .and_then(|trick| {
+            get_provider_registry()
+                .get(trick.provider_config.type_tag())
+                .ok()
+        })
+        .is_none_or(|registration| registration.allows_parallel_actions())
+}
+
+fn get_summary(
+    action_id: &SpecificActionID,
+    targets: &[TrickID],
+    results: &[DeckResult<ActionSuccess>],
+) -> DeckResult<ActionSuccess> {
+    let mut lines = vec![format!("{:<24} {}", "TRICK", "RESULT")];
+    for (trick_id, res) in targets.iter().zip(results) {
+        let outcome = if res.is_ok() { "ok" } else { "failed" };
+        lines.push(format!("{trick_id:<24} {outcome}"));
+    }
+
+    let failures = results.iter().filter(|res| res.is_err()).count();
+    lines.push(String::new());
+    lines.push(format!(
+        "{action_id}: {} succeeded, {failures} failed.",
+        results.len() - failures
+    ));
+    success!(lines.join("\n"))
+}
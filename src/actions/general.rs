@@ -1,10 +1,13 @@
-use crate::add_to_steam::debug_steam_shortcuts;
+use crate::add_to_steam::{
+    ManagedSteamShortcut, debug_steam_shortcuts, get_managed_steam_shortcuts,
+};
 use crate::gui::GuiType;
+use crate::history::{HistoryEntry, HistoryFilter, record_in_history, show_history};
+use crate::network::{DEFAULT_SSH_PORT, NetworkInfo};
 use crate::prelude::*;
-use crate::providers::decky_installer::DeckyInstallerGeneralProvider;
-use crate::providers::flatpak::FlatpakGeneralProvider;
-use crate::steam::SteamSubcommand;
-use rayon::prelude::*;
+use crate::profile::{ProfileSubcommand, apply_profile, export_profile, get_profile_plan};
+use crate::steam::{SteamShortcutsSubcommand, SteamSubcommand};
+use crate::utils::get_current_user;
 use serde::Serialize;
 
 #[derive(Debug, Clone)]
@@ -18,14 +21,30 @@ pub enum GeneralAction {
     Actions {
         id: Option<String>,
         json: bool,
+        explain: bool,
+    },
+    UpdateAll {
+        check: bool,
     },
-    UpdateAll,
     GetConfig,
     Version {
         verbose: bool,
     },
     Steam {
-        _steam_subcommand: SteamSubcommand,
+        steam_subcommand: SteamSubcommand,
+    },
+    Network {
+        port: Option<u16>,
+        qr: bool,
+        json: bool,
+    },
+    Profile {
+        profile_subcommand: ProfileSubcommand,
+    },
+    History {
+        filter: HistoryFilter,
+        limit: usize,
+        json: bool,
     },
 
     // Internal use:
@@ -47,11 +66,30 @@ impl GeneralAction {
         // None of the general commands have any need for overwriting the log level
         current_log_level: LogType,
     ) -> (GeneralExecutionContext, Vec<DeckResult<ActionSuccess>>) {
-        let (loader, full_ctx, _runner) = executor.get_pieces();
-
         let general_ctx = executor.get_new_general_execution_context(current_log_level);
+        let history_name = self.get_history_name();
+        let started = std::time::Instant::now();
+
+        let _running = executor.get_running_actions().track(&general_ctx);
+        let results = self.get_results(executor, &general_ctx, current_log_level);
 
-        let results = match self {
+        if let Some(history_name) = history_name {
+            let entry = HistoryEntry::new(None, history_name, started, &results);
+            record_in_history(&general_ctx, &entry);
+        }
+
+        (general_ctx, results)
+    }
+
+    fn get_results(
+        self,
+        executor: &Executor,
+        general_ctx: &GeneralExecutionContext,
+        current_log_level: LogType,
+    ) -> Vec<DeckResult<ActionSuccess>> {
+        let (loader, full_ctx, _runner) = executor.get_pieces();
+
+        match self {
             Self::List { installed } => {
                 let tricks = loader.get_all_tricks();
 
@@ -83,36 +121,16 @@ impl GeneralAction {
                 let tricks_newline_delineated = tricks_names.join("\n");
                 vec![success!(tricks_newline_delineated)]
             }
-            Self::UpdateAll => {
-                // TODO: in a typesafe way, iterate over all known providers, run their global
-                // update logic, and if they don't have global update logic, run per-trick updates
-                // for every known trick?
-                //
-                // decktricks update <trick-id>
-                // decktricks update_all [provtype]
-                // decktricks update -> decktricks update_all
-
-                let general_providers: Vec<Box<dyn GeneralProvider>> = vec![
-                    Box::new(FlatpakGeneralProvider::new(general_ctx.clone())),
-                    Box::new(DeckyInstallerGeneralProvider),
-                ];
-                let mut results: Vec<DeckResult<ActionSuccess>> = general_providers
-                    .par_iter()
-                    .map(|p| p.update_all())
-                    .collect();
-
-                let any_failures = results.iter().any(Result::is_err);
-
-                // TODO: print successes even if there are failures
-                if !any_failures {
-                    results.push(success!("All updates completed successfully!"));
-                }
-
-                results
-            }
-            Self::Actions { id, json } => {
-                vec![get_all_action_state(executor, id.as_ref(), json, current_log_level)]
+            Self::UpdateAll { check } => {
+                update_all(executor, general_ctx, check, current_log_level)
             }
+            Self::Actions { id, json, explain } => vec![get_all_action_state(
+                executor,
+                id.as_ref(),
+                json,
+                explain,
+                current_log_level,
+            )],
             Self::Gui { gui } => vec![gui.launch(executor)],
             Self::GetConfig => {
                 // TODO: if using live configs, use here
@@ -129,10 +147,28 @@ impl GeneralAction {
                 };
                 vec![success!(msg)]
             }
-            Self::Steam { _steam_subcommand } => {
+            Self::Steam { steam_subcommand } => match steam_subcommand {
+                SteamSubcommand::Shortcuts {
+                    shortcuts_subcommand: SteamShortcutsSubcommand::List { json },
+                } => vec![list_steam_shortcuts(executor, json, current_log_level)],
                 // TODO
-                vec![]
-            }
+                _ => vec![],
+            },
+
+            Self::Network { port, qr, json } => vec![get_network_info(port, qr, json)],
+            Self::Profile { profile_subcommand } => match profile_subcommand {
+                ProfileSubcommand::Export { output } => {
+                    vec![export_profile(general_ctx, executor, output.as_deref())]
+                }
+                ProfileSubcommand::Apply { file, prune } => {
+                    apply_profile(general_ctx, executor, &file, prune, current_log_level)
+                }
+            },
+            Self::History {
+                filter,
+                limit,
+                json,
+            } => vec![show_history(&filter, limit, json)],
 
             Self::GetActionDisplayNameMapping => {
                 let display_mapping = SpecificActionID::get_display_name_mapping();
@@ -145,16 +181,71 @@ impl GeneralAction {
             }
             Self::RunSystemCommand { command, args } => {
                 vec![internal_test_run_system_command(
-                    &general_ctx,
+                    general_ctx,
                     command,
                     args,
                 )]
             }
             Self::DebugSteamShortcuts { filename } => vec![debug_steam_shortcuts(filename)],
-            Self::AddDecktricksToSteam => vec![add_to_steam(&AddToSteamTarget::Decktricks)],
-        };
+            Self::AddDecktricksToSteam => vec![add_to_steam(general_ctx, &AddToSteamTarget::Decktricks)],
+        }
+    }
 
-        (general_ctx, results)
+    // The specific actions this will take on tricks, for warnings to be collected from
+    pub(crate) fn get_specific_targets(
+        &self,
+        executor: &Executor,
+    ) -> Vec<(TrickID, SpecificActionID)> {
+        match self {
+            Self::UpdateAll { check: false } => get_update_targets(executor)
+                .into_iter()
+                .map(|trick_id| (trick_id, SpecificActionID::Update))
+                .collect(),
+            // Profiles which can't be loaded fail on their own once the action is run
+            Self::Profile {
+                profile_subcommand: ProfileSubcommand::Apply { file, prune },
+            } => get_profile_plan(executor, file, *prune)
+                .map(|plan| plan.get_targets())
+                .unwrap_or_default(),
+            Self::UpdateAll { check: true }
+            | Self::AddDecktricksToSteam
+            | Self::Gui { .. }
+            | Self::List { .. }
+            | Self::Actions { .. }
+            | Self::GetConfig
+            | Self::Version { .. }
+            | Self::Steam { .. }
+            | Self::Network { .. }
+            | Self::Profile { .. }
+            | Self::History { .. }
+            | Self::GetActionDisplayNameMapping
+            | Self::RunSystemCommand { .. }
+            | Self::DebugSteamShortcuts { .. } => vec![],
+        }
+    }
+
+    // Only actions which change something are worth recording
+    fn get_history_name(&self) -> Option<&'static str> {
+        match self {
+            // Checking doesn't change anything
+            Self::UpdateAll { check } => (!check).then_some("update-all"),
+            Self::AddDecktricksToSteam => Some("add-decktricks-to-steam"),
+            Self::Profile {
+                profile_subcommand: ProfileSubcommand::Apply { .. },
+            } => Some("profile-apply"),
+            Self::Gui { .. }
+            | Self::List { .. }
+            | Self::Actions { .. }
+            | Self::GetConfig
+            | Self::Version { .. }
+            | Self::Steam { .. }
+            | Self::Network { .. }
+            | Self::Profile { .. }
+            | Self::History { .. }
+            | Self::GetActionDisplayNameMapping
+            | Self::RunSystemCommand { .. }
+            | Self::DebugSteamShortcuts { .. } => None,
+        }
     }
 }
 
@@ -167,15 +258,32 @@ struct SpecificActionState {
     is_running: bool,
     is_added_to_steam: bool,
     available_actions: Vec<String>,
+    // Only filled in with `--explain`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    all_actions: Option<Vec<ActionAvailability>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ActionAvailability {
+    action: String,
+    is_available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<UnavailableReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
 }
 
-fn get_action_state_for_all_tricks(executor: &Executor, current_log_level: LogType) -> Vec<(TrickID, SpecificActionState)> {
+fn get_action_state_for_all_tricks(
+    executor: &Executor,
+    explain: bool,
+    current_log_level: LogType,
+) -> Vec<(TrickID, SpecificActionState)> {
     let (loader, _full_ctx, _runner) = executor.get_pieces();
     let tricks = loader.get_all_tricks();
 
     let mut name_to_action_state = vec![];
     for (id, trick) in tricks {
-        let action_state = get_action_state_for_trick(executor, trick, current_log_level);
+        let action_state = get_action_state_for_trick(executor, trick, explain, current_log_level);
 
         name_to_action_state.push((id.clone(), action_state));
     }
@@ -190,6 +298,7 @@ fn get_action_state_for_all_tricks(executor: &Executor, current_log_level: LogTy
 fn get_action_state_for_trick(
     executor: &Executor,
     trick: &Trick,
+    explain: bool,
     current_log_level: LogType,
 ) -> SpecificActionState {
     let full_ctx = executor.get_current_system_context();
@@ -212,12 +321,30 @@ fn get_action_state_for_trick(
         .iter()
         .map(ToString::to_string)
         .collect();
+
+    let all_actions = explain.then(|| {
+        provider
+            .get_all_actions()
+            .iter()
+            .map(|action_id| {
+                let reason = provider.get_availability(action_id).err();
+                ActionAvailability {
+                    action: action_id.to_string(),
+                    is_available: reason.is_none(),
+                    message: reason.as_ref().map(ToString::to_string),
+                    reason,
+                }
+            })
+            .collect()
+    });
+
     SpecificActionState {
         trick_id: trick.id.clone(),
         is_installing,
         is_running,
         is_added_to_steam,
         available_actions,
+        all_actions,
     }
 }
 
@@ -225,12 +352,13 @@ fn get_all_action_state(
     executor: &Executor,
     maybe_id: Option<&TrickID>,
     json: bool,
+    explain: bool,
     current_log_level: LogType,
 ) -> DeckResult<ActionSuccess> {
     let (loader, _full_ctx, _runner) = executor.get_pieces();
     if let Some(id) = maybe_id {
         let trick = loader.get_trick(id.as_ref())?;
-        let action_state = get_action_state_for_trick(executor, trick, current_log_level);
+        let action_state = get_action_state_for_trick(executor, trick, explain, current_log_level);
 
         // TODO: unit test this:
         if json {
@@ -239,7 +367,7 @@ fn get_all_action_state(
             success!(serde_json::to_string_pretty(&action_state).map_err(KnownError::from)?)
         }
     } else {
-        let results = get_action_state_for_all_tricks(executor, current_log_level);
+        let results = get_action_state_for_all_tricks(executor, explain, current_log_level);
 
         // TODO: unit test this:
         let results_map: std::collections::BTreeMap<_, _> = results.into_iter().collect();
@@ -254,6 +382,85 @@ fn get_all_action_state(
     }
 }
 
+#[derive(Debug, Serialize)]
+struct SteamShortcutStatus {
+    #[serde(flatten)]
+    shortcut: ManagedSteamShortcut,
+    // None for Decktricks itself, and for tricks no longer in the config
+    is_installed: Option<bool>,
+}
+
+fn list_steam_shortcuts(
+    executor: &Executor,
+    json: bool,
+    current_log_level: LogType,
+) -> DeckResult<ActionSuccess> {
+    let (loader, full_ctx, _runner) = executor.get_pieces();
+    let statuses: Vec<SteamShortcutStatus> = get_managed_steam_shortcuts()?
+        .into_iter()
+        .map(|shortcut| {
+            let is_installed = shortcut
+                .trick_id
+                .as_ref()
+                .and_then(|id| loader.get_trick(id).ok())
+                .map(|trick| {
+                    let ctx = executor.get_new_specific_execution_context(
+                        current_log_level,
+                        trick.clone(),
+                        SpecificAction::as_info(&trick.id),
+                        false,
+                        true,
+                    );
+                    DynTrickProvider::new(&ctx, full_ctx).is_installed()
+                });
+            SteamShortcutStatus {
+                shortcut,
+                is_installed,
+            }
+        })
+        .collect();
+
+    if json {
+        return success!(serde_json::to_string(&statuses).map_err(KnownError::from)?);
+    }
+
+    if statuses.is_empty() {
+        return success!("No decktricks shortcuts found in Steam.");
+    }
+
+    let mut lines = vec![format!(
+        "{:<12} {:<24} {:<12} {:<20} {}",
+        "USER", "TRICK", "APP ID", "FULL APP ID", "INSTALLED"
+    )];
+    for status in &statuses {
+        let shortcut = &status.shortcut;
+        let installed = match (&shortcut.trick_id, status.is_installed) {
+            (None, _) => "-",
+            (Some(_), None) => "unknown trick",
+            (Some(_), Some(true)) => "yes",
+            (Some(_), Some(false)) => "no",
+        };
+        lines.push(format!(
+            "{:<12} {:<24} {:<12} {:<20} {installed}",
+            shortcut.user,
+            shortcut.trick_id.as_deref().unwrap_or("(decktricks)"),
+            shortcut.app_id,
+            shortcut.full_app_id,
+        ));
+    }
+    success!(lines.join("\n"))
+}
+
+fn get_network_info(port: Option<u16>, qr: bool, json: bool) -> DeckResult<ActionSuccess> {
+    let info = NetworkInfo::gather(&get_current_user(), port.unwrap_or(DEFAULT_SSH_PORT))?;
+    if json {
+        // NOTE: the QR code is not included here, since the GUI can render its own
+        success!(serde_json::to_string(&info).map_err(KnownError::from)?)
+    } else {
+        success!(info.get_display_text(qr))
+    }
+}
+
 fn internal_test_run_system_command(
     ctx: &impl ExecCtx,
     command: String,
@@ -2,37 +2,81 @@ use crate::prelude::*;
 
 // For the master list of available actions, see command.rs
 
+mod batch;
 mod general;
 mod specific;
+mod update_all;
 
+pub use batch::BatchAction;
 pub(crate) use general::*;
 pub use specific::SpecificActionID;
 pub use specific::SpecificAction;
+pub(crate) use specific::add_to_steam_after_install;
+pub(crate) use update_all::{get_update_targets, update_all};
 
 #[derive(Debug, Clone)]
 pub enum TypedAction {
     Specific(SpecificAction),
+    // The same specific action, on several tricks
+    Batch(BatchAction),
     General(GeneralAction),
 }
 
 impl From<&Action> for TypedAction {
     fn from(action: &Action) -> Self {
         match action.clone() {
-            Action::Run { id } => Self::Specific(SpecificAction::Run { id }),
-            Action::Kill { id } => Self::Specific(SpecificAction::Kill { id }),
+            Action::Run { id, extra_args } => Self::Specific(SpecificAction::Run { id, extra_args }),
+            Action::RunNested { id, extra_args } => {
+                Self::Specific(SpecificAction::RunNested { id, extra_args })
+            }
+            Action::Kill { ids, batch } => Self::for_ids(SpecificActionID::Kill, ids, batch),
             Action::Info { id } => Self::Specific(SpecificAction::Info { id }),
-            Action::Install { id } => Self::Specific(SpecificAction::Install { id }),
-            Action::AddToSteam { id } => Self::Specific(SpecificAction::AddToSteam { id }),
-            Action::Uninstall { id } => Self::Specific(SpecificAction::Uninstall { id }),
-            Action::Update { id: Some(id) } => Self::Specific(SpecificAction::Update { id }),
+            Action::Logs { id } => Self::Specific(SpecificAction::Logs { id }),
+            Action::Install { ids, batch } => {
+                Self::for_ids(SpecificActionID::Install, ids, batch)
+            }
+            Action::AddToSteam { id, extra_args } => {
+                Self::Specific(SpecificAction::AddToSteam { id, extra_args })
+            }
+            Action::RemoveFromSteam { id } => {
+                Self::Specific(SpecificAction::RemoveFromSteam { id })
+            }
+            Action::Uninstall { ids, batch } => {
+                Self::for_ids(SpecificActionID::Uninstall, ids, batch)
+            }
+            Action::Update { ids, batch, check } if ids.is_empty() && !batch.has_selectors() => {
+                Self::General(GeneralAction::UpdateAll { check })
+            }
+            Action::Update { ids, batch, .. } => {
+                Self::for_ids(SpecificActionID::Update, ids, batch)
+            }
+            Action::Reinstall { id, delete_user_data } => {
+                Self::Specific(SpecificAction::Reinstall { id, delete_user_data })
+            }
 
-            Action::Update { id: None } => Self::General(GeneralAction::UpdateAll {}),
             Action::List { installed } => Self::General(GeneralAction::List { installed }),
-            Action::Actions { id, json } => Self::General(GeneralAction::Actions { id, json }),
+            Action::Actions { id, json, explain } => {
+                Self::General(GeneralAction::Actions { id, json, explain })
+            }
             Action::Gui { gui } => Self::General(GeneralAction::Gui { gui }),
             Action::GetConfig => Self::General(GeneralAction::GetConfig),
             Action::Version { verbose } => Self::General(GeneralAction::Version { verbose }),
-            Action::Steam { _steam_subcommand } => Self::General(GeneralAction::Steam { _steam_subcommand }),
+            Action::Steam { steam_subcommand } => Self::General(GeneralAction::Steam { steam_subcommand }),
+            Action::Profile { profile_subcommand } => {
+                Self::General(GeneralAction::Profile { profile_subcommand })
+            }
+            Action::History {
+                filter,
+                limit,
+                json,
+            } => Self::General(GeneralAction::History {
+                filter,
+                limit,
+                json,
+            }),
+            Action::Network { port, qr, json } => {
+                Self::General(GeneralAction::Network { port, qr, json })
+            }
 
             // Internal use:
             Action::GetActionDisplayNameMapping => {
@@ -50,6 +94,53 @@ impl From<&Action> for TypedAction {
 }
 
 impl TypedAction {
+    // A single trick with no selectors is just a plain specific action
+    fn for_ids(action_id: SpecificActionID, mut ids: Vec<TrickID>, batch: BatchArgs) -> Self {
+        if ids.len() == 1 && !batch.has_selectors() {
+            Self::Specific(action_id.as_action(ids.remove(0)))
+        } else {
+            Self::Batch(BatchAction {
+                action_id,
+                ids,
+                batch,
+            })
+        }
+    }
+
+    // Warnings from the config for anything this is about to do, which should be confirmed first
+    #[must_use]
+    pub fn get_warnings(&self, executor: &Executor) -> Vec<PendingWarning> {
+        let targets: Vec<(TrickID, SpecificActionID)> = match self {
+            Self::Specific(action) => vec![(action.id().into(), SpecificActionID::from(action))],
+            Self::Batch(batch_action) => batch_action
+                .get_targets(executor)
+                .into_iter()
+                .map(|trick_id| (trick_id, batch_action.action_id.clone()))
+                .collect(),
+            Self::General(general_action) => general_action.get_specific_targets(executor),
+        };
+
+        let config = executor.get_loaded_config();
+        let mut pending = vec![];
+        for (trick_id, action_id) in targets {
+            // Unknown tricks fail on their own once the action is run
+            let Ok(trick) = config.get_trick(&trick_id) else {
+                continue;
+            };
+            pending.extend(
+                trick
+                    .get_warnings(&action_id)
+                    .into_iter()
+                    .map(|warning| PendingWarning {
+                        trick_id: trick_id.clone(),
+                        action_id: action_id.clone(),
+                        warning: warning.clone(),
+                    }),
+            );
+        }
+        pending
+    }
+
     pub fn do_with(
         self,
         executor: &Executor,
@@ -65,10 +156,27 @@ impl TypedAction {
                 // NOTE: we always want SpecificActions to have a single result
                 (ctx.map(|c| c.as_ctx()), vec![res])
             }
+            Self::Batch(batch_action) => {
+                let (ctx, results) = batch_action.do_with(executor, current_log_level);
+                (Some(ctx.as_ctx()), results)
+            }
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct PendingWarning {
+    pub trick_id: TrickID,
+    pub action_id: SpecificActionID,
+    pub warning: ActionWarning,
+}
+
+impl std::fmt::Display for PendingWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{} {}] {}", self.action_id, self.trick_id, self.warning)
+    }
+}
+
 #[derive(Debug)]
 pub struct ActionSuccess {
     message: Option<String>,
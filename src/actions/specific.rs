@@ -1,16 +1,24 @@
+use crate::history::{HistoryEntry, record_in_history};
+use crate::nested_desktop::NestedSession;
 use crate::{enum_with_all_variants, prelude::*};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
 
 #[derive(Debug, Clone)]
 pub enum SpecificAction {
-    Run { id: String },
+    // Extra args are appended to the trick's own, e.g. a ROM path
+    Run { id: String, extra_args: Vec<String> },
+    RunNested { id: String, extra_args: Vec<String> },
     Install { id: String },
     Kill { id: String },
     Uninstall { id: String },
-    AddToSteam { id: String },
+    // Extra args are baked into the shortcut's launch options
+    AddToSteam { id: String, extra_args: Vec<String> },
+    RemoveFromSteam { id: String },
     Update { id: String },
+    Reinstall { id: String, delete_user_data: bool },
+    Logs { id: String },
     // NOTE: Info does not actually require a provider or anything else,
     // it just reads from the config.
     Info { id: String },
@@ -21,7 +29,7 @@ pub enum SpecificAction {
 //
 // This proc macro gives us access to SpecificActionID.all_variants()
 enum_with_all_variants!(
-    #[derive(Debug, Clone, Serialize, Eq, PartialEq, Hash)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
     #[serde(rename_all = "kebab-case")]
     pub enum SpecificActionID {
         // XXX IMPORTANT! XXX
@@ -30,10 +38,14 @@ enum_with_all_variants!(
         // XXX IMPORTANT! XXX
         Install,
         Run,
+        RunNested,
         AddToSteam,
+        RemoveFromSteam,
         Update,
+        Reinstall,
         Uninstall,
         Kill,
+        Logs,
 
         // Info should always be last:
         Info,
@@ -59,6 +71,7 @@ impl SpecificActionID {
                     "Run"
                 }
             }
+            Self::RunNested => "Run In Desktop",
             Self::AddToSteam => {
                 if is_completed {
                     "Added"
@@ -66,6 +79,7 @@ impl SpecificActionID {
                     "Add To Steam"
                 }
             }
+            Self::RemoveFromSteam => "Remove From Steam",
             Self::Install => {
                 if is_ongoing {
                     "Installing"
@@ -87,7 +101,15 @@ impl SpecificActionID {
                     "Update"
                 }
             }
+            Self::Reinstall => {
+                if is_ongoing {
+                    "Reinstalling"
+                } else {
+                    "Reinstall"
+                }
+            }
             Self::Kill => "Kill",
+            Self::Logs => "Logs",
             Self::Info => "Info",
         }
     }
@@ -107,13 +129,30 @@ impl SpecificActionID {
     pub fn as_action(&self, trick_id: String) -> SpecificAction {
         let id = trick_id;
         match self {
-            Self::Run => SpecificAction::Run { id },
+            Self::Run => SpecificAction::Run {
+                id,
+                extra_args: vec![],
+            },
+            Self::RunNested => SpecificAction::RunNested {
+                id,
+                extra_args: vec![],
+            },
             Self::Install => SpecificAction::Install { id },
             Self::Kill => SpecificAction::Kill { id },
             Self::Uninstall => SpecificAction::Uninstall { id },
-            Self::AddToSteam => SpecificAction::AddToSteam { id },
+            Self::AddToSteam => SpecificAction::AddToSteam {
+                id,
+                extra_args: vec![],
+            },
+            Self::RemoveFromSteam => SpecificAction::RemoveFromSteam { id },
             Self::Info => SpecificAction::Info { id },
             Self::Update => SpecificAction::Update { id },
+            // Reinstalling from the GUI is meant to fix a broken install, not to lose settings
+            Self::Reinstall => SpecificAction::Reinstall {
+                id,
+                delete_user_data: false,
+            },
+            Self::Logs => SpecificAction::Logs { id },
         }
     }
 }
@@ -143,12 +182,16 @@ impl From<&SpecificAction> for SpecificActionID {
     fn from(action: &SpecificAction) -> Self {
         match action {
             SpecificAction::Run { .. } => Self::Run,
+            SpecificAction::RunNested { .. } => Self::RunNested,
             SpecificAction::Install { .. } => Self::Install,
             SpecificAction::Kill { .. } => Self::Kill,
             SpecificAction::Uninstall { .. } => Self::Uninstall,
             SpecificAction::AddToSteam { .. } => Self::AddToSteam,
+            SpecificAction::RemoveFromSteam { .. } => Self::RemoveFromSteam,
             SpecificAction::Info { .. } => Self::Info,
             SpecificAction::Update { .. } => Self::Update,
+            SpecificAction::Reinstall { .. } => Self::Reinstall,
+            SpecificAction::Logs { .. } => Self::Logs,
         }
     }
 }
@@ -156,12 +199,16 @@ impl From<&SpecificAction> for SpecificActionID {
 impl SpecificAction {
     pub(crate) fn id(&self) -> &str {
         match self {
-            Self::Run { id }
+            Self::Run { id, .. }
+            | Self::RunNested { id, .. }
             | Self::Kill { id }
             | Self::Info { id }
             | Self::Install { id }
-            | Self::AddToSteam { id }
+            | Self::AddToSteam { id, .. }
+            | Self::RemoveFromSteam { id }
             | Self::Update { id }
+            | Self::Logs { id }
+            | Self::Reinstall { id, .. }
             | Self::Uninstall { id } => id,
         }
     }
@@ -175,28 +222,48 @@ impl SpecificAction {
         executor: &Executor,
         current_log_level: LogType,
     ) -> (Option<SpecificExecutionContext>, DeckResult<ActionSuccess>) {
-        let config = executor.get_loaded_config();
-        let trick_id = self.id();
+        let (config, full_ctx, _runner) = executor.get_pieces();
+        let trick_id = self.id().to_string();
         let maybe_trick = config.get_trick(trick_id.as_ref());
+        let action_id = SpecificActionID::from(&self);
+        let started = std::time::Instant::now();
 
-        match maybe_trick {
+        let (maybe_ctx, res) = match maybe_trick {
             Ok(trick) => {
                 let ctx = executor.get_new_specific_execution_context(
                     current_log_level,
                     trick.clone(),
                     self.clone(),
-                    // In the context of actually taking an action, we don't care if we're installing
-                    // or added to Steam, since at the moment these are purely for cosmetic purposes
+                    // In the context of actually taking an action, we don't care if we're installing,
+                    // since at the moment that is purely for cosmetic purposes. Whether we're added
+                    // to Steam decides whether we can be removed from it.
                     // TODO: code smell
                     false,
-                    false,
+                    full_ctx.is_added_to_steam(&trick.id),
                 );
 
+                let _running = executor.get_running_actions().track(&ctx);
                 let res = self.do_with_inner(&ctx, executor, trick);
                 (Some(ctx), res)
             }
             Err(err) => (None, Err(err)),
+        };
+
+        // Info and logs only look at a trick, so they aren't worth recording. Anything asked of a
+        // trick which doesn't exist is, as a failure.
+        if !matches!(action_id, SpecificActionID::Info | SpecificActionID::Logs) {
+            let entry =
+                HistoryEntry::new(Some(&trick_id), &action_id.to_string(), started, [&res]);
+            match &maybe_ctx {
+                Some(ctx) => record_in_history(ctx, &entry),
+                None => record_in_history(
+                    &executor.get_new_general_execution_context(current_log_level),
+                    &entry,
+                ),
+            }
         }
+
+        (maybe_ctx, res)
     }
 
     pub fn do_with_inner(
@@ -216,49 +283,69 @@ impl SpecificAction {
                     dbg!(provider.is_addable_to_steam_once_installed());
                     dbg!(provider.is_added_to_steam());
 
-                    if res.is_ok()
-                        && ctx.settings.add_to_steam_on_install_where_applicable
-                        // Since our system context is cached, is_addable_to_steam will
-                        // return false here for anything which relies on is_installed:
-                        && provider.is_addable_to_steam_once_installed()
-                        && !provider.is_added_to_steam()
-                    {
-                        if let Err(err) = provider.add_to_steam() {
-                            let trick_id = &trick.id;
-                            warn!(
-                                ctx,
-                                "{trick_id} successfully installed, but failed to add to steam: {err:?}"
-                            );
-                        }
+                    if res.is_ok() {
+                        add_to_steam_after_install(ctx, &provider);
                     }
                     res
                 }
                 Self::Run { .. } => provider.run(),
+                Self::RunNested { .. } => {
+                    let session = NestedSession::find_or_start(ctx)?;
+                    let nested_ctx = ctx.with_extra_env(session.get_env_vars());
+                    DynTrickProvider::new(&nested_ctx, full_ctx).run()
+                }
                 Self::Uninstall { .. } => provider.uninstall(),
                 Self::AddToSteam { .. } => provider.add_to_steam(),
+                Self::RemoveFromSteam { .. } => provider.remove_from_steam(),
                 Self::Kill { .. } => provider.kill(),
                 Self::Update { .. } => provider.update(),
+                Self::Reinstall { delete_user_data, .. } => provider.reinstall(delete_user_data),
+                Self::Logs { .. } => provider.logs(),
 
                 Self::Info { .. } => {
-                    success!(
-                        "{}",
-                        serde_json::to_string_pretty(trick).map_err(KnownError::from)?
-                    )
+                    let config = serde_json::to_string_pretty(trick).map_err(KnownError::from)?;
+                    match provider.get_extra_info() {
+                        Some(extra_info) => success!(format!("{config}\n\n{extra_info}")),
+                        None => success!(config),
+                    }
                 }
             }
         } else {
-            // TODO: Make these more specific, maybe the checks can return a reason after all
-            //       on the other hand, this is only for the CLI which doesn't really matter.
-            //       Actions within the GUI will only show up if they pass this function.
+            let action_id = SpecificActionID::from(&self);
+            let reason = provider
+                .get_availability(&action_id)
+                .err()
+                .map_or_else(|| "Is it installed/running?".into(), |reason| reason.to_string());
             Err(KnownError::ActionGated(format!(
-                "Action '{}' is not possible on trick '{}' right now. Is it installed/running? HINT: (Try 'actions')",
-                String::try_from(&SpecificActionID::from(&self))?,
+                "Action '{}' is not possible on trick '{}' right now. {reason} HINT: (Try 'actions')",
+                String::try_from(&action_id)?,
                 trick.id
             )))
         }
     }
 }
 
+// Used for tricks installed directly, and for the members of bundles
+pub(crate) fn add_to_steam_after_install(
+    ctx: &SpecificExecutionContext,
+    provider: &DynTrickProvider,
+) {
+    if ctx.settings.add_to_steam_on_install_where_applicable
+        // Since our system context is cached, is_addable_to_steam will
+        // return false here for anything which relies on is_installed:
+        && provider.is_addable_to_steam_once_installed()
+        && !provider.is_added_to_steam()
+    {
+        if let Err(err) = provider.add_to_steam() {
+            let trick_id = &provider.get_trick().id;
+            warn!(
+                ctx,
+                "{trick_id} successfully installed, but failed to add to steam: {err:?}"
+            );
+        }
+    }
+}
+
 #[test]
 fn test_specific_id_display_map() {
     let m = SpecificActionID::get_display_name_mapping();
@@ -0,0 +1,202 @@
+use crate::actions::BatchAction;
+use crate::locks::{LockScope, acquire_lock};
+use crate::prelude::*;
+use crate::providers::registry::{UpdateAllStrategy, get_provider_registry};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+// `update` with no trick ids. Providers which can update everything at once (flatpak) do so,
+// and every other installed trick which can be updated is updated by itself.
+
+struct GlobalUpdate {
+    type_tag: String,
+    updater: Box<dyn GeneralProvider>,
+    tricks: Vec<Trick>,
+}
+
+#[derive(Default)]
+struct UpdatePlan {
+    global: Vec<GlobalUpdate>,
+    per_trick: Vec<TrickID>,
+}
+
+// One line of the table shown at the end
+struct UpdateRow {
+    trick_id: TrickID,
+    step: String,
+    status: &'static str,
+}
+
+pub(crate) fn update_all(
+    executor: &Executor,
+    ctx: &GeneralExecutionContext,
+    check: bool,
+    current_log_level: LogType,
+) -> Vec<DeckResult<ActionSuccess>> {
+    let plan = get_update_plan(executor, ctx);
+    if plan.global.is_empty() && plan.per_trick.is_empty() {
+        return vec![success!("No installed tricks can be updated.")];
+    }
+
+    let (mut results, rows) = if check {
+        check_updates(&plan)
+    } else {
+        let _lock = match acquire_lock(ctx, &LockScope::Global, "updating everything") {
+            Ok(lock) => lock,
+            Err(err) => return vec![Err(err)],
+        };
+        run_updates(executor, ctx, &plan, current_log_level)
+    };
+    results.push(success!(get_table(rows, check)));
+    results
+}
+
+// Every trick which would be updated, by its provider's global update or by itself
+pub(crate) fn get_update_targets(executor: &Executor) -> Vec<TrickID> {
+    let ctx = executor.get_new_general_execution_context(LogType::Warn);
+    let plan = get_update_plan(executor, &ctx);
+    plan.global
+        .iter()
+        .flat_map(|global| global.tricks.iter().map(|trick| trick.id.clone()))
+        .chain(plan.per_trick)
+        .collect()
+}
+
+fn get_update_plan(executor: &Executor, ctx: &GeneralExecutionContext) -> UpdatePlan {
+    let mut installed_by_type: BTreeMap<String, Vec<DynTrickProvider>> = BTreeMap::new();
+    for provider in executor.get_all_providers() {
+        if provider.is_installed() {
+            let type_tag = provider.get_trick().provider_config.type_tag().to_string();
+            installed_by_type
+                .entry(type_tag)
+                .or_default()
+                .push(provider);
+        }
+    }
+
+    let registry = get_provider_registry();
+    let mut plan = UpdatePlan::default();
+    for (type_tag, providers) in installed_by_type {
+        let strategy = registry
+            .get(&type_tag)
+            .map_or(UpdateAllStrategy::PerTrick, |reg| {
+                reg.get_update_all_strategy(ctx)
+            });
+        match strategy {
+            UpdateAllStrategy::Global(updater) => plan.global.push(GlobalUpdate {
+                type_tag,
+                updater,
+                tricks: providers.iter().map(|p| p.get_trick().clone()).collect(),
+            }),
+            UpdateAllStrategy::PerTrick => plan.per_trick.extend(
+                providers
+                    .iter()
+                    .filter(|p| p.can_id(&SpecificActionID::Update))
+                    .map(|p| p.get_trick().id.clone()),
+            ),
+            UpdateAllStrategy::Skip => {}
+        }
+    }
+    plan
+}
+
+fn check_updates(plan: &UpdatePlan) -> (Vec<DeckResult<ActionSuccess>>, Vec<UpdateRow>) {
+    let mut results = vec![];
+    let mut rows = vec![];
+    for global in &plan.global {
+        let tricks: Vec<&Trick> = global.tricks.iter().collect();
+        let with_updates = global.updater.get_tricks_with_updates(&tricks);
+        for trick in &global.tricks {
+            let status = match &with_updates {
+                Ok(Some(ids)) if ids.contains(&trick.id) => "update available",
+                Ok(Some(_)) => "up to date",
+                Ok(None) => "will be updated",
+                Err(_) => "check failed",
+            };
+            rows.push(UpdateRow {
+                trick_id: trick.id.clone(),
+                step: global.type_tag.clone(),
+                status,
+            });
+        }
+        if let Err(err) = with_updates {
+            results.push(Err(err));
+        }
+    }
+
+    // There's no general way to ask a trick whether it has an update
+    rows.extend(plan.per_trick.iter().map(|trick_id| UpdateRow {
+        trick_id: trick_id.clone(),
+        step: SpecificActionID::Update.to_string(),
+        status: "will be updated",
+    }));
+    (results, rows)
+}
+
+fn run_updates(
+    executor: &Executor,
+    ctx: &GeneralExecutionContext,
+    plan: &UpdatePlan,
+    current_log_level: LogType,
+) -> (Vec<DeckResult<ActionSuccess>>, Vec<UpdateRow>) {
+    let mut results: Vec<DeckResult<ActionSuccess>> = plan
+        .global
+        .par_iter()
+        .map(|global| global.updater.update_all())
+        .collect();
+
+    let mut rows = vec![];
+    for (global, res) in plan.global.iter().zip(&results) {
+        rows.extend(global.tricks.iter().map(|trick| UpdateRow {
+            trick_id: trick.id.clone(),
+            step: global.type_tag.clone(),
+            status: if res.is_ok() { "ok" } else { "failed" },
+        }));
+    }
+
+    if !plan.per_trick.is_empty() {
+        let batch = BatchAction {
+            action_id: SpecificActionID::Update,
+            ids: vec![],
+            batch: BatchArgs::default(),
+        };
+        let per_trick_results = batch.run_all(executor, ctx, &plan.per_trick, current_log_level);
+        for (trick_id, res) in plan.per_trick.iter().zip(&per_trick_results) {
+            rows.push(UpdateRow {
+                trick_id: trick_id.clone(),
+                step: SpecificActionID::Update.to_string(),
+                status: if res.is_ok() { "ok" } else { "failed" },
+            });
+        }
+        results.extend(per_trick_results);
+    }
+    (results, rows)
+}
+
+fn get_table(mut rows: Vec<UpdateRow>, check: bool) -> String {
+    rows.sort_by(|a, b| a.trick_id.cmp(&b.trick_id));
+
+    let mut lines = vec![format!("{:<24} {:<12} {}", "TRICK", "STEP", "RESULT")];
+    for row in &rows {
+        lines.push(format!(
+            "{:<24} {:<12} {}",
+            row.trick_id, row.step, row.status
+        ));
+    }
+    lines.push(String::new());
+
+    if check {
+        let pending = rows
+            .iter()
+            .filter(|row| matches!(row.status, "update available" | "will be updated"))
+            .count();
+        lines.push(format!("{pending} tricks would be updated."));
+    } else {
+        let failures = rows.iter().filter(|row| row.status == "failed").count();
+        lines.push(format!(
+            "update-all: {} succeeded, {failures} failed.",
+            rows.len() - failures
+        ));
+    }
+    lines.join("\n")
+}
@@ -1,8 +1,11 @@
 use crate::prelude::geforce_now::GEFORCE_LOCAL_EXECUTABLE;
+use crate::locks::{LockGuard, LockScope, STEAM_SHORTCUTS_RESOURCE_LOCK, acquire_lock};
 use crate::prelude::*;
 use crate::providers::emudeck_installer::get_emudeck_binary_path;
+use crate::providers::flatpak::get_flatpak_run_args;
 use crate::utils::{get_homedir, which};
 use std::collections::HashMap;
+use serde::Serialize;
 use std::fmt::Display;
 use std::sync::LazyLock;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -58,6 +61,60 @@ impl SteamShortcut {
         let trick_tag = format!("decktricks-{}", trick_id);
         self.tags.contains(&trick_tag)
     }
+
+    fn get_trick_id(&self) -> Option<TrickID> {
+        self.tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix("decktricks-"))
+            .map(Into::into)
+    }
+}
+
+// A shortcut we created, and where it lives
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagedSteamShortcut {
+    // The Steam user id the shortcut belongs to, or the filename if overridden
+    pub user: String,
+    // None for the shortcut to Decktricks itself
+    pub trick_id: Option<TrickID>,
+    pub app_name: String,
+    pub app_id: u32,
+    pub full_app_id: u64,
+}
+
+/// # Errors
+///
+/// Errors from finding, reading, and parsing shortcuts.vdf files
+pub fn get_managed_steam_shortcuts() -> DeckResult<Vec<ManagedSteamShortcut>> {
+    let mut managed = vec![];
+    for (filename, shortcuts) in get_steam_shortcuts_inner(None, false)? {
+        let user = get_steam_userid_from_shortcuts_path(&filename);
+        for shortcut in shortcuts {
+            let trick_id = shortcut.get_trick_id();
+            if trick_id.is_none() && !shortcut.is_decktricks_shortcut() {
+                continue;
+            }
+            managed.push(ManagedSteamShortcut {
+                user: user.clone(),
+                trick_id,
+                app_name: shortcut.app_name.clone(),
+                app_id: shortcut.app_id,
+                full_app_id: get_full_appid(shortcut.app_id),
+            });
+        }
+    }
+    managed.sort_by(|a, b| (&a.user, &a.trick_id).cmp(&(&b.user, &b.trick_id)));
+    Ok(managed)
+}
+
+// .../userdata/<steam userid>/config/shortcuts.vdf
+fn get_steam_userid_from_shortcuts_path(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .parent()
+        .filter(|config_dir| config_dir.ends_with("config"))
+        .and_then(std::path::Path::parent)
+        .and_then(std::path::Path::file_name)
+        .map_or_else(|| filename.into(), |userid| userid.to_string_lossy().into())
 }
 
 #[derive(Debug, Default, Clone)]
@@ -157,8 +214,7 @@ impl TryFrom<&Trick> for TrickAddToSteamContext {
             ProviderConfig::Flatpak(flatpak) => {
                 let exe = "\"/usr/bin/flatpak\"".into();
 
-                // NOTE: in the future you may need to add --command=blah here, but for most things this will work
-                let launch_options = format!("\"run\" \"--branch=stable\" \"--arch=x86_64\" \"{}\"", flatpak.id);
+                let launch_options = get_flatpak_launch_options(&flatpak);
 
                 // This is what Steam uses, even if it's a silly place to cd to
                 let start_dir = "/usr/bin".into();
@@ -215,10 +271,8 @@ impl TryFrom<&Trick> for TrickAddToSteamContext {
 
                 let start_dir = cmd.execution_dir.unwrap_or_else(|| "/usr/bin".into());
 
-                let launch_options = match cmd.args {
-                    Some(args) => args.join(" "),
-                    None => String::default(),
-                };
+                let launch_options =
+                    get_launch_options_with_env(cmd.env.as_ref(), cmd.args.as_deref());
 
                 TrickAddToSteamContext {
                     trick_id,
@@ -237,10 +291,8 @@ impl TryFrom<&Trick> for TrickAddToSteamContext {
 
                 let start_dir = d.execution_dir.unwrap_or_else(|| "/usr/bin".into());
 
-                let launch_options = match d.args {
-                    Some(args) => args.join(" "),
-                    None => String::default(),
-                };
+                let launch_options =
+                    get_launch_options_with_env(d.env.as_ref(), d.args.as_deref());
 
                 TrickAddToSteamContext {
                     trick_id,
@@ -252,11 +304,86 @@ impl TryFrom<&Trick> for TrickAddToSteamContext {
                     launch_options,
                 }
             },
+            // Bundles, SSH sessions, and downstream providers have nothing to launch from Steam
+            _ => {
+                Err(KnownError::AddToSteamError(format!(
+                    "Adding \"{}\" tricks to Steam is not supported.",
+                    trick.provider_config.type_tag()
+                )))?
+            },
         };
         Ok(ctx)
     }
 }
 
+impl TrickAddToSteamContext {
+    // Args from `decktricks add-to-steam <id> -- <args>` go last, after the trick's own, which is
+    // where `decktricks run <id> -- <args>` puts them too
+    #[must_use]
+    pub fn with_extra_args(mut self, extra_args: &[String]) -> Self {
+        let quoted = extra_args.iter().map(|arg| quote_for_launch_options(arg));
+        self.launch_options = std::iter::once(self.launch_options)
+            .filter(|launch_options| !launch_options.is_empty())
+            .chain(quoted)
+            .collect::<Vec<_>>()
+            .join(" ");
+        self
+    }
+}
+
+// The same args as `decktricks run` uses
+fn get_flatpak_launch_options(flatpak: &Flatpak) -> String {
+    // NOTE: in the future you may need to add --command=blah here, but for most things this will work
+    let env = flatpak.env.clone().unwrap_or_default();
+    let mut launch_args = get_flatpak_run_args(&flatpak.id, &env);
+    // Right after "run"
+    launch_args.splice(
+        1..1,
+        ["--branch=stable".to_string(), "--arch=x86_64".to_string()],
+    );
+    launch_args
+        .iter()
+        .map(|arg| quote_for_launch_options(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Steam runs launch options through a shell, so anything in them which isn't meant to be
+// expanded has to be in double quotes, with the characters still special there escaped
+fn quote_for_launch_options(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Steam runs launch options through a shell, and replaces %command% with the shortcut's exe. This
+// lets launches from Steam see the same environment as launches from decktricks.
+// Configured args always come last, so anything a user appends to the launch options in Steam
+// reaches the app the same way as `decktricks run <id> -- <args>`.
+fn get_launch_options_with_env(env: Option<&EnvVars>, args: Option<&[String]>) -> String {
+    let args = args.map(|a| a.join(" ")).unwrap_or_default();
+    let env_prefix: Vec<String> = env
+        .into_iter()
+        .flatten()
+        .map(|(varname, value)| format!("{varname}={}", quote_for_launch_options(value)))
+        .collect();
+
+    if env_prefix.is_empty() {
+        args
+    } else {
+        format!("{} %command% {args}", env_prefix.join(" "))
+            .trim_end()
+            .to_string()
+    }
+}
+
 #[allow(clippy::cast_possible_truncation)]
 fn get_unix_time() -> u32 {
     SystemTime::now()
@@ -420,7 +547,14 @@ fn get_desired_order_num(shortcuts: &[SteamShortcut]) -> String {
     .to_string()
 }
 
-fn write_shortcuts_to_disk(path: &str, shortcuts: &[SteamShortcut]) -> DeckResult<()> {
+fn write_shortcuts_to_disk(
+    ctx: &impl ExecCtx,
+    path: &str,
+    shortcuts: &[SteamShortcut],
+) -> DeckResult<()> {
+    if skip_for_dry_run(ctx, format!("update the Steam shortcuts in \"{path}\" ({} total)", shortcuts.len())) {
+        return Ok(());
+    }
     let shortcut_bytes_vec = shortcuts_to_bytes(&shortcuts.iter().map(|s| s.borrow()).collect());
     std::fs::write(path, &shortcut_bytes_vec).map_err(|e| {
         KnownError::AddToSteamError(format!("Failed to write shortcuts to disk: {e:#?}"))
@@ -432,16 +566,20 @@ fn write_shortcuts_to_disk(path: &str, shortcuts: &[SteamShortcut]) -> DeckResul
 ///
 /// Errors from finding, reading, and parsing shortcuts.vdf files
 #[cfg(not(test))]
-pub fn add_to_steam(target: &AddToSteamTarget) -> DeckResult<ActionSuccess> {
-    add_to_steam_real(target)
+pub fn add_to_steam(ctx: &impl ExecCtx, target: &AddToSteamTarget) -> DeckResult<ActionSuccess> {
+    add_to_steam_real(ctx, target)
 }
 
 #[cfg(test)]
-pub fn add_to_steam(_target: &AddToSteamTarget) -> DeckResult<ActionSuccess> {
+pub fn add_to_steam(_ctx: &impl ExecCtx, _target: &AddToSteamTarget) -> DeckResult<ActionSuccess> {
     success!("Ran in test...")
 }
 
-pub fn add_to_steam_real(target: &AddToSteamTarget) -> DeckResult<ActionSuccess> {
+pub fn add_to_steam_real(
+    ctx: &impl ExecCtx,
+    target: &AddToSteamTarget,
+) -> DeckResult<ActionSuccess> {
+    let _lock = lock_shortcuts(ctx, &format!("adding \"{target}\" to Steam"))?;
     let mut newest_shortcut = None;
     // In all likelihood, this will only ever run once. But since we don't know the
     // steam userid of the current user, we hedge our bets and add the shortcut to
@@ -464,11 +602,16 @@ pub fn add_to_steam_real(target: &AddToSteamTarget) -> DeckResult<ActionSuccess>
         newest_shortcut = Some(new_shortcut.clone());
 
         shortcuts.push(new_shortcut);
-        write_shortcuts_to_disk(&filename, &shortcuts)?;
+        write_shortcuts_to_disk(ctx, &filename, &shortcuts)?;
     }
 
     if let AddToSteamTarget::Decktricks = target {
         match newest_shortcut {
+            Some(_)
+                if skip_for_dry_run(
+                    ctx,
+                    format!("write the full appid to \"{DECKTRICKS_FULL_APPID_FILENAME}\""),
+                ) => {}
             Some(shortcut) => {
                 std::fs::write(
                     DECKTRICKS_FULL_APPID_FILENAME,
@@ -489,6 +632,53 @@ pub fn add_to_steam_real(target: &AddToSteamTarget) -> DeckResult<ActionSuccess>
     success!("Successfully added \"{}\" to Steam.", target)
 }
 
+/// # Errors
+///
+/// Errors from finding, reading, parsing, and writing shortcuts.vdf files
+#[cfg(not(test))]
+pub fn remove_from_steam(ctx: &impl ExecCtx, trick_id: &TrickID) -> DeckResult<ActionSuccess> {
+    remove_from_steam_real(ctx, trick_id)
+}
+
+#[cfg(test)]
+pub fn remove_from_steam(_ctx: &impl ExecCtx, _trick_id: &TrickID) -> DeckResult<ActionSuccess> {
+    success!("Ran in test...")
+}
+
+pub fn remove_from_steam_real(
+    ctx: &impl ExecCtx,
+    trick_id: &TrickID,
+) -> DeckResult<ActionSuccess> {
+    let _lock = lock_shortcuts(ctx, &format!("removing \"{trick_id}\" from Steam"))?;
+    let mut removed = 0;
+    // As with adding, we don't know which Steam user is ours, so remove from all of them
+    for (filename, mut shortcuts) in get_steam_shortcuts_inner(None, false)? {
+        let count_before = shortcuts.len();
+        shortcuts.retain(|s| !s.is_existing_trick_shortcut(trick_id));
+        if shortcuts.len() != count_before {
+            removed += count_before - shortcuts.len();
+            write_shortcuts_to_disk(ctx, &filename, &shortcuts)?;
+        }
+    }
+
+    if removed == 0 {
+        Err(KnownError::AddToSteamError(format!(
+            "No Steam shortcut found for \"{trick_id}\"."
+        )))
+    } else {
+        success!(format!("Successfully removed \"{trick_id}\" from Steam."))
+    }
+}
+
+// Shortcuts are read, changed, and written back whole, so two writers at once would lose one's changes
+fn lock_shortcuts(ctx: &impl ExecCtx, doing: &str) -> DeckResult<LockGuard> {
+    acquire_lock(
+        ctx,
+        &LockScope::Resource(STEAM_SHORTCUTS_RESOURCE_LOCK),
+        doing,
+    )
+}
+
 pub(crate) fn debug_steam_shortcuts(filename: Option<String>) -> DeckResult<ActionSuccess> {
     let mut outputs = Vec::<String>::default();
     for (filename, shortcuts) in get_steam_shortcuts_inner(filename, true)? {
@@ -503,3 +693,94 @@ pub(crate) fn debug_steam_shortcuts(filename: Option<String>) -> DeckResult<Acti
     }
     success!(outputs.join("\n"))
 }
+
+#[test]
+fn steam_userid_from_shortcuts_path() {
+    assert_eq!(
+        get_steam_userid_from_shortcuts_path(
+            "/home/deck/.local/share/Steam/userdata/12345/config/shortcuts.vdf"
+        ),
+        "12345"
+    );
+    assert_eq!(
+        get_steam_userid_from_shortcuts_path("/tmp/override.vdf"),
+        "/tmp/override.vdf"
+    );
+}
+
+#[test]
+fn launch_options_pass_extra_args() -> DeckResult<()> {
+    let extra_args = vec!["/roms/some game.sfc".to_string(), "$HOME".to_string()];
+
+    let flatpak = Trick {
+        provider_config: ProviderConfig::Flatpak(Flatpak {
+            id: "org.test.Package".into(),
+            env: None,
+        }),
+        ..Trick::test()
+    };
+    let sctx = TrickAddToSteamContext::try_from(&flatpak)?.with_extra_args(&extra_args);
+    assert_eq!(
+        sctx.launch_options,
+        r#""run" "--branch=stable" "--arch=x86_64" "org.test.Package" "/roms/some game.sfc" "\$HOME""#
+    );
+
+    let simple_command = Trick {
+        provider_config: ProviderConfig::SimpleCommand(SimpleCommand {
+            command: "retroarch".into(),
+            args: Some(vec!["--fullscreen".into()]),
+            execution_dir: None,
+            env: Some(EnvVars::from([("FOO".into(), "bar".into())])),
+        }),
+        ..Trick::test()
+    };
+    let sctx = TrickAddToSteamContext::try_from(&simple_command)?.with_extra_args(&extra_args);
+    assert_eq!(
+        sctx.launch_options,
+        r#"FOO="bar" %command% --fullscreen "/roms/some game.sfc" "\$HOME""#
+    );
+
+    // Nothing extra, nothing changed
+    let sctx = TrickAddToSteamContext::try_from(&flatpak)?.with_extra_args(&[]);
+    assert_eq!(
+        sctx.launch_options,
+        r#""run" "--branch=stable" "--arch=x86_64" "org.test.Package""#
+    );
+    Ok(())
+}
+
+#[test]
+fn launch_options_include_env() {
+    let env = EnvVars::from([("FOO".into(), "bar baz".into())]);
+    let args = vec!["--fullscreen".to_string()];
+
+    assert_eq!(
+        get_launch_options_with_env(Some(&env), Some(&args)),
+        "FOO=\"bar baz\" %command% --fullscreen"
+    );
+    assert_eq!(get_launch_options_with_env(None, Some(&args)), "--fullscreen");
+    assert_eq!(get_launch_options_with_env(Some(&env), None), "FOO=\"bar baz\" %command%");
+
+    // Nothing in a value is expanded by Steam's shell
+    let env = EnvVars::from([("FOO".into(), r#"say "hi" to $USER `now` \o/"#.into())]);
+    assert_eq!(
+        get_launch_options_with_env(Some(&env), None),
+        r#"FOO="say \"hi\" to \$USER \`now\` \\o/" %command%"#
+    );
+}
+
+#[test]
+fn flatpak_launch_options_match_run_args() -> DeckResult<()> {
+    let flatpak = Trick {
+        provider_config: ProviderConfig::Flatpak(Flatpak {
+            id: "org.test.Package".into(),
+            env: Some(EnvVars::from([("FOO".into(), "$bar baz".into())])),
+        }),
+        ..Trick::test()
+    };
+    assert_eq!(
+        TrickAddToSteamContext::try_from(&flatpak)?.launch_options,
+        r#""run" "--branch=stable" "--arch=x86_64" "--env=FOO=\$bar baz" "org.test.Package""#
+    );
+    Ok(())
+}
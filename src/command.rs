@@ -1,12 +1,15 @@
 use crate::{actions::SpecificAction, steam::SteamSubcommand};
-use clap::ValueEnum;
+use clap::{Args, ValueEnum};
 use serde::Serialize;
 use crate::gui::GuiType;
-use crate::prelude::TypedAction;
+use crate::history::{DEFAULT_HISTORY_LIMIT, HistoryFilter};
+use crate::profile::ProfileSubcommand;
+use crate::prelude::{CategoryID, TypedAction};
 pub use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[clap(name = "decktricks")]
+#[allow(clippy::struct_excessive_bools)]
 pub struct DecktricksCommand {
     // The actual command to be executed, such as "run" or "install".
     // To seed an Executor for repeated use, use "actions".
@@ -29,6 +32,27 @@ pub struct DecktricksCommand {
     // Will default to the current_log_level of the executor
     #[clap(short, long)]
     pub log_level: Option<LogType>,
+
+    // Print the commands and file writes an action would do, instead of doing them
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+
+    // With --dry-run, don't look at the system at all: system context isn't gathered, and even
+    // commands which only read state aren't run
+    #[clap(long, global = true, requires = "dry_run")]
+    pub no_gather: bool,
+
+    // Go ahead without asking, even if the config has warnings for the action
+    #[clap(short, long, global = true)]
+    pub yes: bool,
+
+    // Fail instead of waiting when another decktricks is busy with something which conflicts
+    #[clap(long, global = true)]
+    pub no_wait: bool,
+
+    // Run remote installer scripts even if they've changed since they were last run
+    #[clap(long, global = true)]
+    pub accept_changes: bool,
 }
 
 impl DecktricksCommand {
@@ -39,6 +63,11 @@ impl DecktricksCommand {
             gather_context_on_specific_actions: false,
             config: None,
             log_level: None,
+            dry_run: false,
+            no_gather: false,
+            yes: false,
+            no_wait: false,
+            accept_changes: false,
         }
     }
 }
@@ -98,27 +127,67 @@ impl From<u8> for LogType {
 pub enum Action {
     Run {
         id: String,
+        // Anything after `--` is passed along to the trick, e.g. `run <id> -- --fullscreen`
+        #[clap(last = true)]
+        extra_args: Vec<String>,
     },
-    Install {
+    // Run inside the nested desktop session, starting one if needed
+    RunNested {
         id: String,
+        #[clap(last = true)]
+        extra_args: Vec<String>,
+    },
+    // Install, Kill, Uninstall, and Update can take several tricks at once, see BatchArgs
+    Install {
+        #[clap(required_unless_present_any = BATCH_SELECTORS)]
+        ids: Vec<String>,
+        #[clap(flatten)]
+        batch: BatchArgs,
     },
     Kill {
-        id: String,
+        #[clap(required_unless_present_any = BATCH_SELECTORS)]
+        ids: Vec<String>,
+        #[clap(flatten)]
+        batch: BatchArgs,
     },
     #[clap(alias = "remove")]
     Uninstall {
-        id: String,
+        #[clap(required_unless_present_any = BATCH_SELECTORS)]
+        ids: Vec<String>,
+        #[clap(flatten)]
+        batch: BatchArgs,
     },
     AddToSteam {
         id: String,
+        // Anything after `--` is passed along whenever the trick is launched from Steam
+        #[clap(last = true)]
+        extra_args: Vec<String>,
+    },
+    RemoveFromSteam {
+        id: String,
     },
     Info {
         id: String,
     },
+    Logs {
+        id: String,
+    },
 
-    // Note that "update" can work both globally or for a specific id.
+    // Reinstall in place, keeping any Steam shortcuts. User data is kept unless asked not to.
+    Reinstall {
+        id: String,
+        #[clap(long)]
+        delete_user_data: bool,
+    },
+
+    // Note that "update" can work both globally or for specific ids.
     Update {
-        id: Option<String>,
+        ids: Vec<String>,
+        #[clap(flatten)]
+        batch: BatchArgs,
+        // Only list what would be updated. Only for updating everything.
+        #[clap(long, conflicts_with_all = ["ids", "category", "all_installed"])]
+        check: bool,
     },
 
     // Items below do not take trick ids, and function differently.
@@ -130,6 +199,9 @@ pub enum Action {
         id: Option<String>,
         #[clap(long)]
         json: bool,
+        /// Also list the actions which aren't available, and why
+        #[clap(long)]
+        explain: bool,
     },
     Gui {
         #[clap(subcommand)]
@@ -142,7 +214,31 @@ pub enum Action {
     },
     Steam {
         #[clap(subcommand)]
-        _steam_subcommand: SteamSubcommand,
+        steam_subcommand: SteamSubcommand,
+    },
+    // Export this machine's setup, or set this machine up from an exported profile
+    Profile {
+        #[clap(subcommand)]
+        profile_subcommand: ProfileSubcommand,
+    },
+    // Show what decktricks has done on this machine, oldest first
+    History {
+        #[clap(flatten)]
+        filter: HistoryFilter,
+        // Show at most this many of the most recent matching actions
+        #[clap(long, default_value_t = DEFAULT_HISTORY_LIMIT)]
+        limit: usize,
+        #[clap(long)]
+        json: bool,
+    },
+    // Show this machine's addresses, and how to connect to it from another computer
+    Network {
+        #[clap(long)]
+        port: Option<u16>,
+        #[clap(long)]
+        qr: bool,
+        #[clap(long)]
+        json: bool,
     },
 
 
@@ -159,25 +255,86 @@ pub enum Action {
     AddDecktricksToSteam,
 }
 
+const BATCH_SELECTORS: [&str; 2] = ["category", "all_installed"];
+pub const DEFAULT_BATCH_JOBS: usize = 4;
+
+// Selects more tricks for the actions which can work on several at once. Tricks picked this way
+// are skipped if the action isn't possible for them, unlike tricks which are named directly.
+#[derive(Debug, Clone, Args)]
+pub struct BatchArgs {
+    // Every trick in this category
+    #[clap(long)]
+    pub category: Option<CategoryID>,
+
+    // Every installed trick
+    #[clap(long)]
+    pub all_installed: bool,
+
+    // How many tricks to work on at once, for providers which allow it
+    #[clap(long, default_value_t = DEFAULT_BATCH_JOBS)]
+    pub jobs: usize,
+}
+
+impl Default for BatchArgs {
+    fn default() -> Self {
+        Self {
+            category: None,
+            all_installed: false,
+            jobs: DEFAULT_BATCH_JOBS,
+        }
+    }
+}
+
+impl BatchArgs {
+    #[must_use]
+    pub fn has_selectors(&self) -> bool {
+        self.category.is_some() || self.all_installed
+    }
+}
+
 impl Action {
     #[must_use]
     pub fn does_not_need_system_context(&self, gather_context_on_specific_actions: bool) -> bool {
-        matches!(self, Self::Info { .. } | Self::GetConfig)
+        matches!(
+            self,
+            Self::Info { .. } | Self::GetConfig | Self::Network { .. } | Self::History { .. }
+        )
             || (gather_context_on_specific_actions
-                && matches!(TypedAction::from(self), TypedAction::Specific(_)))
+                && matches!(TypedAction::from(self), TypedAction::Specific(_))
+                // Whether there's a shortcut to remove is only known from the system context
+                && !matches!(self, Self::RemoveFromSteam { .. }))
     }
 }
 
 impl From<SpecificAction> for Action {
     fn from(a: SpecificAction) -> Self {
         match a {
-            SpecificAction::Run { id } => Self::Run { id },
-            SpecificAction::Update { id } => Self::Update { id: Some(id) },
-            SpecificAction::Uninstall { id } => Self::Uninstall { id },
-            SpecificAction::Install { id } => Self::Install { id },
-            SpecificAction::Kill { id } => Self::Kill { id },
-            SpecificAction::AddToSteam { id } => Self::AddToSteam { id },
+            SpecificAction::Run { id, extra_args } => Self::Run { id, extra_args },
+            SpecificAction::RunNested { id, extra_args } => Self::RunNested { id, extra_args },
+            SpecificAction::Update { id } => Self::Update {
+                ids: vec![id],
+                batch: BatchArgs::default(),
+                check: false,
+            },
+            SpecificAction::Reinstall { id, delete_user_data } => {
+                Self::Reinstall { id, delete_user_data }
+            }
+            SpecificAction::Uninstall { id } => Self::Uninstall {
+                ids: vec![id],
+                batch: BatchArgs::default(),
+            },
+            SpecificAction::Install { id } => Self::Install {
+                ids: vec![id],
+                batch: BatchArgs::default(),
+            },
+            SpecificAction::Kill { id } => Self::Kill {
+                ids: vec![id],
+                batch: BatchArgs::default(),
+            },
+            SpecificAction::AddToSteam { id, extra_args } => Self::AddToSteam { id, extra_args },
+            SpecificAction::RemoveFromSteam { id } => Self::RemoveFromSteam { id },
             SpecificAction::Info { id } => Self::Info { id },
+            SpecificAction::Logs { id } => Self::Logs { id },
         }
     }
 }
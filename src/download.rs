@@ -0,0 +1,926 @@
+use crate::locks::{DOWNLOAD_CACHE_RESOURCE_LOCK, LockScope, acquire_lock};
+use crate::prelude::*;
+use crate::progress::ProgressReader;
+use crate::utils::get_homedir;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Downloads of installers and scripts. Deck Wi-Fi is often flaky and some installers are large,
+// so failed attempts are retried with backoff, and pick up where they left off with Range
+// requests. Finished downloads are cached by URL, and are only downloaded again once the server
+// says they've changed (by ETag).
+//
+// Each URL has these in the cache directory, all named after its cache key:
+//   <key>            the last complete download
+//   <key>.json       the CacheEntry for it
+//   <key>.part       a download in progress (or interrupted)
+//   <key>.part.json  the CacheEntry for the version being downloaded, to resume against
+
+pub const DOWNLOAD_CACHE_DIR_ENV_VAR: &str = "DECKTRICKS_DOWNLOAD_CACHE_DIR";
+
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+// How often a cancelled action is noticed while waiting to retry
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Configured under "downloads" in the config's settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    pub connect_timeout_secs: u64,
+    // How long the server can go without sending anything before we give up on an attempt
+    pub read_timeout_secs: u64,
+    pub max_attempts: u32,
+    // Doubled after each failed attempt
+    pub retry_backoff_ms: u64,
+    // Refuse to run anything downloaded without a sha256 or signature to check it against
+    pub require_verification: bool,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 15,
+            read_timeout_secs: 60,
+            max_attempts: 5,
+            retry_backoff_ms: 1000,
+            require_verification: false,
+        }
+    }
+}
+
+// What a download is checked against before it's run, as configured for each trick that
+// downloads something (see e.g. DeckyInstaller)
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadVerification {
+    // Hex-encoded
+    pub sha256: Option<String>,
+    pub signature: Option<DetachedSignature>,
+}
+
+impl DownloadVerification {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sha256.is_none() && self.signature.is_none()
+    }
+}
+
+// A detached GPG signature for a download, checked with gpgv against a keyring of trusted keys
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetachedSignature {
+    pub url: String,
+    // Path to the keyring file
+    pub keyring: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+}
+
+struct CachePaths {
+    file: PathBuf,
+    entry: PathBuf,
+    part: PathBuf,
+    part_entry: PathBuf,
+}
+
+impl CachePaths {
+    fn new(cache_dir: &Path, url: &str) -> Self {
+        // Hashed, since URLs can be longer than filenames are allowed to be. The last part of
+        // the URL is kept to make the cache easier to look through. (The hash has to stay the
+        // same between builds, or the cache would be lost, so std's hashers won't do.)
+        let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+        let name: String = url
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+            .take(64)
+            .collect();
+        let key = format!("{}-{name}", &hash[..16]);
+
+        Self {
+            file: cache_dir.join(&key),
+            entry: cache_dir.join(format!("{key}.json")),
+            part: cache_dir.join(format!("{key}.part")),
+            part_entry: cache_dir.join(format!("{key}.part.json")),
+        }
+    }
+}
+
+enum Fetched {
+    Unchanged,
+    Downloaded,
+}
+
+enum AttemptError {
+    // Worth trying again, for the given reason
+    Retryable(String),
+    Fatal(KnownError),
+}
+
+#[must_use]
+pub fn get_download_cache_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var(DOWNLOAD_CACHE_DIR_ENV_VAR) {
+        return PathBuf::from(dir);
+    }
+    let cache_home = std::env::var("XDG_CACHE_HOME")
+        .map_or_else(|_| Path::new(get_homedir()).join(".cache"), PathBuf::from);
+    cache_home.join("decktricks/downloads")
+}
+
+// Downloads `url` to `dest` as an executable. Whatever is at `dest` is only replaced once the
+// download has completed and been verified.
+pub(crate) fn download_executable(
+    ctx: &impl ExecCtx,
+    url: &str,
+    verification: &DownloadVerification,
+    dest: &Path,
+) -> DeckResult<()> {
+    download_executable_with_cache(ctx, &get_download_cache_dir(), url, verification, dest)
+}
+
+fn download_executable_with_cache(
+    ctx: &impl ExecCtx,
+    cache_dir: &Path,
+    url: &str,
+    verification: &DownloadVerification,
+    dest: &Path,
+) -> DeckResult<()> {
+    // A dry run should fail the same way the real run would
+    if verification.is_empty() && ctx.get_settings().downloads.require_verification {
+        return Err(KnownError::UnverifiedDownloadRefused(url.into()));
+    }
+    if skip_for_dry_run(ctx, format!("download \"{url}\" to \"{}\"", dest.display())) {
+        return Ok(());
+    }
+
+    let cached = fetch_to_cache(ctx, cache_dir, url)?;
+    let signature_file = match &verification.signature {
+        Some(signature) => Some(fetch_to_cache(ctx, cache_dir, &signature.url)?),
+        None => None,
+    };
+    let res = install_from_cache(&cached, dest, |copy| {
+        verify_download(ctx, url, copy, verification, signature_file.as_deref())
+    });
+
+    // Whatever was cached can't be trusted, so the next attempt starts from scratch
+    if let Err(KnownError::DownloadVerificationFailed(_)) = res {
+        let signature_url = verification.signature.as_ref().map(|s| s.url.as_str());
+        for cached_url in std::iter::once(url).chain(signature_url) {
+            let paths = CachePaths::new(cache_dir, cached_url);
+            let _ = std::fs::remove_file(paths.entry);
+            let _ = std::fs::remove_file(paths.file);
+        }
+    }
+    res
+}
+
+fn fetch_to_cache(ctx: &impl ExecCtx, cache_dir: &Path, url: &str) -> DeckResult<PathBuf> {
+    let _lock = acquire_lock(
+        ctx,
+        &LockScope::Resource(DOWNLOAD_CACHE_RESOURCE_LOCK),
+        &format!("downloading {url}"),
+    )?;
+    std::fs::create_dir_all(cache_dir).map_err(|e| {
+        KnownError::DownloadError(format!(
+            "Could not create download cache \"{}\": {e}",
+            cache_dir.display()
+        ))
+    })?;
+
+    let paths = CachePaths::new(cache_dir, url);
+    let settings = ctx.get_settings().downloads.clone();
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(settings.connect_timeout_secs))
+        .timeout_read(Duration::from_secs(settings.read_timeout_secs))
+        .build();
+
+    let cached = read_entry(&paths.entry).filter(|entry| entry.url == url && paths.file.exists());
+    let cached_etag = cached.as_ref().and_then(|entry| entry.etag.as_deref());
+
+    let mut attempt = 1;
+    loop {
+        let reason = match try_download(ctx, &agent, url, &paths, cached_etag) {
+            Ok(Fetched::Unchanged) => {
+                info!(ctx, "Cached download of {url} is up to date.");
+                return Ok(paths.file);
+            }
+            Ok(Fetched::Downloaded) => return Ok(paths.file),
+            Err(AttemptError::Fatal(err)) => return Err(err),
+            Err(AttemptError::Retryable(reason)) => reason,
+        };
+
+        if attempt >= settings.max_attempts {
+            if cached.is_some() {
+                warn!(
+                    ctx,
+                    "Could not check {url} for changes ({reason}), so using the cached copy."
+                );
+                return Ok(paths.file);
+            }
+            return Err(KnownError::DownloadError(format!(
+                "Gave up on {url} after {attempt} attempts: {reason}"
+            )));
+        }
+
+        let backoff = get_retry_backoff(&settings, attempt);
+        attempt += 1;
+        warn!(
+            ctx,
+            "Downloading {url} failed ({reason}). Trying again in {:.1}s (attempt {attempt}/{})...",
+            backoff.as_secs_f64(),
+            settings.max_attempts
+        );
+        sleep_unless_cancelled(ctx, backoff);
+        if ctx.get_cancel_handle().is_cancelled() {
+            return Err(KnownError::DownloadError(format!(
+                "Cancelled downloading {url}."
+            )));
+        }
+    }
+}
+
+fn try_download(
+    ctx: &impl ExecCtx,
+    agent: &ureq::Agent,
+    url: &str,
+    paths: &CachePaths,
+    cached_etag: Option<&str>,
+) -> Result<Fetched, AttemptError> {
+    let cache_err = |e: std::io::Error| {
+        AttemptError::Fatal(KnownError::DownloadError(format!(
+            "Could not write to download cache: {e}"
+        )))
+    };
+
+    let mut request = agent.get(url);
+    if let Some(etag) = cached_etag {
+        request = request.set("If-None-Match", etag);
+    }
+    // Only resumed against the same version of the file, which the server checks with If-Range
+    let resume = read_entry(&paths.part_entry)
+        .filter(|entry| entry.url == url)
+        .and_then(|entry| entry.etag)
+        .map(|etag| (std::fs::metadata(&paths.part).map_or(0, |m| m.len()), etag))
+        .filter(|(len, _)| *len > 0);
+    if let Some((len, etag)) = &resume {
+        request = request
+            .set("Range", &format!("bytes={len}-"))
+            .set("If-Range", etag);
+    }
+
+    let response = match request.call() {
+        // Most likely the partial download was already complete, so there was nothing left to
+        // send. It can't be trusted either way, so this starts over without it.
+        Err(ureq::Error::Status(416, _)) if resume.is_some() => {
+            warn!(ctx, "Could not resume downloading {url}, so starting over.");
+            let _ = std::fs::remove_file(&paths.part_entry);
+            let _ = std::fs::remove_file(&paths.part);
+            return try_download(ctx, agent, url, paths, cached_etag);
+        }
+        res => res,
+    };
+    let response = response.map_err(|err| match err {
+        ureq::Error::Status(code, _) if is_retryable_status(code) => {
+            AttemptError::Retryable(format!("HTTP {code}"))
+        }
+        ureq::Error::Status(code, response) => AttemptError::Fatal(KnownError::DownloadError(
+            format!("{url} returned HTTP {code} {}", response.status_text()),
+        )),
+        ureq::Error::Transport(transport) => AttemptError::Retryable(transport.to_string()),
+    })?;
+
+    let (mut file, done, total) = match (response.status(), &resume) {
+        (304, _) if cached_etag.is_some() => return Ok(Fetched::Unchanged),
+        (206, Some((len, _))) => {
+            let range = response
+                .header("Content-Range")
+                .and_then(parse_content_range);
+            if range.is_none_or(|(start, _)| start != *len) {
+                // Start over from scratch next time
+                let _ = std::fs::remove_file(&paths.part_entry);
+                return Err(AttemptError::Retryable(
+                    "server sent the wrong part of the file".into(),
+                ));
+            }
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&paths.part)
+                .map_err(cache_err)?;
+            (file, *len, range.and_then(|(_, total)| total))
+        }
+        _ => {
+            // A fresh copy, either because there was nothing to resume, or because the file has
+            // changed since
+            let entry = CacheEntry {
+                url: url.into(),
+                etag: response.header("ETag").map(Into::into),
+            };
+            write_entry(&paths.part_entry, &entry).map_err(cache_err)?;
+            let file = File::create(&paths.part).map_err(cache_err)?;
+            let total = response
+                .header("Content-Length")
+                .and_then(|len| len.parse::<u64>().ok());
+            (file, 0, total)
+        }
+    };
+
+    let mut reader =
+        ProgressReader::new(ctx, response.into_reader(), "Downloading", total).starting_at(done);
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    let mut received = done;
+    loop {
+        if ctx.get_cancel_handle().is_cancelled() {
+            return Err(AttemptError::Fatal(KnownError::DownloadError(format!(
+                "Cancelled downloading {url}."
+            ))));
+        }
+        let num_read = reader.read(&mut buf).map_err(|e| {
+            AttemptError::Retryable(format!("connection lost after {received} bytes: {e}"))
+        })?;
+        if num_read == 0 {
+            break;
+        }
+        file.write_all(&buf[..num_read]).map_err(cache_err)?;
+        received += num_read as u64;
+    }
+    if let Some(total) = total
+        && received != total
+    {
+        return Err(AttemptError::Retryable(format!(
+            "only got {received} of {total} bytes"
+        )));
+    }
+    file.sync_all().map_err(cache_err)?;
+
+    // The old entry goes first, so that a crash part way through can't pair it with the new file
+    let _ = std::fs::remove_file(&paths.entry);
+    std::fs::rename(&paths.part, &paths.file).map_err(cache_err)?;
+    std::fs::rename(&paths.part_entry, &paths.entry).map_err(cache_err)?;
+    Ok(Fetched::Downloaded)
+}
+
+// The copy is verified where it will be run from, so that what's checked is what's run. It's in
+// the same directory as `dest`, so that it can be renamed into place.
+fn install_from_cache(
+    cached: &Path,
+    dest: &Path,
+    verify: impl FnOnce(&Path) -> DeckResult<()>,
+) -> DeckResult<()> {
+    let install_err = |e: std::io::Error| {
+        KnownError::DownloadError(format!(
+            "Could not copy download to \"{}\": {e}",
+            dest.display()
+        ))
+    };
+    let dest_dir = dest
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".decktricks-download-")
+        .tempfile_in(dest_dir)
+        .map_err(install_err)?;
+    let mut source = File::open(cached).map_err(install_err)?;
+    std::io::copy(&mut source, temp.as_file_mut()).map_err(install_err)?;
+    temp.as_file().sync_all().map_err(install_err)?;
+    verify(temp.path())?;
+    temp.as_file()
+        .set_permissions(std::fs::Permissions::from_mode(0o755))
+        .map_err(install_err)?;
+    temp.persist(dest).map_err(|e| install_err(e.error))?;
+    Ok(())
+}
+
+fn verify_download(
+    ctx: &impl ExecCtx,
+    url: &str,
+    file: &Path,
+    verification: &DownloadVerification,
+    signature_file: Option<&Path>,
+) -> DeckResult<()> {
+    if let Some(expected) = &verification.sha256 {
+        let actual = get_sha256(file).map_err(|e| {
+            KnownError::DownloadError(format!("Could not read \"{}\": {e}", file.display()))
+        })?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(KnownError::DownloadVerificationFailed(format!(
+                "{url} has SHA-256 {actual}, but {expected} was expected."
+            )));
+        }
+    }
+
+    if let (Some(signature), Some(signature_file)) = (&verification.signature, signature_file) {
+        let res = ctx
+            .sys_command(
+                "gpgv",
+                [
+                    "--keyring",
+                    signature.keyring.as_str(),
+                    &signature_file.to_string_lossy(),
+                    &file.to_string_lossy(),
+                ],
+            )
+            .run()
+            .map_err(|err| match err {
+                KnownError::SystemCommandRunFailure(run_err)
+                    if run_err.error.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    KnownError::DownloadVerificationFailed(format!(
+                        "\"gpgv\" is needed to check the signature of {url}, but it isn't \
+                         installed. Install gpgv (or gnupg) and try again."
+                    ))
+                }
+                other => other,
+            })?;
+        if !res.ran_successfully() {
+            return Err(KnownError::DownloadVerificationFailed(format!(
+                "The signature from {} for {url} could not be verified with \"{}\": {}",
+                signature.url,
+                signature.keyring,
+                String::from_utf8_lossy(&res.raw_output().stderr).trim()
+            )));
+        }
+    }
+
+    if !verification.is_empty() {
+        info!(ctx, "Verified download of {url}.");
+    }
+    Ok(())
+}
+
+pub(crate) fn get_sha256(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn read_entry(path: &Path) -> Option<CacheEntry> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+fn write_entry(path: &Path, entry: &CacheEntry) -> std::io::Result<()> {
+    std::fs::write(path, serde_json::to_string(entry)?)
+}
+
+// Timeouts, rate limiting, and server trouble may well be gone by the next attempt
+fn is_retryable_status(code: u16) -> bool {
+    matches!(code, 408 | 429) || code >= 500
+}
+
+fn get_retry_backoff(settings: &DownloadSettings, attempt: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    Duration::from_millis(settings.retry_backoff_ms.saturating_mul(factor)).min(MAX_RETRY_BACKOFF)
+}
+
+fn sleep_unless_cancelled(ctx: &impl ExecCtx, duration: Duration) {
+    let mut remaining = duration;
+    while !remaining.is_zero() && !ctx.get_cancel_handle().is_cancelled() {
+        let step = remaining.min(CANCEL_CHECK_INTERVAL);
+        std::thread::sleep(step);
+        remaining -= step;
+    }
+}
+
+// "bytes 500-999/1000" is (500, Some(1000)). The total can also be "*", for unknown.
+fn parse_content_range(header: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = header.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    // Stands in for a web server. Each request is answered with whatever `respond` returns for
+    // its headers and its number (starting from 0), and the connection is then closed.
+    struct TestServer {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestServer {
+        fn start(respond: impl Fn(&str, usize) -> Vec<u8> + Send + 'static) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/installer.sh", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(vec![]));
+            let seen = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else {
+                        break;
+                    };
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut head = String::new();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                            break;
+                        }
+                        head.push_str(&line.to_lowercase());
+                    }
+                    let index = {
+                        let mut seen = seen.lock().unwrap();
+                        seen.push(head.clone());
+                        seen.len() - 1
+                    };
+                    let _ = stream.write_all(&respond(&head, index));
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn get_requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    fn response(status: &str, headers: &[(&str, String)], body: &[u8]) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {status}\r\nConnection: close\r\n");
+        for (name, value) in headers {
+            out.push_str(&format!("{name}: {value}\r\n"));
+        }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn file_response(etag: &str, body: &[u8]) -> Vec<u8> {
+        response(
+            "200 OK",
+            &[
+                ("ETag", etag.into()),
+                ("Content-Length", body.len().to_string()),
+            ],
+            body,
+        )
+    }
+
+    fn get_body() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn fast_retry_ctx() -> GeneralExecutionContext {
+        GeneralExecutionContext {
+            settings: Arc::new(DecktricksConfigSettings {
+                downloads: DownloadSettings {
+                    max_attempts: 3,
+                    retry_backoff_ms: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..GeneralExecutionContext::test()
+        }
+    }
+
+    #[test]
+    fn interrupted_downloads_resume() {
+        let server = TestServer::start(|head, index| {
+            let body = get_body();
+            if index == 0 {
+                // Claims the whole file, but the connection drops halfway through
+                return response(
+                    "200 OK",
+                    &[
+                        ("ETag", "\"v1\"".into()),
+                        ("Content-Length", body.len().to_string()),
+                    ],
+                    &body[..50_000],
+                );
+            }
+            assert!(head.contains("range: bytes=50000-"));
+            assert!(head.contains("if-range: \"v1\""));
+            response(
+                "206 Partial Content",
+                &[
+                    ("Content-Range", format!("bytes 50000-99999/{}", body.len())),
+                    ("Content-Length", "50000".into()),
+                ],
+                &body[50_000..],
+            )
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let dest = dest_dir.path().join("installer.sh");
+
+        download_executable_with_cache(
+            &fast_retry_ctx(),
+            cache_dir.path(),
+            &server.url,
+            &DownloadVerification::default(),
+            &dest,
+        )
+        .unwrap();
+
+        assert_eq!(2, server.get_requests().len());
+        assert_eq!(get_body(), std::fs::read(&dest).unwrap());
+        let mode = std::fs::metadata(&dest).unwrap().permissions().mode();
+        assert_eq!(0o755, mode & 0o777);
+        // Nothing is left half-done
+        let leftovers: Vec<_> = std::fs::read_dir(cache_dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.contains(".part"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn unresumable_downloads_start_over() {
+        let server = TestServer::start(|head, index| {
+            let body = get_body();
+            match index {
+                // Complete, but the connection drops before anything is moved into place
+                0 => response(
+                    "200 OK",
+                    &[
+                        ("ETag", "\"v1\"".into()),
+                        ("Content-Length", (body.len() + 1).to_string()),
+                    ],
+                    &body,
+                ),
+                1 => {
+                    assert!(head.contains(&format!("range: bytes={}-", body.len())));
+                    response("416 Range Not Satisfiable", &[], b"")
+                }
+                _ => {
+                    assert!(!head.contains("range:"));
+                    file_response("\"v1\"", &body)
+                }
+            }
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let dest = dest_dir.path().join("installer.sh");
+
+        download_executable_with_cache(
+            &fast_retry_ctx(),
+            cache_dir.path(),
+            &server.url,
+            &DownloadVerification::default(),
+            &dest,
+        )
+        .unwrap();
+
+        assert_eq!(3, server.get_requests().len());
+        assert_eq!(get_body(), std::fs::read(&dest).unwrap());
+    }
+
+    #[test]
+    fn cached_downloads_are_checked_by_etag() {
+        let server = TestServer::start(|head, index| match index {
+            0 => file_response("\"v1\"", b"first"),
+            1 => {
+                assert!(head.contains("if-none-match: \"v1\""));
+                response("304 Not Modified", &[], b"")
+            }
+            _ => file_response("\"v2\"", b"second"),
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let dest = dest_dir.path().join("installer.sh");
+        let ctx = fast_retry_ctx();
+
+        for expected in ["first", "first", "second"] {
+            download_executable_with_cache(
+                &ctx,
+                cache_dir.path(),
+                &server.url,
+                &DownloadVerification::default(),
+                &dest,
+            )
+            .unwrap();
+            assert_eq!(expected, std::fs::read_to_string(&dest).unwrap());
+        }
+        assert_eq!(3, server.get_requests().len());
+    }
+
+    #[test]
+    fn only_some_failures_are_retried() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+
+        let server = TestServer::start(|_, _| response("503 Service Unavailable", &[], b""));
+        let err = fetch_to_cache(&fast_retry_ctx(), cache_dir.path(), &server.url).unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts: HTTP 503"));
+        assert_eq!(3, server.get_requests().len());
+
+        let server = TestServer::start(|_, _| response("404 Not Found", &[], b""));
+        let err = fetch_to_cache(&fast_retry_ctx(), cache_dir.path(), &server.url).unwrap_err();
+        assert!(err.to_string().contains("returned HTTP 404"));
+        assert_eq!(1, server.get_requests().len());
+        assert!(!dest.exists());
+    }
+
+    fn sha256_only(sha256: &str) -> DownloadVerification {
+        DownloadVerification {
+            sha256: Some(sha256.into()),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn checksums_are_checked_before_installing() {
+        let server = TestServer::start(|_, _| file_response("\"v1\"", b"contents"));
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+        let ctx = fast_retry_ctx();
+
+        let wrong = sha256_only(&"0".repeat(64));
+        let err =
+            download_executable_with_cache(&ctx, cache_dir.path(), &server.url, &wrong, &dest)
+                .unwrap_err();
+        assert!(matches!(err, KnownError::DownloadVerificationFailed(_)));
+        assert!(!dest.exists());
+
+        // The cached copy was thrown out, so this downloads it again
+        let right = sha256_only(&format!("{:X}", Sha256::digest(b"contents")));
+        download_executable_with_cache(&ctx, cache_dir.path(), &server.url, &right, &dest).unwrap();
+        assert_eq!("contents", std::fs::read_to_string(&dest).unwrap());
+        let requests = server.get_requests();
+        assert_eq!(2, requests.len());
+        assert!(!requests[1].contains("if-none-match"));
+    }
+
+    #[test]
+    fn signatures_are_checked_with_gpgv() {
+        let server = TestServer::start(|head, _| {
+            if head.starts_with("get /installer.sh.sig ") {
+                file_response("\"sig\"", b"signature")
+            } else {
+                file_response("\"v1\"", b"contents")
+            }
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+        let verification = DownloadVerification {
+            sha256: None,
+            signature: Some(DetachedSignature {
+                url: format!("{}.sig", server.url),
+                keyring: "/etc/decktricks/trusted.gpg".into(),
+            }),
+        };
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .withf(|cmd| {
+                cmd.cmd == "gpgv"
+                    && cmd.args[..2] == ["--keyring", "/etc/decktricks/trusted.gpg"]
+                    && std::fs::read_to_string(&cmd.args[2]).unwrap() == "signature"
+                    && std::fs::read_to_string(&cmd.args[3]).unwrap() == "contents"
+            })
+            .returning(|_| {
+                Ok(SysCommandResult::fake_for_test(
+                    "gpgv",
+                    vec![],
+                    256,
+                    "",
+                    "gpgv: BAD signature",
+                ))
+            });
+        let ctx = GeneralExecutionContext {
+            runner: Arc::new(mock),
+            ..fast_retry_ctx()
+        };
+
+        let err = download_executable_with_cache(
+            &ctx,
+            cache_dir.path(),
+            &server.url,
+            &verification,
+            &dest,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("gpgv: BAD signature"));
+        assert!(!dest.exists());
+        // Neither the download nor its signature is trusted from the cache next time
+        let signature_url = &verification.signature.as_ref().unwrap().url;
+        for cached_url in [&server.url, signature_url] {
+            let paths = CachePaths::new(cache_dir.path(), cached_url);
+            assert!(!paths.entry.exists() && !paths.file.exists());
+        }
+    }
+
+    #[test]
+    fn missing_gpgv_fails_verification() {
+        let server = TestServer::start(|_, _| file_response("\"v1\"", b"contents"));
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+        let verification = DownloadVerification {
+            sha256: None,
+            signature: Some(DetachedSignature {
+                url: format!("{}.sig", server.url),
+                keyring: "/etc/decktricks/trusted.gpg".into(),
+            }),
+        };
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run().times(1).returning(|cmd| {
+            Err(KnownError::SystemCommandRunFailure(Box::new(SysCommandRunError {
+                cmd: cmd.clone(),
+                error: std::io::ErrorKind::NotFound.into(),
+            })))
+        });
+        let ctx = GeneralExecutionContext {
+            runner: Arc::new(mock),
+            ..fast_retry_ctx()
+        };
+
+        let err = download_executable_with_cache(
+            &ctx,
+            cache_dir.path(),
+            &server.url,
+            &verification,
+            &dest,
+        )
+        .unwrap_err();
+        match err {
+            KnownError::DownloadVerificationFailed(msg) => assert!(msg.contains("Install gpgv")),
+            other => panic!("Expected a verification failure, got: {other:?}"),
+        }
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn unverified_downloads_can_be_refused() {
+        let server = TestServer::start(|_, _| file_response("\"v1\"", b"contents"));
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+        let ctx = GeneralExecutionContext {
+            settings: Arc::new(DecktricksConfigSettings {
+                downloads: DownloadSettings {
+                    require_verification: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..GeneralExecutionContext::test()
+        };
+
+        let dry_run_ctx = GeneralExecutionContext {
+            runner: Arc::new(DryRunActualRunner::new()),
+            ..ctx.clone()
+        };
+
+        for ctx in [ctx, dry_run_ctx] {
+            let err = download_executable_with_cache(
+                &ctx,
+                cache_dir.path(),
+                &server.url,
+                &DownloadVerification::default(),
+                &dest,
+            )
+            .unwrap_err();
+            assert!(matches!(err, KnownError::UnverifiedDownloadRefused(_)));
+        }
+        assert!(server.get_requests().is_empty());
+    }
+
+    #[test]
+    fn dry_run_downloads_nothing() {
+        let server = TestServer::start(|_, _| file_response("\"v1\"", b"contents"));
+        let cache_dir = tempfile::tempdir().unwrap();
+        let dest = cache_dir.path().join("installer.sh");
+        let ctx = GeneralExecutionContext::test_with_runner(Arc::new(DryRunActualRunner::new()));
+
+        download_executable_with_cache(
+            &ctx,
+            cache_dir.path(),
+            &server.url,
+            &DownloadVerification::default(),
+            &dest,
+        )
+        .unwrap();
+        assert!(server.get_requests().is_empty());
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn content_range_is_parsed() {
+        assert_eq!(
+            Some((500, Some(1000))),
+            parse_content_range("bytes 500-999/1000")
+        );
+        assert_eq!(Some((0, None)), parse_content_range("bytes 0-99/*"));
+        assert_eq!(None, parse_content_range("items 0-1/2"));
+    }
+}
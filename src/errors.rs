@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::script_review::ScriptReview;
 use std::fmt::Display;
 use std::{backtrace::Backtrace, fmt::Debug};
 use urlencoding::encode;
@@ -13,25 +14,42 @@ pub enum KnownError {
     ActionNotImplementedYet(&'static str),
     ActionNotPossible(&'static str),
     AddToSteamError(String),
+    // The summary has a line for every member, including the ones which succeeded or were skipped
+    BundleMemberFailures(String, Vec<(TrickID, KnownError)>),
     CommandLineParseError(clap::error::Error),
     ConfigParsing(serde_json::Error),
     ConfigRead(std::io::Error),
     DeckyInstall(DynamicError),
+    DownloadError(String),
+    DownloadVerificationFailed(String),
     EmuDeckInstall(DynamicError),
     ErrorDuringRun(&'static str),
+    HistoryReadError(String),
+    HistoryWriteError(String),
+    LockError(String),
+    LockHeld(String),
+    NestedDesktopError(String),
+    NetworkInfoError(String),
     NoAvailableActions(TrickID),
+    ProfileError(String),
     ProviderNotImplemented(String),
+    ReinstallFailedAfterUninstall(Box<KnownError>),
     //RawSystemFailure(std::io::Error),
+    RemoteScriptChanged(Box<ScriptReview>),
     RemoteScriptError(String),
     SeriousError(SeriousError),
+    SshSessionError(String),
     LiveSystemCommandKillError(std::io::Error),
     LiveSystemCommandStatusCheckError(std::io::Error),
+    SystemCommandCancelled(Box<SysCommand>),
     SystemCommandFailed(Box<SysCommandResult>),
     SystemCommandParse(String),
     SystemCommandThreadError(String),
+    SystemCommandTimedOut(Box<SysCommand>),
     SystemCommandRunFailure(Box<SysCommandRunError>),
     TestError(String),
     UnknownTrickID(TrickID),
+    UnverifiedDownloadRefused(String),
 }
 
 impl Display for KnownError {
@@ -40,6 +58,9 @@ impl Display for KnownError {
             Self::AddToSteamError(msg) => {
                 write!(f, "Error adding to Steam: {msg}")
             }
+            Self::BundleMemberFailures(summary, _) => {
+                write!(f, "Some bundle members failed:\n{summary}")
+            }
             Self::ConfigParsing(serde_json_err) => {
                 write!(f, "Error parsing config: {serde_json_err:?}")
             }
@@ -54,15 +75,22 @@ impl Display for KnownError {
             Self::EmuDeckInstall(emudeck_install_err) => {
                 write!(f, "Error installing EmuDeck: {emudeck_install_err:#?}")
             }
+            Self::DownloadError(msg) => write!(f, "Error downloading: {msg}"),
+            Self::DownloadVerificationFailed(msg) => {
+                write!(f, "Download failed verification, so it was not run: {msg}")
+            }
+            Self::UnverifiedDownloadRefused(url) => write!(
+                f,
+                "Refusing to run {url}, since there is no sha256 or signature to verify it with \
+                 and downloads.require_verification is set."
+            ),
             Self::SeriousError(serious_err) => write!(f, "{serious_err}"),
+            Self::SshSessionError(msg) => write!(f, "Error setting up SSH session: {msg}"),
             Self::SystemCommandParse(sys_parse_err) => {
                 write!(f, "Error parsing system command: {sys_parse_err:#?}")
             }
             Self::SystemCommandRunFailure(sys_run_err) => {
-                let command = &sys_run_err.cmd.cmd;
-                let args = sys_run_err.cmd.args.join(" ");
-                let sep = if sys_run_err.cmd.args.len() > 0 { " " } else { "" };
-                let command_string = format!("{command}{sep}{args}");
+                let command_string = sys_run_err.cmd.get_cmdline();
                 let err_msg = sys_run_err.error.to_string();
 
                 write!(f, "Error running system command: `{command_string}`: \"{err_msg}\"")
@@ -70,6 +98,17 @@ impl Display for KnownError {
             Self::SystemCommandThreadError(sys_run_err) => {
                 write!(f, "Error in system command thread: {sys_run_err:#?}")
             }
+            Self::SystemCommandCancelled(sys_command) => {
+                write!(f, "Cancelled system command: `{}`", sys_command.get_cmdline())
+            }
+            Self::SystemCommandTimedOut(sys_command) => {
+                let secs = sys_command.timeout.unwrap_or_default().as_secs();
+                write!(
+                    f,
+                    "System command timed out after {secs} seconds: `{}`",
+                    sys_command.get_cmdline()
+                )
+            }
             Self::SystemCommandFailed(output) => {
                 write!(f, "System command failed: {output:?}")
             }
@@ -79,15 +118,33 @@ impl Display for KnownError {
             Self::LiveSystemCommandStatusCheckError(sys_status_err) => {
                 write!(f, "Error checking status of live process: {sys_status_err:#?}")
             }
+            Self::RemoteScriptChanged(review) => write!(
+                f,
+                "{review}\n\nIf these changes look fine, run again with --accept-changes."
+            ),
             Self::RemoteScriptError(output) => {
                 write!(f, "Error while fetching remote script: {output}")
             }
+            Self::ReinstallFailedAfterUninstall(err) => write!(
+                f,
+                "Uninstalled (and removed user data), but installing again failed, so this is \
+                 no longer installed. Try installing it again once the problem is fixed: {err}"
+            ),
             Self::UnknownTrickID(trick_id) => write!(f, "Unknown trick ID: {trick_id}"),
+            Self::HistoryReadError(msg) => write!(f, "Error reading action history: {msg}"),
+            Self::HistoryWriteError(msg) => write!(f, "Error writing action history: {msg}"),
+            Self::NestedDesktopError(msg) => write!(f, "Error using nested desktop session: {msg}"),
+            Self::NetworkInfoError(msg) => write!(f, "Error reading network info: {msg}"),
+            Self::ProfileError(msg) => write!(f, "Error with machine profile: {msg}"),
             Self::NoAvailableActions(trick_id) => write!(
                 f,
                 "No actions available for \"{trick_id}\". This is almost certainly a bug."
             ),
-            Self::ActionGated(msg) | Self::ProviderNotImplemented(msg) | Self::TestError(msg) => {
+            Self::LockError(msg) => write!(f, "Error with decktricks lock: {msg}"),
+            Self::ActionGated(msg)
+            | Self::LockHeld(msg)
+            | Self::ProviderNotImplemented(msg)
+            | Self::TestError(msg) => {
                 write!(f, "{msg}")
             }
 
@@ -2,6 +2,7 @@ use crate::prelude::*;
 use crate::providers::system_context::FullSystemContext;
 use crate::tricks_status::AllTricksStatus;
 use crate::tricks_status::TrickStatus;
+use std::borrow::Cow;
 use std::sync::Arc;
 
 pub trait ExecCtx: Clone + Send + Sync {
@@ -11,6 +12,10 @@ pub trait ExecCtx: Clone + Send + Sync {
     fn get_current_log_level(&self) -> LogType;
     fn get_logger(&self) -> LoggerRc;
     fn get_settings(&self) -> SettingsRc;
+    fn get_cancel_handle(&self) -> &CancelHandle;
+    fn get_progress_sender(&self) -> Option<&ProgressSender>;
+    fn get_lock_wait(&self) -> LockWait;
+    fn accepts_script_changes(&self) -> bool;
 
     #[allow(clippy::needless_pass_by_value)]
     #[must_use]
@@ -77,6 +82,34 @@ impl ExecCtx for ExecutionContext {
         }
     }
 
+    fn get_cancel_handle(&self) -> &CancelHandle {
+        match self {
+            Self::General(x) => x.get_cancel_handle(),
+            Self::Specific(x) => x.get_cancel_handle(),
+        }
+    }
+
+    fn get_progress_sender(&self) -> Option<&ProgressSender> {
+        match self {
+            Self::General(x) => x.get_progress_sender(),
+            Self::Specific(x) => x.get_progress_sender(),
+        }
+    }
+
+    fn get_lock_wait(&self) -> LockWait {
+        match self {
+            Self::General(x) => x.get_lock_wait(),
+            Self::Specific(x) => x.get_lock_wait(),
+        }
+    }
+
+    fn accepts_script_changes(&self) -> bool {
+        match self {
+            Self::General(x) => x.accepts_script_changes(),
+            Self::Specific(x) => x.accepts_script_changes(),
+        }
+    }
+
     fn as_ctx(&self) -> ExecutionContext {
         self.clone()
     }
@@ -118,6 +151,34 @@ impl ExecCtx for &ExecutionContext {
         }
     }
 
+    fn get_cancel_handle(&self) -> &CancelHandle {
+        match self {
+            ExecutionContext::General(x) => x.get_cancel_handle(),
+            ExecutionContext::Specific(x) => x.get_cancel_handle(),
+        }
+    }
+
+    fn get_progress_sender(&self) -> Option<&ProgressSender> {
+        match self {
+            ExecutionContext::General(x) => x.get_progress_sender(),
+            ExecutionContext::Specific(x) => x.get_progress_sender(),
+        }
+    }
+
+    fn get_lock_wait(&self) -> LockWait {
+        match self {
+            ExecutionContext::General(x) => x.get_lock_wait(),
+            ExecutionContext::Specific(x) => x.get_lock_wait(),
+        }
+    }
+
+    fn accepts_script_changes(&self) -> bool {
+        match self {
+            ExecutionContext::General(x) => x.accepts_script_changes(),
+            ExecutionContext::Specific(x) => x.accepts_script_changes(),
+        }
+    }
+
     fn as_ctx(&self) -> ExecutionContext {
         (*self).clone()
     }
@@ -180,6 +241,13 @@ pub struct SpecificExecutionContext {
     pub runner: RunnerRc,
     pub logger: LoggerRc,
     pub settings: SettingsRc,
+    // Applied to every command run for this trick, e.g. to point it at a nested desktop session
+    pub extra_env: EnvVars,
+    pub cancel_handle: CancelHandle,
+    pub progress_sender: Option<ProgressSender>,
+    pub lock_wait: LockWait,
+    // Whether remote scripts may be run even though they've changed, see crate::script_review
+    pub accept_script_changes: bool,
 
     // There's a code smell here. This is essentially "information from
     // the full system context relevant to this action/trick"
@@ -197,6 +265,11 @@ pub struct GeneralExecutionContext {
     pub runner: RunnerRc,
     pub logger: LoggerRc,
     pub settings: SettingsRc,
+    pub cancel_handle: CancelHandle,
+    pub progress_sender: Option<ProgressSender>,
+    pub lock_wait: LockWait,
+    // Whether remote scripts may be run even though they've changed, see crate::script_review
+    pub accept_script_changes: bool,
 }
 
 impl GeneralExecutionContext {
@@ -214,6 +287,10 @@ impl GeneralExecutionContext {
             runner,
             logger,
             settings,
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
         }
     }
 
@@ -227,6 +304,10 @@ impl GeneralExecutionContext {
             runner: Arc::new(MockTestActualRunner::new()),
             logger: Arc::new(DecktricksConsoleLogger::new()),
             settings: Arc::new(DecktricksConfigSettings::default()),
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
         }
     }
 
@@ -238,6 +319,31 @@ impl GeneralExecutionContext {
             runner,
             logger: Arc::new(DecktricksConsoleLogger::new()),
             settings: Arc::new(DecktricksConfigSettings::default()),
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_progress_sender(self, progress_sender: Option<ProgressSender>) -> Self {
+        Self {
+            progress_sender,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_lock_wait(self, lock_wait: LockWait) -> Self {
+        Self { lock_wait, ..self }
+    }
+
+    #[must_use]
+    pub(crate) fn with_accept_script_changes(self, accept_script_changes: bool) -> Self {
+        Self {
+            accept_script_changes,
+            ..self
         }
     }
 
@@ -275,6 +381,22 @@ impl ExecCtx for GeneralExecutionContext {
     fn get_settings(&self) -> SettingsRc {
         self.settings.clone()
     }
+
+    fn get_cancel_handle(&self) -> &CancelHandle {
+        &self.cancel_handle
+    }
+
+    fn get_progress_sender(&self) -> Option<&ProgressSender> {
+        self.progress_sender.as_ref()
+    }
+
+    fn get_lock_wait(&self) -> LockWait {
+        self.lock_wait
+    }
+
+    fn accepts_script_changes(&self) -> bool {
+        self.accept_script_changes
+    }
 }
 
 impl ExecCtx for SpecificExecutionContext {
@@ -301,6 +423,22 @@ impl ExecCtx for SpecificExecutionContext {
     fn get_settings(&self) -> SettingsRc {
         self.settings.clone()
     }
+
+    fn get_cancel_handle(&self) -> &CancelHandle {
+        &self.cancel_handle
+    }
+
+    fn get_progress_sender(&self) -> Option<&ProgressSender> {
+        self.progress_sender.as_ref()
+    }
+
+    fn get_lock_wait(&self) -> LockWait {
+        self.lock_wait
+    }
+
+    fn accepts_script_changes(&self) -> bool {
+        self.accept_script_changes
+    }
 }
 
 impl SpecificExecutionContext {
@@ -325,11 +463,93 @@ impl SpecificExecutionContext {
             runner,
             logger,
             settings,
+            extra_env: EnvVars::default(),
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
             is_installing,
             is_added_to_steam,
         }
     }
 
+    // For acting on another trick on behalf of this one (e.g. the members of a bundle), with the
+    // same runner, logger, settings and log level.
+    #[must_use]
+    pub(crate) fn for_other_trick(
+        &self,
+        trick: Trick,
+        is_installing: bool,
+        is_added_to_steam: bool,
+    ) -> Self {
+        let action = SpecificAction::as_info(&trick.id);
+        Self::new(
+            trick,
+            action,
+            self.runner.clone(),
+            self.current_log_level,
+            self.logger.clone(),
+            self.settings.clone(),
+            is_installing,
+            is_added_to_steam,
+        )
+        .with_extra_env(self.extra_env.clone())
+        .with_cancel_handle(self.cancel_handle.clone())
+        .with_progress_sender(self.progress_sender.clone())
+        .with_lock_wait(self.lock_wait)
+        .with_accept_script_changes(self.accept_script_changes)
+    }
+
+    // Passed through from e.g. `decktricks run <id> -- <args>`
+    #[must_use]
+    pub fn get_extra_run_args(&self) -> &[String] {
+        match &self.action {
+            // Launches from Steam get them from the shortcut
+            SpecificAction::Run { extra_args, .. }
+            | SpecificAction::RunNested { extra_args, .. }
+            | SpecificAction::AddToSteam { extra_args, .. } => extra_args,
+            _ => &[],
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_extra_env(&self, extra_env: EnvVars) -> Self {
+        Self {
+            extra_env,
+            ..self.clone()
+        }
+    }
+
+    // So that cancelling this action also cancels whatever is being done on its behalf
+    #[must_use]
+    fn with_cancel_handle(self, cancel_handle: CancelHandle) -> Self {
+        Self {
+            cancel_handle,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_progress_sender(self, progress_sender: Option<ProgressSender>) -> Self {
+        Self {
+            progress_sender,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_lock_wait(self, lock_wait: LockWait) -> Self {
+        Self { lock_wait, ..self }
+    }
+
+    #[must_use]
+    pub(crate) fn with_accept_script_changes(self, accept_script_changes: bool) -> Self {
+        Self {
+            accept_script_changes,
+            ..self
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn test(trick: Trick) -> Self {
         Self {
@@ -340,6 +560,11 @@ impl SpecificExecutionContext {
             action: SpecificAction::as_info(&"FAKE_FOR_TEST"),
             logger: Arc::new(DecktricksConsoleLogger::new()),
             settings: Arc::new(DecktricksConfigSettings::default()),
+            extra_env: EnvVars::default(),
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
             is_installing: false,
             is_added_to_steam: false,
         }
@@ -355,6 +580,11 @@ impl SpecificExecutionContext {
             action: SpecificAction::as_info(&"FAKE_FOR_TEST"),
             logger: Arc::new(DecktricksConsoleLogger::new()),
             settings: Arc::new(DecktricksConfigSettings::default()),
+            extra_env: EnvVars::default(),
+            cancel_handle: CancelHandle::new(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
             is_installing: false,
             is_added_to_steam: false,
         }
@@ -375,6 +605,10 @@ pub struct Executor {
     pub runner: RunnerRc,
     pub logger: LoggerRc,
     initial_log_level: LogType,
+    running_actions: RunningActions,
+    progress_sender: Option<ProgressSender>,
+    lock_wait: LockWait,
+    accept_script_changes: bool,
 }
 
 impl Executor {
@@ -449,9 +683,18 @@ impl Executor {
             runner,
             logger,
             initial_log_level,
+            running_actions: RunningActions::default(),
+            progress_sender: None,
+            lock_wait: LockWait::default(),
+            accept_script_changes: false,
         }
     }
 
+    // Progress from every action run from now on is sent here, see crate::progress
+    pub fn set_progress_sender(&mut self, progress_sender: ProgressSender) {
+        self.progress_sender = Some(progress_sender);
+    }
+
     pub fn get_new_general_execution_context(&self, log_level: LogType) -> GeneralExecutionContext {
         GeneralExecutionContext::new(
             self.runner.clone(),
@@ -459,6 +702,9 @@ impl Executor {
             self.logger.clone(),
             self.loader.get_settings(),
         )
+        .with_progress_sender(self.progress_sender.clone())
+        .with_lock_wait(self.lock_wait)
+        .with_accept_script_changes(self.accept_script_changes)
     }
 
     pub fn get_new_specific_execution_context(
@@ -479,6 +725,9 @@ impl Executor {
             is_installing,
             is_added_to_steam,
         )
+        .with_progress_sender(self.progress_sender.clone())
+        .with_lock_wait(self.lock_wait)
+        .with_accept_script_changes(self.accept_script_changes)
     }
 
     // NOTE: if the initial full system check is too slow, you can have Specific check types do the
@@ -495,7 +744,41 @@ impl Executor {
     ) -> (Option<ExecutionContext>, Vec<DeckResult<ActionSuccess>>) {
         let typed_action = TypedAction::from(&command.action);
         let current_log_level = command.log_level.unwrap_or(self.initial_log_level);
-        typed_action.do_with(self, current_log_level)
+        // Everything gathered so far is kept, only how the action is carried out changes
+        let mut executor = Cow::Borrowed(self);
+        if command.dry_run && !self.runner.is_dry_run() {
+            let dry_run_runner = if command.no_gather {
+                DryRunActualRunner::new()
+            } else {
+                DryRunActualRunner::with_real_runner(self.runner.clone())
+            };
+            executor.to_mut().runner = Arc::new(dry_run_runner);
+        }
+        if command.no_wait {
+            executor.to_mut().lock_wait = LockWait::FailFast;
+        }
+        if command.accept_changes {
+            executor.to_mut().accept_script_changes = true;
+        }
+        typed_action.do_with(&executor, current_log_level)
+    }
+
+    // Kills any commands being run for actions on this trick. Returns whether anything was running.
+    #[must_use]
+    pub fn cancel(&self, trick_id: &str) -> bool {
+        self.running_actions.cancel(trick_id) > 0
+    }
+
+    // Shared between clones of this executor, so the GUI can cancel actions running on another thread
+    #[must_use]
+    pub fn get_running_actions(&self) -> &RunningActions {
+        &self.running_actions
+    }
+
+    // See TypedAction::get_warnings. Callers are expected to check these before calling execute.
+    #[must_use]
+    pub fn get_warnings(&self, command: &DecktricksCommand) -> Vec<PendingWarning> {
+        TypedAction::from(&command.action).get_warnings(self)
     }
 
     //    pub fn reload_config(&mut self) -> DeckResult<()> {
@@ -576,6 +859,9 @@ fn gather_full_system_context(
     maybe_command: Option<&DecktricksCommand>,
 ) -> FullSystemContext {
     if let Some(command) = maybe_command {
+        if command.dry_run && command.no_gather {
+            return FullSystemContext::default();
+        }
         if matches!(mode, ExecutorMode::OnceOff) {
             let do_not_gather = command
                 .action
@@ -659,7 +945,8 @@ mod tests {
     #[test]
     fn top_level_install() -> DeckResult<()> {
         let command = DecktricksCommand::new(Action::Install {
-            id: "lutris".into(),
+            ids: vec!["lutris".into()],
+            batch: BatchArgs::default(),
         });
 
         let executor = get_executor(None)?;
@@ -675,10 +962,160 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn top_level_batch_install() -> DeckResult<()> {
+        let command = DecktricksCommand::new(Action::Install {
+            ids: vec!["lutris".into(), "FAKE_PACKAGE".into(), "protonup-qt".into()],
+            batch: BatchArgs::default(),
+        });
+
+        let executor = get_executor(None)?;
+        let (_ctx, results) = executor.execute(&command);
+        // One per trick, then the summary
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(KnownError::UnknownTrickID(_))));
+        assert!(results[2].is_ok());
+
+        let summary = results[3].as_ref().unwrap().get_message().unwrap();
+        assert!(summary.lines().any(|l| l.starts_with("lutris") && l.ends_with(" ok")));
+        assert!(summary.lines().any(|l| l.starts_with("FAKE_PACKAGE") && l.ends_with(" failed")));
+        assert!(summary.ends_with("install: 2 succeeded, 1 failed."));
+        Ok(())
+    }
+
+    #[test]
+    fn top_level_batch_uninstall_all_installed() -> DeckResult<()> {
+        let command = DecktricksCommand::new(Action::Uninstall {
+            ids: vec![],
+            batch: BatchArgs {
+                all_installed: true,
+                ..BatchArgs::default()
+            },
+        });
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .with(predicate::eq(
+                ExecutionContext::general_for_test()
+                    .sys_command("flatpak", ["list", "--app", "--columns=application"]),
+            ))
+            .returning(|_| Ok(SysCommandResult::success_output("net.lutris.Lutris")));
+        mock.expect_run()
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let executor = get_executor(Some(mock))?;
+        let (_ctx, results) = executor.execute(&command);
+        let summary = results.last().unwrap().as_ref().unwrap().get_message().unwrap();
+        assert!(summary.lines().any(|l| l.starts_with("lutris") && l.ends_with(" ok")));
+        assert!(!summary.contains("protonup-qt"));
+        Ok(())
+    }
+
+    fn get_update_all_mock(update_runs: usize) -> MockTestActualRunner {
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .with(predicate::eq(
+                ExecutionContext::general_for_test()
+                    .sys_command("flatpak", ["list", "--app", "--columns=application"]),
+            ))
+            .returning(|_| {
+                Ok(SysCommandResult::success_output(
+                    "net.lutris.Lutris\nnet.davidotek.pupgui2",
+                ))
+            });
+        mock.expect_run()
+            .with(predicate::eq(
+                ExecutionContext::general_for_test().sys_command(
+                    "flatpak",
+                    ["remote-ls", "--updates", "--app", "--columns=application"],
+                ),
+            ))
+            .returning(|_| Ok(SysCommandResult::success_output("net.lutris.Lutris")));
+        mock.expect_run()
+            .withf(|cmd| cmd.cmd == "flatpak" && cmd.args == ["update", "-y"])
+            .times(update_runs)
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+        // Updating Decky would download and run its installer for real
+        mock.expect_run()
+            .withf(|cmd| {
+                cmd.cmd == "/usr/bin/systemctl" && cmd.args == ["is-enabled", "plugin_loader"]
+            })
+            .returning(|cmd| {
+                Ok(SysCommandResult::fake_for_test(
+                    &cmd.cmd,
+                    cmd.args.iter().map(String::as_str).collect(),
+                    1,
+                    "disabled",
+                    "",
+                ))
+            });
+        mock.expect_run()
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+        mock
+    }
+
+    #[test]
+    fn top_level_update_all() -> DeckResult<()> {
+        let command = DecktricksCommand::new(Action::Update {
+            ids: vec![],
+            batch: BatchArgs::default(),
+            check: false,
+        });
+
+        // Flatpak's update is run twice, but is one step for every flatpak
+        let executor = get_executor(Some(get_update_all_mock(2)))?;
+        let (_ctx, results) = executor.execute(&command);
+        assert!(results.iter().all(Result::is_ok));
+
+        let summary = results.last().unwrap().as_ref().unwrap().get_message().unwrap();
+        let flatpak_row = |trick_id: &str| {
+            summary
+                .lines()
+                .any(|l| l.starts_with(trick_id) && l.contains(" flatpak ") && l.ends_with(" ok"))
+        };
+        assert!(flatpak_row("lutris"));
+        assert!(flatpak_row("protonup-qt"));
+        assert!(!summary.contains("moonlight"));
+        assert!(summary.ends_with(" 0 failed."));
+        Ok(())
+    }
+
+    #[test]
+    fn top_level_update_all_check() -> DeckResult<()> {
+        let command = DecktricksCommand::new(Action::Update {
+            ids: vec![],
+            batch: BatchArgs::default(),
+            check: true,
+        });
+
+        let executor = get_executor(Some(get_update_all_mock(0)))?;
+        let (_ctx, results) = executor.execute(&command);
+        let summary = results.last().unwrap().as_ref().unwrap().get_message().unwrap();
+        assert!(summary.lines().any(|l| l.starts_with("lutris") && l.ends_with(" update available")));
+        assert!(summary.lines().any(|l| l.starts_with("protonup-qt") && l.ends_with(" up to date")));
+        Ok(())
+    }
+
+    #[test]
+    fn update_all_warns_for_every_trick_it_updates() -> DeckResult<()> {
+        let executor = get_executor(Some(get_update_all_mock(0)))?;
+
+        let targets = GeneralAction::UpdateAll { check: false }.get_specific_targets(&executor);
+        assert!(targets.contains(&("lutris".into(), SpecificActionID::Update)));
+        assert!(targets.contains(&("protonup-qt".into(), SpecificActionID::Update)));
+
+        // Checking doesn't update anything
+        let targets = GeneralAction::UpdateAll { check: true }.get_specific_targets(&executor);
+        assert!(targets.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn top_level_incorrect_run() -> DeckResult<()> {
         let command = DecktricksCommand::new(Action::Run {
             id: "FAKE_PACKAGE".into(),
+            extra_args: vec![],
         });
 
         let executor = get_executor(None)?;
@@ -0,0 +1,413 @@
+use crate::prelude::*;
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// A journal of every action which changed something on this machine, one JSON object per line.
+// Actions which only look at things (info, logs, list, history, etc) aren't recorded, since the
+// GUI runs them constantly. Actions asked of unknown tricks are recorded, as failures.
+
+// Point this at a file to use it instead of the journal in the decktricks data dir
+pub const HISTORY_FILE_ENV_VAR: &str = "DECKTRICKS_HISTORY_FILE";
+const HISTORY_FILENAME: &str = "history.jsonl";
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+// Batch actions finish on several threads at once, and lines must not be interleaved
+static HISTORY_WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryOutcome {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    // None for actions which aren't on a particular trick, like updating everything
+    pub trick_id: Option<TrickID>,
+    pub action: String,
+    pub duration_ms: u64,
+    pub outcome: HistoryOutcome,
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    #[must_use]
+    pub fn new<'a>(
+        trick_id: Option<&str>,
+        action: &str,
+        started: Instant,
+        results: impl IntoIterator<Item = &'a DeckResult<ActionSuccess>>,
+    ) -> Self {
+        let errors: Vec<String> = results
+            .into_iter()
+            .filter_map(|res| res.as_ref().err().map(ToString::to_string))
+            .collect();
+        Self {
+            timestamp: Utc::now(),
+            trick_id: trick_id.map(Into::into),
+            action: action.into(),
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            outcome: if errors.is_empty() {
+                HistoryOutcome::Success
+            } else {
+                HistoryOutcome::Failure
+            },
+            error: (!errors.is_empty()).then(|| errors.join("\n")),
+        }
+    }
+
+    #[must_use]
+    pub fn is_failure(&self) -> bool {
+        self.outcome == HistoryOutcome::Failure
+    }
+
+    fn get_display_line(&self) -> String {
+        let local_time: DateTime<Local> = self.timestamp.into();
+        let outcome = if self.is_failure() { "failed" } else { "ok" };
+        #[allow(clippy::cast_precision_loss)]
+        let seconds = self.duration_ms as f64 / 1000.0;
+        let mut line = format!(
+            "{}  {:<18} {:<24} {outcome:<7} {seconds:.1}s",
+            local_time.format("%Y-%m-%d %H:%M:%S"),
+            self.action,
+            self.trick_id.as_deref().unwrap_or("-"),
+        );
+        // Only the first line, the rest is in --json
+        if let Some(first_line) = self.error.as_ref().and_then(|err| err.lines().next()) {
+            line.push_str("  ");
+            line.push_str(first_line);
+        }
+        line
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct HistoryFilter {
+    // Only show actions on this trick
+    #[clap(long)]
+    pub trick: Option<TrickID>,
+
+    // Only show this action, e.g. "install"
+    #[clap(long)]
+    pub action: Option<String>,
+
+    // Only show failed actions
+    #[clap(long)]
+    pub failed: bool,
+
+    // Only show actions from the last N minutes/hours/days/weeks, e.g. "2d"
+    #[clap(long, value_parser = parse_since)]
+    pub since: Option<TimeDelta>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry, now: DateTime<Utc>) -> bool {
+        self.trick
+            .as_ref()
+            .is_none_or(|trick| entry.trick_id.as_ref() == Some(trick))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| &entry.action == action)
+            && (!self.failed || entry.is_failure())
+            && self
+                .since
+                .is_none_or(|since| entry.timestamp >= now - since)
+    }
+}
+
+fn parse_since(since: &str) -> Result<TimeDelta, String> {
+    let invalid =
+        || format!("\"{since}\" should be a number followed by m, h, d, or w, e.g. \"2d\"");
+    let (unit_index, unit) = since.char_indices().last().ok_or_else(invalid)?;
+    let amount: i64 = since[..unit_index].parse().map_err(|_| invalid())?;
+    match unit {
+        'm' => TimeDelta::try_minutes(amount),
+        'h' => TimeDelta::try_hours(amount),
+        'd' => TimeDelta::try_days(amount),
+        'w' => TimeDelta::try_weeks(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    // Oldest first
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// # Errors
+    ///
+    /// Returns errors relating to reading the journal. A missing journal is just an empty history.
+    pub fn load() -> DeckResult<Self> {
+        let path = get_history_file();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(KnownError::HistoryReadError(format!(
+                "Could not read \"{}\": {err}",
+                path.display()
+            ))),
+        }
+    }
+
+    // A crash mid-write can leave a partial last line, which shouldn't hide everything else
+    fn parse(contents: &str) -> Self {
+        Self {
+            entries: contents
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn get_entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    // The most recent `limit` matching entries, oldest first
+    #[must_use]
+    pub fn get_filtered(&self, filter: &HistoryFilter, limit: usize) -> Vec<&HistoryEntry> {
+        let now = Utc::now();
+        let mut matching: Vec<&HistoryEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry, now))
+            .take(limit)
+            .collect();
+        matching.reverse();
+        matching
+    }
+
+    // Tricks which were successfully acted on, most recent first and without repeats
+    #[must_use]
+    pub fn get_recently_used(&self, limit: usize) -> Vec<TrickID> {
+        let mut recently_used: Vec<TrickID> = vec![];
+        for entry in self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| !entry.is_failure())
+        {
+            if let Some(trick_id) = &entry.trick_id
+                && !recently_used.contains(trick_id)
+            {
+                recently_used.push(trick_id.clone());
+            }
+            if recently_used.len() >= limit {
+                break;
+            }
+        }
+        recently_used
+    }
+
+    #[must_use]
+    pub fn get_last_failed(&self) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|entry| entry.is_failure())
+    }
+}
+
+#[must_use]
+pub fn get_history_file() -> PathBuf {
+    std::env::var(HISTORY_FILE_ENV_VAR).map_or_else(|_| get_default_history_file(), PathBuf::from)
+}
+
+#[cfg(not(test))]
+fn get_default_history_file() -> PathBuf {
+    crate::utils::get_decktricks_dir().join(HISTORY_FILENAME)
+}
+
+// Unit tests record history for real, just never in the developer's own journal
+#[cfg(test)]
+fn get_default_history_file() -> PathBuf {
+    static TEST_HISTORY_DIR: std::sync::LazyLock<tempfile::TempDir> =
+        std::sync::LazyLock::new(|| tempfile::tempdir().expect("Could not create a temp dir"));
+    TEST_HISTORY_DIR.path().join(HISTORY_FILENAME)
+}
+
+// Failing to write history never fails the action itself, it's only warned about
+pub(crate) fn record_in_history(ctx: &impl ExecCtx, entry: &HistoryEntry) {
+    // Nothing actually happened
+    if ctx.get_runner().is_dry_run() {
+        return;
+    }
+    if let Err(err) = append_to_file(&get_history_file(), entry) {
+        warn!(
+            ctx,
+            "Could not record \"{}\" in history: {err}", entry.action
+        );
+    }
+}
+
+fn append_to_file(path: &std::path::Path, entry: &HistoryEntry) -> DeckResult<()> {
+    let history_err = |e: &dyn std::fmt::Display| {
+        KnownError::HistoryWriteError(format!("Could not write to \"{}\": {e}", path.display()))
+    };
+    let mut line = serde_json::to_string(entry).map_err(|e| history_err(&e))?;
+    line.push('\n');
+
+    let _guard = HISTORY_WRITE_LOCK.lock();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| history_err(&e))?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| history_err(&e))
+}
+
+/// # Errors
+///
+/// Returns errors relating to reading the journal, or serializing it for `--json`.
+pub(crate) fn show_history(
+    filter: &HistoryFilter,
+    limit: usize,
+    json: bool,
+) -> DeckResult<ActionSuccess> {
+    let history = History::load()?;
+    let entries = history.get_filtered(filter, limit);
+
+    if json {
+        return success!(serde_json::to_string(&entries).map_err(KnownError::from)?);
+    }
+    if entries.is_empty() {
+        return success!("No matching actions in history.");
+    }
+    success!(
+        entries
+            .iter()
+            .map(|entry| entry.get_display_line())
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(trick_id: Option<&str>, action: &str, error: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: Utc::now(),
+            trick_id: trick_id.map(Into::into),
+            action: action.into(),
+            duration_ms: 1500,
+            outcome: if error.is_some() {
+                HistoryOutcome::Failure
+            } else {
+                HistoryOutcome::Success
+            },
+            error: error.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn appends_and_reads_back() -> DeckResult<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(HISTORY_FILENAME);
+        let first = entry(Some("lutris"), "install", None);
+        let second = entry(None, "update-all", Some("flatpak is broken\nmore detail"));
+        append_to_file(&path, &first)?;
+        append_to_file(&path, &second)?;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        // A partial line from a crash is skipped
+        let history = History::parse(&format!("{contents}{{\"timestamp\":"));
+        assert_eq!(vec![first, second], history.get_entries());
+        Ok(())
+    }
+
+    #[test]
+    fn recorded_entries_are_written() -> DeckResult<()> {
+        let recorded = entry(Some("recorded-for-test"), "install", None);
+        record_in_history(&GeneralExecutionContext::test(), &recorded);
+
+        let dry_run = entry(Some("dry-run-for-test"), "install", None);
+        let dry_run_ctx = GeneralExecutionContext::test_with_runner(std::sync::Arc::new(
+            crate::system_command_runners::DryRunActualRunner::new(),
+        ));
+        record_in_history(&dry_run_ctx, &dry_run);
+
+        // Other tests record in the same file, so only these entries are looked for
+        let history = History::load()?;
+        assert!(history.get_entries().contains(&recorded));
+        assert!(!history.get_entries().contains(&dry_run));
+        Ok(())
+    }
+
+    #[test]
+    fn filters_and_limits() {
+        let mut old = entry(Some("lutris"), "install", Some("no network"));
+        old.timestamp -= TimeDelta::days(3);
+        let history = History {
+            entries: vec![
+                old,
+                entry(Some("lutris"), "install", None),
+                entry(Some("protonup-qt"), "install", None),
+                entry(Some("lutris"), "run", None),
+            ],
+        };
+
+        let by_trick = HistoryFilter {
+            trick: Some("lutris".into()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(3, history.get_filtered(&by_trick, 10).len());
+        let limited = history.get_filtered(&by_trick, 2);
+        assert_eq!("install", limited[0].action);
+        assert_eq!("run", limited[1].action);
+
+        let recent_failures = HistoryFilter {
+            failed: true,
+            since: Some(TimeDelta::days(1)),
+            ..HistoryFilter::default()
+        };
+        assert!(history.get_filtered(&recent_failures, 10).is_empty());
+
+        assert_eq!(
+            vec!["lutris".to_string(), "protonup-qt".to_string()],
+            history.get_recently_used(5)
+        );
+        assert_eq!(
+            Some("no network"),
+            history.get_last_failed().and_then(|e| e.error.as_deref())
+        );
+    }
+
+    #[test]
+    fn parses_since() {
+        assert_eq!(Ok(TimeDelta::hours(12)), parse_since("12h"));
+        assert_eq!(Ok(TimeDelta::weeks(1)), parse_since("1w"));
+        assert!(parse_since("").is_err());
+        assert!(parse_since("d").is_err());
+        assert!(parse_since("2y").is_err());
+        // Units are characters, not bytes
+        assert!(parse_since("2é").is_err());
+        assert!(parse_since("é").is_err());
+    }
+
+    #[test]
+    fn entry_outcome_comes_from_results() {
+        let results = vec![
+            success!("fine"),
+            Err(KnownError::TestError("broken".into())),
+        ];
+        let entry = HistoryEntry::new(None, "update-all", Instant::now(), &results);
+        assert!(entry.is_failure());
+        assert!(entry.error.as_ref().unwrap().contains("broken"));
+        assert!(entry.get_display_line().contains("update-all"));
+    }
+}
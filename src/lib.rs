@@ -8,6 +8,7 @@ decktricks_logging_init!(LogType::Warn);
 pub mod actions;
 pub mod add_to_steam;
 pub mod controller_layout;
+pub mod download;
 pub mod errors;
 //#[allow(refining_impl_trait)]
 pub mod prelude;
@@ -17,6 +18,13 @@ pub mod macros;
 pub mod command;
 pub mod executor;
 pub mod gui;
+pub mod history;
+pub mod locks;
+pub mod nested_desktop;
+pub mod network;
+pub mod profile;
+pub mod progress;
+pub mod script_review;
 #[macro_use]
 pub mod logging;
 pub mod system_command_runners;
@@ -0,0 +1,536 @@
+use crate::prelude::*;
+use crate::utils::get_decktricks_dir;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Advisory locks, so that separate decktricks processes (say the GUI, and the CLI over SSH) and
+// separate actions within one process don't run conflicting operations at the same time.
+//
+// A held lock is a file in the lock directory, saying which process holds it and what for. Lock
+// files left behind by a process which is gone are stale, and are removed by the next taker.
+//
+// The global lock is for actions which touch everything (update-all, profile apply). While
+// another process holds it nothing else can be locked, and it can't be taken while another
+// process holds any lock at all. Within a process it only keeps global actions apart, since
+// those take provider and resource locks of their own as they go.
+
+pub const LOCK_DIR_ENV_VAR: &str = "DECKTRICKS_LOCK_DIR";
+const LOCK_DIR_NAME: &str = "locks";
+const LOCK_FILE_EXTENSION: &str = "lock";
+
+pub const FLATPAK_PROVIDER_LOCK: &str = "flatpak";
+pub const STEAM_SHORTCUTS_RESOURCE_LOCK: &str = "steam-shortcuts";
+pub const DOWNLOAD_CACHE_RESOURCE_LOCK: &str = "download-cache";
+
+// How often a held lock is checked on while waiting for it
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// How often we say that we're still waiting
+const WAITING_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+// Locks held by actions in this process, by lock file. The files alone can't tell those apart.
+static HELD_IN_PROCESS: LazyLock<(Mutex<HashSet<PathBuf>>, Condvar)> =
+    LazyLock::new(|| (Mutex::new(HashSet::new()), Condvar::new()));
+
+// Makes the temporary files used while taking a lock unique within this process
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// What to do when another decktricks process holds a lock we need. Within one process, actions
+// always wait for each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockWait {
+    #[default]
+    Wait,
+    FailFast,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockScope {
+    Global,
+    Provider(&'static str),
+    Resource(&'static str),
+}
+
+impl LockScope {
+    fn get_filename(&self) -> String {
+        match self {
+            Self::Global => format!("global.{LOCK_FILE_EXTENSION}"),
+            Self::Provider(name) => format!("provider-{name}.{LOCK_FILE_EXTENSION}"),
+            Self::Resource(name) => format!("resource-{name}.{LOCK_FILE_EXTENSION}"),
+        }
+    }
+}
+
+impl Display for LockScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Global => write!(f, "the global lock"),
+            Self::Provider(name) | Self::Resource(name) => write!(f, "the {name} lock"),
+        }
+    }
+}
+
+// The contents of a lock file
+#[derive(Debug, Serialize, Deserialize)]
+struct LockHolder {
+    pid: u32,
+    description: String,
+    started: DateTime<Utc>,
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "decktricks (pid {}, {} since {})",
+            self.pid,
+            self.description,
+            self.started.with_timezone(&Local).format("%H:%M:%S")
+        )
+    }
+}
+
+#[must_use = "the lock is released as soon as this is dropped"]
+pub(crate) struct LockGuard {
+    // None when nothing was actually locked (dry runs and tests)
+    path: Option<PathBuf>,
+    file_created: bool,
+}
+
+impl LockGuard {
+    fn nothing_locked() -> Self {
+        Self {
+            path: None,
+            file_created: false,
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if self.file_created {
+            let _ = std::fs::remove_file(path);
+        }
+        let (held, condvar) = &*HELD_IN_PROCESS;
+        if let Ok(mut held) = held.lock() {
+            held.remove(path);
+        }
+        condvar.notify_all();
+    }
+}
+
+#[must_use]
+pub fn get_lock_dir() -> PathBuf {
+    std::env::var(LOCK_DIR_ENV_VAR)
+        .map_or_else(|_| get_decktricks_dir().join(LOCK_DIR_NAME), PathBuf::from)
+}
+
+// `description` is what we're doing, for anyone left waiting on us: "installing lutris"
+#[cfg(not(test))]
+pub(crate) fn acquire_lock(
+    ctx: &impl ExecCtx,
+    scope: &LockScope,
+    description: &str,
+) -> DeckResult<LockGuard> {
+    // Nothing is going to be changed, so there's nothing to protect
+    if ctx.get_runner().is_dry_run() {
+        return Ok(LockGuard::nothing_locked());
+    }
+    acquire_lock_in(&get_lock_dir(), ctx, scope, description)
+}
+
+#[cfg(test)]
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) fn acquire_lock(
+    _ctx: &impl ExecCtx,
+    _scope: &LockScope,
+    _description: &str,
+) -> DeckResult<LockGuard> {
+    Ok(LockGuard::nothing_locked())
+}
+
+fn acquire_lock_in(
+    lock_dir: &Path,
+    ctx: &impl ExecCtx,
+    scope: &LockScope,
+    description: &str,
+) -> DeckResult<LockGuard> {
+    std::fs::create_dir_all(lock_dir).map_err(|e| {
+        KnownError::LockError(format!(
+            "Could not create lock directory \"{}\": {e}",
+            lock_dir.display()
+        ))
+    })?;
+    let path = lock_dir.join(scope.get_filename());
+
+    // From here on, dropping the guard gives up our place in this process
+    let mut guard = take_in_process(ctx, &path, scope)?;
+
+    let mut last_waiting_log: Option<Instant> = None;
+    loop {
+        let Some(holder) = try_create_lock_file(lock_dir, &path, scope, description)? else {
+            guard.file_created = true;
+            if last_waiting_log.is_some() {
+                log!(ctx, "Got {scope}, continuing.");
+            }
+            return Ok(guard);
+        };
+
+        if ctx.get_lock_wait() == LockWait::FailFast {
+            return Err(KnownError::LockHeld(format!(
+                "Could not get {scope}, since it is held by {holder}. Try again once that's \
+                 finished, or leave out --no-wait to wait for it."
+            )));
+        }
+        if ctx.get_cancel_handle().is_cancelled() {
+            return Err(KnownError::LockError(format!(
+                "Cancelled while waiting for {holder}."
+            )));
+        }
+        if last_waiting_log.is_none_or(|logged| logged.elapsed() >= WAITING_LOG_INTERVAL) {
+            warn!(
+                ctx,
+                "Waiting for {holder} to finish before {description}..."
+            );
+            last_waiting_log = Some(Instant::now());
+        }
+        std::thread::sleep(LOCK_POLL_INTERVAL);
+    }
+}
+
+fn take_in_process(ctx: &impl ExecCtx, path: &Path, scope: &LockScope) -> DeckResult<LockGuard> {
+    let poisoned = |_| KnownError::LockError("Lock registry was poisoned.".into());
+    let (held, condvar) = &*HELD_IN_PROCESS;
+    let mut held: MutexGuard<HashSet<PathBuf>> = held.lock().map_err(poisoned)?;
+
+    let mut logged = false;
+    while held.contains(path) {
+        if ctx.get_cancel_handle().is_cancelled() {
+            return Err(KnownError::LockError(format!(
+                "Cancelled while waiting for {scope}."
+            )));
+        }
+        if !logged {
+            log!(ctx, "Waiting for another action to release {scope}...");
+            logged = true;
+        }
+        held = condvar
+            .wait_timeout(held, LOCK_POLL_INTERVAL)
+            .map_err(|_| KnownError::LockError("Lock registry was poisoned.".into()))?
+            .0;
+    }
+
+    held.insert(path.to_path_buf());
+    Ok(LockGuard {
+        path: Some(path.to_path_buf()),
+        file_created: false,
+    })
+}
+
+// Returns whoever is in the way, if anyone
+fn try_create_lock_file(
+    lock_dir: &Path,
+    path: &Path,
+    scope: &LockScope,
+    description: &str,
+) -> DeckResult<Option<LockHolder>> {
+    if let Some(holder) = find_blocking_holder(lock_dir, scope)? {
+        return Ok(Some(holder));
+    }
+
+    let lock_err = |e: std::io::Error| {
+        KnownError::LockError(format!("Could not write \"{}\": {e}", path.display()))
+    };
+    let holder = LockHolder {
+        pid: std::process::id(),
+        description: description.into(),
+        started: Utc::now(),
+    };
+    let contents = serde_json::to_string(&holder)
+        .map_err(|e| KnownError::LockError(format!("Could not serialize lock holder: {e}")))?;
+
+    // The file is written in full elsewhere and then linked into place, which fails if someone
+    // else got there first. That way nobody ever reads a half-written lock file.
+    let temp_path = lock_dir.join(format!(
+        ".{}.{}.{}.tmp",
+        scope.get_filename(),
+        holder.pid,
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temp_path, contents).map_err(lock_err)?;
+    let link_res = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+
+    match link_res {
+        Ok(()) => {
+            // Someone taking the global lock and someone taking any other lock can both get past
+            // the check above at the same time, so whoever got theirs second has to see the
+            // other's now. If both do, both back off and try again.
+            let Some(holder) = find_blocking_holder(lock_dir, scope)? else {
+                return Ok(None);
+            };
+            std::fs::remove_file(path).map_err(|e| {
+                KnownError::LockError(format!("Could not remove \"{}\": {e}", path.display()))
+            })?;
+            Ok(Some(holder))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            if let Some(holder) = read_live_holder(path)? {
+                return Ok(Some(holder));
+            }
+            remove_stale_lock_file(path)?;
+            try_create_lock_file(lock_dir, path, scope, description)
+        }
+        Err(err) => Err(lock_err(err)),
+    }
+}
+
+// Another process's global lock keeps everyone else out, and taking the global lock means
+// waiting for other processes to be done with everything.
+fn find_blocking_holder(lock_dir: &Path, scope: &LockScope) -> DeckResult<Option<LockHolder>> {
+    if *scope != LockScope::Global {
+        return read_live_holder(&lock_dir.join(LockScope::Global.get_filename()));
+    }
+
+    let entries = std::fs::read_dir(lock_dir).map_err(|e| {
+        KnownError::LockError(format!("Could not read \"{}\": {e}", lock_dir.display()))
+    })?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext == LOCK_FILE_EXTENSION)
+            && let Some(holder) = read_live_holder(&path)?
+        {
+            return Ok(Some(holder));
+        }
+    }
+    Ok(None)
+}
+
+// None if there's no lock file, or if the process which wrote it is us or is gone
+fn read_live_holder(path: &Path) -> DeckResult<Option<LockHolder>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(KnownError::LockError(format!(
+                "Could not read \"{}\": {err}",
+                path.display()
+            )));
+        }
+    };
+    // Lock files are only ever linked into place fully written, so this one is just broken
+    let Ok(holder) = serde_json::from_str::<LockHolder>(&contents) else {
+        return Ok(None);
+    };
+
+    // Our own locks are kept apart by HELD_IN_PROCESS, so a lock file with our pid which we
+    // aren't holding there was left behind somehow
+    let is_live = holder.pid != std::process::id() && is_decktricks_process(holder.pid);
+    Ok(is_live.then_some(holder))
+}
+
+// Pids are reused, so after a crash the pid in a lock file may belong to something else entirely.
+// Only the program itself is looked at, since plenty of other things (editors, grep) could have
+// "decktricks" somewhere in their arguments.
+fn is_decktricks_process(pid: u32) -> bool {
+    let is_decktricks = |program: &Path| {
+        program.file_name().is_some_and(|name| {
+            name.to_string_lossy()
+                .to_lowercase()
+                .starts_with("decktricks")
+        })
+    };
+
+    // Can't be read for other users' processes
+    let exe = std::fs::read_link(format!("/proc/{pid}/exe"));
+    if exe.as_deref().is_ok_and(is_decktricks) {
+        return true;
+    }
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+    let argv0 = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+    is_decktricks(Path::new(&*String::from_utf8_lossy(argv0)))
+}
+
+fn remove_stale_lock_file(path: &Path) -> DeckResult<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        // Someone else cleaned it up first
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(KnownError::LockError(format!(
+            "Could not remove stale lock \"{}\": {err}",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+
+    // Looks like another decktricks process to the lock code, once it has started
+    fn spawn_fake_holder() -> Child {
+        let child = Command::new("bash")
+            .args(["-c", "exec -a decktricks-fake-holder sleep 30"])
+            .spawn()
+            .unwrap();
+        let cmdline_path = format!("/proc/{}/cmdline", child.id());
+        while !std::fs::read_to_string(&cmdline_path)
+            .unwrap_or_default()
+            .starts_with("decktricks-fake-holder")
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        child
+    }
+
+    fn write_lock_file(lock_dir: &Path, scope: &LockScope, pid: u32) {
+        let holder = LockHolder {
+            pid,
+            description: "testing".into(),
+            started: Utc::now(),
+        };
+        std::fs::write(
+            lock_dir.join(scope.get_filename()),
+            serde_json::to_string(&holder).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn fail_fast_ctx() -> GeneralExecutionContext {
+        GeneralExecutionContext::test().with_lock_wait(LockWait::FailFast)
+    }
+
+    #[test]
+    fn lock_file_lives_as_long_as_guard() {
+        let lock_dir = tempfile::tempdir().unwrap();
+        let scope = LockScope::Provider("test");
+        let lock_path = lock_dir.path().join(scope.get_filename());
+
+        let guard = acquire_lock_in(
+            lock_dir.path(),
+            &GeneralExecutionContext::test(),
+            &scope,
+            "testing",
+        )
+        .unwrap();
+        assert!(lock_path.exists());
+        drop(guard);
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn stale_locks_are_taken_over() {
+        let lock_dir = tempfile::tempdir().unwrap();
+        let scope = LockScope::Resource("test");
+
+        // A process which has already exited
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        write_lock_file(lock_dir.path(), &scope, dead_pid);
+
+        let _guard = acquire_lock_in(lock_dir.path(), &fail_fast_ctx(), &scope, "testing").unwrap();
+        let contents = std::fs::read_to_string(lock_dir.path().join(scope.get_filename())).unwrap();
+        assert!(contents.contains(&format!("\"pid\":{}", std::process::id())));
+    }
+
+    #[test]
+    fn only_decktricks_programs_hold_locks() {
+        let mut holder = spawn_fake_holder();
+        assert!(is_decktricks_process(holder.id()));
+
+        // Like an editor with a decktricks file open
+        let mut other = Command::new("bash")
+            .args(["-c", "sleep 30; : decktricks.json"])
+            .spawn()
+            .unwrap();
+        assert!(!is_decktricks_process(other.id()));
+
+        for child in [&mut holder, &mut other] {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+    }
+
+    #[test]
+    fn live_holders_fail_fast() {
+        let lock_dir = tempfile::tempdir().unwrap();
+        let mut holder = spawn_fake_holder();
+        write_lock_file(lock_dir.path(), &LockScope::Global, holder.id());
+
+        // Another process's global lock keeps out everything else
+        let res = acquire_lock_in(
+            lock_dir.path(),
+            &fail_fast_ctx(),
+            &LockScope::Provider("test"),
+            "testing",
+        );
+        holder.kill().unwrap();
+        holder.wait().unwrap();
+
+        let err = res.err().unwrap().to_string();
+        assert!(err.contains("Could not get the test lock"));
+        assert!(err.contains(&format!("pid {}, testing since", holder.id())));
+    }
+
+    #[test]
+    fn global_lock_waits_for_other_processes() {
+        let lock_dir = tempfile::tempdir().unwrap();
+        let mut holder = spawn_fake_holder();
+        write_lock_file(lock_dir.path(), &LockScope::Resource("test"), holder.id());
+
+        let res = acquire_lock_in(
+            lock_dir.path(),
+            &fail_fast_ctx(),
+            &LockScope::Global,
+            "testing",
+        );
+        assert!(matches!(res, Err(KnownError::LockHeld(_))));
+
+        holder.kill().unwrap();
+        holder.wait().unwrap();
+        assert!(
+            acquire_lock_in(
+                lock_dir.path(),
+                &fail_fast_ctx(),
+                &LockScope::Global,
+                "testing"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn actions_in_one_process_wait_for_each_other() {
+        let lock_dir = tempfile::tempdir().unwrap();
+        let scope = LockScope::Provider("test");
+        // Even when failing fast, since that's only about other processes
+        let ctx = fail_fast_ctx();
+
+        let guard = acquire_lock_in(lock_dir.path(), &ctx, &scope, "testing").unwrap();
+        let waiter = {
+            let lock_dir = lock_dir.path().to_path_buf();
+            let (ctx, scope) = (ctx.clone(), scope.clone());
+            std::thread::spawn(move || {
+                let _guard = acquire_lock_in(&lock_dir, &ctx, &scope, "testing").unwrap();
+                Instant::now()
+            })
+        };
+
+        std::thread::sleep(Duration::from_millis(100));
+        let released = Instant::now();
+        drop(guard);
+        assert!(waiter.join().unwrap() >= released);
+    }
+}
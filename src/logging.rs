@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::progress::print_over_cli_progress_bar;
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -117,24 +118,24 @@ impl DecktricksLogger for DecktricksConsoleLogger {
     }
 
     fn actual_print(&self, text: String) {
-        println!("{text}");
+        print_over_cli_progress_bar(|| println!("{text}"));
     }
 
     fn actual_print_debug(&self, text: String) {
-        eprintln!("{text}");
+        print_over_cli_progress_bar(|| eprintln!("{text}"));
     }
 
     fn actual_print_error(&self, text: String) {
-        eprintln!("{text}");
+        print_over_cli_progress_bar(|| eprintln!("{text}"));
     }
 
     fn actual_print_info(&self, text: String) {
-        eprintln!("{text}");
+        print_over_cli_progress_bar(|| eprintln!("{text}"));
     }
 
     fn actual_print_warn(&self, text: String) {
         // NOTE: as noted above, eprintln_for_gui_logs assumes that actual_print_warn uses eprintln/stderr
-        eprintln!("{text}");
+        print_over_cli_progress_bar(|| eprintln!("{text}"));
     }
 
     fn store(&self, _ctx: ExecutionContext, _log_type: LogType, _text: String) {}
@@ -1,5 +1,9 @@
 use decktricks::decktricks_logging_init;
 use decktricks::prelude::*;
+use decktricks::progress::{clear_cli_progress_bar, draw_cli_progress_bar};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
 
 decktricks_logging_init!(LogType::Warn);
 
@@ -8,13 +12,34 @@ fn main() -> DeckResult<()> {
 
     // Use the log level cmdline flag, the log level env var, or the default, in that order:
     let log_level = cmd.log_level.unwrap_or(get_log_level());
-    let executor = Executor::create_with_gather(
+    let mut executor = Executor::create_with_gather(
         ExecutorMode::OnceOff,
         log_level,
         CRATE_DECKTRICKS_LOGGER.clone(),
         Some(&cmd),
     );
-    let (_ctx, results) = executor.execute(&cmd);
+
+    let warnings = executor.get_warnings(&cmd);
+    if !warnings.is_empty() && !confirm_warnings(&warnings, cmd.yes || cmd.dry_run) {
+        std::process::exit(1);
+    }
+
+    exit_or_cancel_on_ctrl_c(&executor);
+
+    // Progress bars would only garble output that isn't going to a terminal
+    let progress_bar = std::io::stderr().is_terminal().then(|| {
+        let (sender, receiver) = mpsc::channel();
+        executor.set_progress_sender(sender);
+        std::thread::spawn(move || draw_progress_bar(&receiver))
+    });
+
+    let (ctx, results) = executor.execute(&cmd);
+
+    // Once every sender is gone the bar is cleared, so that it doesn't end up mixed into results
+    drop((ctx, executor));
+    if let Some(progress_bar) = progress_bar {
+        let _ = progress_bar.join();
+    }
 
     let mut experienced_error = false;
     results.iter().for_each(|res| match res {
@@ -33,3 +58,47 @@ fn main() -> DeckResult<()> {
         Ok(())
     }
 }
+
+// Commands are run in their own process groups, so Ctrl-C from the terminal doesn't reach them.
+// Instead, the first Ctrl-C cancels whatever is running (which kills those groups) and lets us
+// report it as usual. If nothing is running, or on a second Ctrl-C, we just exit.
+fn exit_or_cancel_on_ctrl_c(executor: &Executor) {
+    let running_actions = executor.get_running_actions().clone();
+    let already_interrupted = AtomicBool::new(false);
+    let res = ctrlc::set_handler(move || {
+        if already_interrupted.swap(true, Ordering::SeqCst) || running_actions.cancel_all() == 0 {
+            std::process::exit(130);
+        }
+    });
+    if let Err(err) = res {
+        eprintln!("Failed to set up Ctrl-C handling: {err}");
+    }
+}
+
+fn draw_progress_bar(receiver: &Receiver<ProgressEvent>) {
+    for event in receiver {
+        draw_cli_progress_bar(&event);
+    }
+    clear_cli_progress_bar();
+}
+
+// Warnings from the config are always shown, and need a "y" (or --yes) before anything happens.
+// Dry runs don't change anything, so they don't need confirming.
+fn confirm_warnings(warnings: &[PendingWarning], skip_prompt: bool) -> bool {
+    for warning in warnings {
+        eprintln!("{warning}");
+    }
+    if skip_prompt {
+        return true;
+    }
+
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        eprintln!("Not continuing without confirmation. Pass --yes to go ahead anyway.");
+        return false;
+    }
+    eprint!("Continue? [y/N] ");
+    let mut answer = String::new();
+    stdin.read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
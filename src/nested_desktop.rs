@@ -0,0 +1,248 @@
+use crate::prelude::*;
+use crate::utils::which;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+// Game mode has no window manager to speak of, so apps which want a mouse or real windows (Spotify,
+// Plasma settings, etc) are much happier inside the nested Plasma session SteamOS can start.
+
+const NESTED_DESKTOP_COMMAND: &str = "steamos-nested-desktop";
+const NESTED_PLASMA_DIRNAME: &str = "nested_plasma";
+
+// kwin is the first thing the nested session starts, but anything running inside the session
+// has the same DISPLAY/XAUTHORITY.
+const PREFERRED_PROCESS_NAMES: &[&str] = &["kwin_wayland", "kwin_x11"];
+
+const NESTED_SESSION_START_TIMEOUT: Duration = Duration::from_secs(30);
+const NESTED_SESSION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Only SteamOS ships the nested desktop, and it won't appear while we're running, so this is only
+// checked once.
+static NESTED_DESKTOP_IS_AVAILABLE: LazyLock<bool> =
+    LazyLock::new(|| which(NESTED_DESKTOP_COMMAND).is_ok());
+
+#[must_use]
+pub fn is_nested_desktop_available() -> bool {
+    *NESTED_DESKTOP_IS_AVAILABLE
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NestedSession {
+    pub display: String,
+    pub xauthority: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProcessEnvironment {
+    pub(crate) name: String,
+    pub(crate) env: EnvVars,
+}
+
+impl NestedSession {
+    #[must_use]
+    pub fn get_env_vars(&self) -> EnvVars {
+        EnvVars::from([
+            ("DISPLAY".into(), self.display.clone()),
+            ("XAUTHORITY".into(), self.xauthority.clone()),
+        ])
+    }
+
+    #[must_use]
+    pub fn find() -> Option<Self> {
+        find_in_processes(&get_nested_plasma_dir(), &read_process_environments())
+    }
+
+    /// # Errors
+    ///
+    /// Returns errors if no nested session is running and one could not be started.
+    pub fn find_or_start(ctx: &impl ExecCtx) -> DeckResult<Self> {
+        if let Some(session) = Self::find() {
+            return Ok(session);
+        }
+
+        info!(ctx, "No nested desktop session found, starting one...");
+        let nested_desktop_path = which(NESTED_DESKTOP_COMMAND).map_err(|_| {
+            KnownError::NestedDesktopError(format!(
+                "\"{NESTED_DESKTOP_COMMAND}\" was not found in $PATH. Is this a Steam Deck?"
+            ))
+        })?;
+
+        // The session needs to outlive us, and its output would otherwise hold our pipes open
+        // until it exits.
+        ctx.sys_command(
+            "sh",
+            [
+                "-c",
+                "setsid -f \"$0\" >/dev/null 2>&1 </dev/null",
+                &nested_desktop_path,
+            ],
+        )
+        .run()?
+        .as_success()?;
+
+        // Nothing was started, so there's nothing to wait for
+        if ctx.get_runner().is_dry_run() {
+            return Ok(Self {
+                display: "<nested desktop DISPLAY>".into(),
+                xauthority: "<nested desktop XAUTHORITY>".into(),
+            });
+        }
+
+        let started = Instant::now();
+        while started.elapsed() < NESTED_SESSION_START_TIMEOUT {
+            std::thread::sleep(NESTED_SESSION_POLL_INTERVAL);
+            if let Some(session) = Self::find() {
+                return Ok(session);
+            }
+        }
+
+        Err(KnownError::NestedDesktopError(format!(
+            "Timed out after {}s waiting for the nested desktop session to start.",
+            NESTED_SESSION_START_TIMEOUT.as_secs()
+        )))
+    }
+}
+
+fn get_nested_plasma_dir() -> PathBuf {
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
+        let uid = std::fs::metadata("/proc/self")
+            .map_or(1000, |meta| std::os::unix::fs::MetadataExt::uid(&meta));
+        format!("/run/user/{uid}")
+    });
+    Path::new(&runtime_dir).join(NESTED_PLASMA_DIRNAME)
+}
+
+// Processes belonging to other users can't be read, which is fine since the nested session
+// always runs as us.
+fn read_process_environments() -> Vec<ProcessEnvironment> {
+    let Ok(proc_entries) = std::fs::read_dir("/proc") else {
+        return vec![];
+    };
+
+    proc_entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter_map(|entry| {
+            let name = std::fs::read_to_string(entry.path().join("comm")).ok()?;
+            let environ = std::fs::read(entry.path().join("environ")).ok()?;
+            Some(ProcessEnvironment {
+                name: name.trim().into(),
+                env: parse_environ(&environ),
+            })
+        })
+        .collect()
+}
+
+// /proc/<pid>/environ is a list of NUL-separated KEY=value pairs
+fn parse_environ(environ: &[u8]) -> EnvVars {
+    String::from_utf8_lossy(environ)
+        .split('\0')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
+
+pub(crate) fn find_in_processes(
+    nested_plasma_dir: &Path,
+    processes: &[ProcessEnvironment],
+) -> Option<NestedSession> {
+    let session_for = |proc_env: &ProcessEnvironment| {
+        let xauthority = proc_env.env.get("XAUTHORITY")?;
+        let display = proc_env.env.get("DISPLAY")?;
+        Path::new(xauthority)
+            .starts_with(nested_plasma_dir)
+            .then(|| NestedSession {
+                display: display.clone(),
+                xauthority: xauthority.clone(),
+            })
+    };
+
+    processes
+        .iter()
+        .filter(|proc_env| PREFERRED_PROCESS_NAMES.contains(&proc_env.name.as_str()))
+        .find_map(session_for)
+        .or_else(|| processes.iter().find_map(session_for))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NESTED_DIR: &str = "/run/user/1000/nested_plasma";
+
+    fn proc_env(name: &str, vars: &[(&str, &str)]) -> ProcessEnvironment {
+        ProcessEnvironment {
+            name: name.into(),
+            env: vars
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_environ() {
+        let env = parse_environ(b"DISPLAY=:2\0XAUTHORITY=/tmp/xauth\0EMPTY=\0GARBAGE\0");
+        assert_eq!(Some(&":2".to_string()), env.get("DISPLAY"));
+        assert_eq!(Some(&"/tmp/xauth".to_string()), env.get("XAUTHORITY"));
+        assert_eq!(Some(&String::new()), env.get("EMPTY"));
+        assert!(!env.contains_key("GARBAGE"));
+    }
+
+    #[test]
+    fn prefers_kwin_in_nested_session() {
+        let processes = vec![
+            // Game mode's own session
+            proc_env(
+                "steam",
+                &[("DISPLAY", ":0"), ("XAUTHORITY", "/tmp/xauth_game")],
+            ),
+            proc_env(
+                "konsole",
+                &[
+                    ("DISPLAY", ":3"),
+                    ("XAUTHORITY", "/run/user/1000/nested_plasma/xauth_old"),
+                ],
+            ),
+            proc_env(
+                "kwin_wayland",
+                &[
+                    ("DISPLAY", ":2"),
+                    ("XAUTHORITY", "/run/user/1000/nested_plasma/xauth_abcd"),
+                ],
+            ),
+        ];
+        let session = find_in_processes(Path::new(NESTED_DIR), &processes).unwrap();
+        assert_eq!(":2", session.display);
+        assert_eq!(
+            "/run/user/1000/nested_plasma/xauth_abcd",
+            session.xauthority
+        );
+    }
+
+    #[test]
+    fn falls_back_to_any_process_in_nested_session() {
+        let processes = vec![
+            proc_env("kwin_wayland", &[("DISPLAY", ":0")]),
+            proc_env(
+                "plasmashell",
+                &[
+                    ("DISPLAY", ":2"),
+                    ("XAUTHORITY", "/run/user/1000/nested_plasma/xauth_abcd"),
+                ],
+            ),
+        ];
+        let session = find_in_processes(Path::new(NESTED_DIR), &processes).unwrap();
+        assert_eq!(":2", session.display);
+    }
+
+    #[test]
+    fn no_nested_session() {
+        let processes = vec![proc_env(
+            "steam",
+            &[("DISPLAY", ":0"), ("XAUTHORITY", "/tmp/xauth_game")],
+        )];
+        assert!(find_in_processes(Path::new(NESTED_DIR), &processes).is_none());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+// Point this at a JSON file (see `RawNetworkState`) to use it instead of the system's interfaces
+pub const NETWORK_FIXTURE_ENV_VAR: &str = "DECKTRICKS_NETWORK_FIXTURE";
+
+pub const DEFAULT_SSH_PORT: u16 = 22;
+
+// Bridges and container interfaces can't be reached from other machines, so they're never primary
+// or offered as somewhere to connect to
+const VIRTUAL_INTERFACE_PREFIXES: &[&str] =
+    &["docker", "br-", "veth", "virbr", "podman", "lxcbr", "vboxnet", "vmnet"];
+
+// Exactly what we read from the system, before deciding which address is the primary one.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RawNetworkState {
+    // The interface which has the default IPv4 route, if any
+    pub default_route_interface: Option<String>,
+    pub interfaces: Vec<RawInterface>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RawInterface {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+impl RawNetworkState {
+    /// # Errors
+    ///
+    /// Returns errors relating to reading/parsing the fixture, if one is set.
+    pub fn gather() -> DeckResult<Self> {
+        match std::env::var(NETWORK_FIXTURE_ENV_VAR) {
+            Ok(fixture_path) => Self::from_fixture(&fixture_path),
+            Err(_) => Ok(Self::read_from_system()),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns errors relating to reading/parsing the fixture file.
+    pub fn from_fixture(path: &str) -> DeckResult<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            KnownError::NetworkInfoError(format!("Could not read fixture \"{path}\": {e}"))
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    // Tests never look at the real interfaces, so they get the same answer on every machine
+    #[cfg(test)]
+    fn read_from_system() -> Self {
+        Self {
+            default_route_interface: Some("wlan0".into()),
+            interfaces: vec![RawInterface {
+                name: "wlan0".into(),
+                address: IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 23)),
+            }],
+        }
+    }
+
+    #[cfg(not(test))]
+    fn read_from_system() -> Self {
+        let interfaces = if_addrs::get_if_addrs()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|iface| RawInterface {
+                address: iface.ip(),
+                name: iface.name,
+            })
+            .collect();
+
+        let default_route_interface = std::fs::read_to_string("/proc/net/route")
+            .ok()
+            .and_then(|route_table| parse_default_route_interface(&route_table));
+
+        Self {
+            default_route_interface,
+            interfaces,
+        }
+    }
+}
+
+// /proc/net/route has a header line, then one route per line. The default route is the one with
+// a destination of 00000000.
+fn parse_default_route_interface(route_table: &str) -> Option<String> {
+    route_table.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let iface = fields.next()?;
+        let destination = fields.next()?;
+        (destination == "00000000").then(|| iface.to_string())
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkAddress {
+    pub interface: String,
+    pub address: IpAddr,
+    pub is_primary: bool,
+}
+
+impl NetworkAddress {
+    // IPv6 addresses need brackets in `user@host:path` style arguments
+    fn host_for_path_args(&self) -> String {
+        match self.address {
+            IpAddr::V4(addr) => addr.to_string(),
+            IpAddr::V6(addr) => format!("[{addr}]"),
+        }
+    }
+
+    fn is_virtual(&self) -> bool {
+        VIRTUAL_INTERFACE_PREFIXES
+            .iter()
+            .any(|prefix| self.interface.starts_with(prefix))
+    }
+}
+
+// Ready-to-copy commands for reaching this machine from another one
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionHints {
+    pub ssh: String,
+    pub rsync: String,
+    pub sshfs: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInfo {
+    pub user: String,
+    pub port: u16,
+    pub addresses: Vec<NetworkAddress>,
+    // None when there's no primary address to connect to
+    pub connection_hints: Option<ConnectionHints>,
+}
+
+impl NetworkInfo {
+    /// # Errors
+    ///
+    /// Returns errors relating to reading/parsing the fixture, if one is set.
+    pub fn gather(user: &str, port: u16) -> DeckResult<Self> {
+        Ok(Self::new(RawNetworkState::gather()?, user, port))
+    }
+
+    #[must_use]
+    pub fn new(raw: RawNetworkState, user: &str, port: u16) -> Self {
+        let mut addresses: Vec<NetworkAddress> = raw
+            .interfaces
+            .into_iter()
+            .filter(|iface| !iface.address.is_loopback())
+            .map(|iface| NetworkAddress {
+                interface: iface.name,
+                address: iface.address,
+                is_primary: false,
+            })
+            .collect();
+
+        if let Some(primary) =
+            choose_primary_index(&addresses, raw.default_route_interface.as_deref())
+        {
+            addresses[primary].is_primary = true;
+        }
+
+        // Primary first, then IPv4 before IPv6, otherwise in the order the system gave them
+        addresses.sort_by_key(|addr| (!addr.is_primary, addr.address.is_ipv6()));
+
+        let connection_hints = addresses
+            .iter()
+            .find(|addr| addr.is_primary)
+            .map(|primary| get_connection_hints(primary, user, port));
+
+        Self {
+            user: user.into(),
+            port,
+            addresses,
+            connection_hints,
+        }
+    }
+
+    #[must_use]
+    pub fn get_primary(&self) -> Option<&NetworkAddress> {
+        self.addresses.iter().find(|addr| addr.is_primary)
+    }
+
+    // Only the ones another machine could connect to, primary first
+    #[must_use]
+    pub fn get_ipv4_addresses(&self) -> Vec<String> {
+        self.addresses
+            .iter()
+            .filter(|addr| addr.address.is_ipv4() && !addr.is_virtual())
+            .map(|addr| addr.address.to_string())
+            .collect()
+    }
+
+    // Rendered with unicode half-blocks, so it fits in a terminal
+    #[must_use]
+    pub fn get_qr_code(&self) -> Option<String> {
+        let primary = self.get_primary()?;
+        let code = qrcode::QrCode::new(primary.address.to_string()).ok()?;
+        Some(
+            code.render::<qrcode::render::unicode::Dense1x2>()
+                .dark_color(qrcode::render::unicode::Dense1x2::Light)
+                .light_color(qrcode::render::unicode::Dense1x2::Dark)
+                .build(),
+        )
+    }
+
+    #[must_use]
+    pub fn get_display_text(&self, include_qr_code: bool) -> String {
+        if self.addresses.is_empty() {
+            return "No network addresses were found. Are you connected to a network?".into();
+        }
+
+        let mut lines = vec!["Addresses:".to_string()];
+        for addr in &self.addresses {
+            let primary_marker = if addr.is_primary { " (primary)" } else { "" };
+            lines.push(format!(
+                "  {:<12} {}{primary_marker}",
+                addr.interface, addr.address
+            ));
+        }
+
+        if let Some(hints) = &self.connection_hints {
+            lines.push(String::new());
+            lines.push("Connect from another computer with:".into());
+            lines.push(format!("  {}", hints.ssh));
+            lines.push(format!("  {}", hints.rsync));
+            lines.push(format!("  {}", hints.sshfs));
+        }
+
+        if include_qr_code && let Some(qr_code) = self.get_qr_code() {
+            lines.push(String::new());
+            lines.push(qr_code);
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn get_connection_hints(primary: &NetworkAddress, user: &str, port: u16) -> ConnectionHints {
+    let host = primary.host_for_path_args();
+    let (ssh_port, rsync_port) = if port == DEFAULT_SSH_PORT {
+        (String::new(), String::new())
+    } else {
+        (format!("-p {port} "), format!("-e \"ssh -p {port}\" "))
+    };
+
+    ConnectionHints {
+        ssh: format!("ssh {ssh_port}{user}@{}", primary.address),
+        rsync: format!("rsync -avP {rsync_port}<FILE> {user}@{host}:"),
+        sshfs: format!("sshfs {ssh_port}{user}@{host}: <MOUNTPOINT>"),
+    }
+}
+
+// Prefers IPv4 on the interface with the default route, then IPv4 on anything which isn't
+// obviously virtual, then any globally-reachable IPv6. Link-local IPv6 is never primary.
+fn choose_primary_index(
+    addresses: &[NetworkAddress],
+    default_route_interface: Option<&str>,
+) -> Option<usize> {
+    let is_link_local_v6 = |addr: &NetworkAddress| match addr.address {
+        IpAddr::V6(v6) => v6.is_unicast_link_local(),
+        IpAddr::V4(_) => false,
+    };
+
+    addresses
+        .iter()
+        .position(|addr| {
+            addr.address.is_ipv4() && Some(addr.interface.as_str()) == default_route_interface
+        })
+        .or_else(|| {
+            addresses
+                .iter()
+                .position(|addr| addr.address.is_ipv4() && !addr.is_virtual())
+        })
+        .or_else(|| {
+            addresses
+                .iter()
+                .position(|addr| !addr.is_virtual() && !is_link_local_v6(addr))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"{
+        "default_route_interface": "wlan0",
+        "interfaces": [
+            { "name": "lo", "address": "127.0.0.1" },
+            { "name": "docker0", "address": "172.17.0.1" },
+            { "name": "wlan0", "address": "fe80::1234:5678:9abc:def0" },
+            { "name": "wlan0", "address": "192.168.1.23" }
+        ]
+    }"#;
+
+    fn get_info(fixture: &str, port: u16) -> NetworkInfo {
+        let raw: RawNetworkState = serde_json::from_str(fixture).unwrap();
+        NetworkInfo::new(raw, "deck", port)
+    }
+
+    #[test]
+    fn primary_is_ipv4_on_default_route() {
+        let info = get_info(FIXTURE, DEFAULT_SSH_PORT);
+        let primary = info.get_primary().unwrap();
+        assert_eq!("wlan0", primary.interface);
+        assert_eq!("192.168.1.23", primary.address.to_string());
+        assert_eq!(3, info.addresses.len());
+        assert_eq!(vec!["192.168.1.23"], info.get_ipv4_addresses());
+    }
+
+    #[test]
+    fn virtual_interfaces_are_not_primary_without_default_route() {
+        let fixture = FIXTURE.replace(r#""default_route_interface": "wlan0","#, "");
+        let info = get_info(&fixture, DEFAULT_SSH_PORT);
+        assert_eq!(
+            "192.168.1.23",
+            info.get_primary().unwrap().address.to_string()
+        );
+    }
+
+    #[test]
+    fn hints_include_port_and_bracket_ipv6() {
+        let info = get_info(FIXTURE, DEFAULT_SSH_PORT);
+        let hints = info.connection_hints.clone().unwrap();
+        assert_eq!("ssh deck@192.168.1.23", hints.ssh);
+        assert_eq!("rsync -avP <FILE> deck@192.168.1.23:", hints.rsync);
+        assert_eq!("sshfs deck@192.168.1.23: <MOUNTPOINT>", hints.sshfs);
+
+        let ipv6_only = r#"{ "interfaces": [ { "name": "eth0", "address": "2001:db8::5" } ] }"#;
+        let hints = get_info(ipv6_only, 2222).connection_hints.unwrap();
+        assert_eq!("ssh -p 2222 deck@2001:db8::5", hints.ssh);
+        assert_eq!(
+            "rsync -avP -e \"ssh -p 2222\" <FILE> deck@[2001:db8::5]:",
+            hints.rsync
+        );
+        assert_eq!(
+            "sshfs -p 2222 deck@[2001:db8::5]: <MOUNTPOINT>",
+            hints.sshfs
+        );
+    }
+
+    #[test]
+    fn no_addresses_gives_no_hints() {
+        let info = get_info(r#"{ "interfaces": [] }"#, DEFAULT_SSH_PORT);
+        assert!(info.connection_hints.is_none());
+        assert!(info.get_qr_code().is_none());
+        assert!(info.get_display_text(true).contains("No network addresses"));
+    }
+
+    #[test]
+    fn parses_default_route() {
+        let route_table = "Iface\tDestination\tGateway\n\
+                           eth0\t000200C0\t00000000\n\
+                           wlan0\t00000000\t010200C0\n";
+        assert_eq!(
+            Some("wlan0".to_string()),
+            parse_default_route_interface(route_table)
+        );
+    }
+}
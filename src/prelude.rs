@@ -2,7 +2,9 @@ pub use crate::actions::*;
 pub use crate::add_to_steam::*;
 pub use crate::command::*;
 pub use crate::executor::*;
+pub use crate::locks::LockWait;
 pub use crate::logging::*;
+pub use crate::progress::{ProgressEvent, ProgressSender, ProgressUpdate};
 pub(crate) use crate::tricks_config::*;
 
 // TODO: bring in rayon::spawn here, and use it everywhere so you can
@@ -0,0 +1,513 @@
+use crate::actions::BatchAction;
+use crate::locks::{LockScope, acquire_lock};
+use crate::prelude::*;
+use crate::stdout_println;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// A machine profile is a snapshot of what decktricks manages on a machine, which can be applied
+// to other machines to set them up the same way.
+
+// Bumped whenever a profile written by this version can't be understood by older versions
+pub const PROFILE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ProfileSubcommand {
+    // Write the installed tricks, Steam shortcuts, and settings on this machine as JSON
+    Export {
+        // Write to this file instead of to stdout
+        #[clap(long, short)]
+        output: Option<String>,
+    },
+    // Install and add to Steam whatever is in the profile but missing here. The plan is always
+    // printed first, so use --dry-run to only see what would change.
+    Apply {
+        file: String,
+        // Also uninstall tricks and remove shortcuts which aren't in the profile
+        #[clap(long)]
+        prune: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub version: u32,
+    #[serde(default)]
+    pub installed: BTreeSet<TrickID>,
+    #[serde(default)]
+    pub added_to_steam: BTreeSet<TrickID>,
+    // Settings can't be applied (they come from the config), but differences are shown
+    pub settings: Option<DecktricksConfigSettings>,
+}
+
+impl Profile {
+    #[must_use]
+    pub fn from_executor(executor: &Executor) -> Self {
+        let mut installed = BTreeSet::new();
+        let mut added_to_steam = BTreeSet::new();
+        for provider in executor.get_all_providers() {
+            let trick_id = &provider.get_trick().id;
+            if provider.is_installed() {
+                installed.insert(trick_id.clone());
+            }
+            if provider.is_added_to_steam() {
+                added_to_steam.insert(trick_id.clone());
+            }
+        }
+
+        Self {
+            version: PROFILE_VERSION,
+            installed,
+            added_to_steam,
+            settings: Some((*executor.get_loaded_config().get_settings()).clone()),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns errors relating to reading or parsing the profile, or if it's from a newer version.
+    pub fn load(path: &str) -> DeckResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| KnownError::ProfileError(format!("Could not read \"{path}\": {e}")))?;
+        Self::parse(&contents)
+            .map_err(|msg| KnownError::ProfileError(format!("Could not parse \"{path}\": {msg}")))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let profile: Self = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        if profile.version > PROFILE_VERSION {
+            return Err(format!(
+                "It was written by a newer version of decktricks (profile version {}, this version understands up to {PROFILE_VERSION}).",
+                profile.version
+            ));
+        }
+        Ok(profile)
+    }
+}
+
+// What it would take to turn the current machine into the desired one. Everything is sorted by
+// trick id, since it comes from BTreeSets.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProfilePlan {
+    pub install: Vec<TrickID>,
+    pub add_to_steam: Vec<TrickID>,
+    pub remove_from_steam: Vec<TrickID>,
+    pub uninstall: Vec<TrickID>,
+    // Not in the profile, but can't be uninstalled or removed from Steam (like simple commands)
+    pub unremovable: Vec<TrickID>,
+    // In the profile, but not in the config on this machine
+    pub unknown_tricks: Vec<TrickID>,
+    pub differing_settings: Vec<String>,
+}
+
+impl ProfilePlan {
+    // `can` says whether an action is currently possible for a trick, which decides what --prune
+    // can actually remove
+    #[must_use]
+    pub fn new(
+        current: &Profile,
+        desired: &Profile,
+        config: &LoadedConfig,
+        prune: bool,
+        can: impl Fn(&TrickID, &SpecificActionID) -> bool,
+    ) -> Self {
+        let is_known = |id: &&TrickID| config.get_trick(id).is_ok();
+        let missing = |wanted: &BTreeSet<TrickID>, have: &BTreeSet<TrickID>| -> Vec<TrickID> {
+            wanted.difference(have).filter(is_known).cloned().collect()
+        };
+
+        let mut unremovable = BTreeSet::new();
+        let mut extra = |wanted: &BTreeSet<TrickID>,
+                         have: &BTreeSet<TrickID>,
+                         action_id: SpecificActionID|
+         -> Vec<TrickID> {
+            if !prune {
+                return vec![];
+            }
+            let (removable, left): (Vec<TrickID>, Vec<TrickID>) = missing(have, wanted)
+                .into_iter()
+                .partition(|id| can(id, &action_id));
+            unremovable.extend(left);
+            removable
+        };
+        let remove_from_steam = extra(
+            &desired.added_to_steam,
+            &current.added_to_steam,
+            SpecificActionID::RemoveFromSteam,
+        );
+        let uninstall = extra(
+            &desired.installed,
+            &current.installed,
+            SpecificActionID::Uninstall,
+        );
+
+        let unknown_tricks = desired
+            .installed
+            .union(&desired.added_to_steam)
+            .filter(|id| !is_known(id))
+            .cloned()
+            .collect();
+
+        Self {
+            install: missing(&desired.installed, &current.installed),
+            add_to_steam: missing(&desired.added_to_steam, &current.added_to_steam),
+            remove_from_steam,
+            unremovable: unremovable
+                .into_iter()
+                .filter(|id| !uninstall.contains(id))
+                .collect(),
+            uninstall,
+            unknown_tricks,
+            differing_settings: get_differing_settings(
+                current.settings.as_ref(),
+                desired.settings.as_ref(),
+            ),
+        }
+    }
+
+    #[must_use]
+    pub fn has_steps(&self) -> bool {
+        !(self.install.is_empty()
+            && self.add_to_steam.is_empty()
+            && self.remove_from_steam.is_empty()
+            && self.uninstall.is_empty())
+    }
+
+    // In the order they're taken
+    fn get_steps(&self) -> Vec<(SpecificActionID, &[TrickID])> {
+        vec![
+            (SpecificActionID::Install, &self.install),
+            (SpecificActionID::AddToSteam, &self.add_to_steam),
+            (SpecificActionID::RemoveFromSteam, &self.remove_from_steam),
+            (SpecificActionID::Uninstall, &self.uninstall),
+        ]
+    }
+
+    // Every action the plan takes, on each trick
+    #[must_use]
+    pub fn get_targets(&self) -> Vec<(TrickID, SpecificActionID)> {
+        self.get_steps()
+            .into_iter()
+            .flat_map(|(action_id, trick_ids)| {
+                trick_ids
+                    .iter()
+                    .map(move |trick_id| (trick_id.clone(), action_id.clone()))
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn get_display(&self) -> String {
+        let mut lines = vec![];
+        if self.has_steps() {
+            lines.push("Plan:".to_string());
+            for (action_id, trick_ids) in self.get_steps() {
+                let sign = match action_id {
+                    SpecificActionID::Install | SpecificActionID::AddToSteam => '+',
+                    _ => '-',
+                };
+                for trick_id in trick_ids {
+                    lines.push(format!("  {sign} {:<18} {trick_id}", action_id.to_string()));
+                }
+            }
+        } else {
+            lines.push("This machine already matches the profile.".to_string());
+        }
+
+        if !self.unremovable.is_empty() {
+            lines.push(format!(
+                "Not in the profile, but can't be removed, so left alone: {}",
+                self.unremovable.join(", ")
+            ));
+        }
+        if !self.unknown_tricks.is_empty() {
+            lines.push(format!(
+                "Not in the config on this machine, so skipped: {}",
+                self.unknown_tricks.join(", ")
+            ));
+        }
+        if !self.differing_settings.is_empty() {
+            lines.push(format!(
+                "Settings which differ (these come from the config, so are left alone): {}",
+                self.differing_settings.join(", ")
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+fn get_differing_settings(
+    current: Option<&DecktricksConfigSettings>,
+    desired: Option<&DecktricksConfigSettings>,
+) -> Vec<String> {
+    let (Some(current), Some(desired)) = (current, desired) else {
+        return vec![];
+    };
+    let (Ok(serde_json::Value::Object(current)), Ok(serde_json::Value::Object(desired))) =
+        (serde_json::to_value(current), serde_json::to_value(desired))
+    else {
+        return vec![];
+    };
+    desired
+        .iter()
+        .filter(|(key, value)| current.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+pub(crate) fn export_profile(
+    ctx: &GeneralExecutionContext,
+    executor: &Executor,
+    output: Option<&str>,
+) -> DeckResult<ActionSuccess> {
+    let profile = Profile::from_executor(executor);
+    let contents = serde_json::to_string_pretty(&profile).map_err(KnownError::from)?;
+
+    let Some(path) = output else {
+        return success!(contents);
+    };
+    if !skip_for_dry_run(ctx, format!("write the profile to \"{path}\"")) {
+        std::fs::write(path, format!("{contents}\n"))
+            .map_err(|e| KnownError::ProfileError(format!("Could not write \"{path}\": {e}")))?;
+    }
+    success!(format!("Wrote profile to \"{path}\"."))
+}
+
+/// # Errors
+///
+/// Returns errors relating to reading or parsing the profile.
+pub(crate) fn get_profile_plan(
+    executor: &Executor,
+    path: &str,
+    prune: bool,
+) -> DeckResult<ProfilePlan> {
+    let desired = Profile::load(path)?;
+    let current = Profile::from_executor(executor);
+    let providers = executor.get_all_providers();
+    let can = |trick_id: &TrickID, action_id: &SpecificActionID| {
+        providers
+            .iter()
+            .any(|provider| &provider.get_trick().id == trick_id && provider.can_id(action_id))
+    };
+    Ok(ProfilePlan::new(
+        &current,
+        &desired,
+        executor.get_loaded_config(),
+        prune,
+        can,
+    ))
+}
+
+pub(crate) fn apply_profile(
+    ctx: &GeneralExecutionContext,
+    executor: &Executor,
+    path: &str,
+    prune: bool,
+    current_log_level: LogType,
+) -> Vec<DeckResult<ActionSuccess>> {
+    let plan = match get_profile_plan(executor, path, prune) {
+        Ok(plan) => plan,
+        Err(err) => return vec![Err(err)],
+    };
+
+    // The plan must be seen before anything happens, so this doesn't wait to be returned
+    let plan_display = plan.get_display();
+    stdout_println!(ctx, plan_display);
+    if !plan.has_steps() {
+        return vec![];
+    }
+    let _lock = match acquire_lock(ctx, &LockScope::Global, &format!("applying profile {path}")) {
+        Ok(lock) => lock,
+        Err(err) => return vec![Err(err)],
+    };
+
+    let mut results = vec![];
+    let mut outcomes: Vec<(SpecificActionID, TrickID, bool)> = vec![];
+    let mut run_step = |executor: &Executor, action_id: SpecificActionID, targets: &[TrickID]| {
+        if targets.is_empty() {
+            return;
+        }
+        let batch = BatchAction {
+            action_id: action_id.clone(),
+            ids: vec![],
+            batch: BatchArgs::default(),
+        };
+        let step_results = batch.run_all(executor, ctx, targets, current_log_level);
+        for (trick_id, res) in targets.iter().zip(&step_results) {
+            outcomes.push((action_id.clone(), trick_id.clone(), res.is_ok()));
+        }
+        results.extend(step_results);
+    };
+
+    run_step(executor, SpecificActionID::Install, &plan.install);
+
+    // The system context was gathered before installing, so tricks installed above need a fresh
+    // one before they can be added to Steam (and may have been added automatically on install)
+    let (newly_installed, already_installed): (Vec<TrickID>, Vec<TrickID>) = plan
+        .add_to_steam
+        .iter()
+        .cloned()
+        .partition(|id| plan.install.contains(id));
+    run_step(executor, SpecificActionID::AddToSteam, &already_installed);
+    if !newly_installed.is_empty()
+        && !skip_for_dry_run(
+            ctx,
+            format!(
+                "add to Steam once installed: {}",
+                newly_installed.join(", ")
+            ),
+        )
+    {
+        let mut refreshed = executor.clone();
+        refreshed.update_system_context(executor.gather_new_system_context());
+        let not_yet_added: Vec<TrickID> = newly_installed
+            .into_iter()
+            .filter(|id| !refreshed.get_current_system_context().is_added_to_steam(id))
+            .collect();
+        run_step(&refreshed, SpecificActionID::AddToSteam, &not_yet_added);
+    }
+
+    run_step(
+        executor,
+        SpecificActionID::RemoveFromSteam,
+        &plan.remove_from_steam,
+    );
+    run_step(executor, SpecificActionID::Uninstall, &plan.uninstall);
+
+    let failures = outcomes.iter().filter(|(_, _, ok)| !ok).count();
+    let mut lines: Vec<String> = outcomes
+        .iter()
+        .map(|(action_id, trick_id, ok)| {
+            let outcome = if *ok { "ok" } else { "failed" };
+            format!("{:<18} {trick_id:<24} {outcome}", action_id.to_string())
+        })
+        .collect();
+    lines.push(String::new());
+    lines.push(format!(
+        "profile apply: {} succeeded, {failures} failed.",
+        outcomes.len() - failures
+    ));
+    results.push(success!(lines.join("\n")));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(installed: &[&str], added_to_steam: &[&str]) -> Profile {
+        Profile {
+            version: PROFILE_VERSION,
+            installed: installed.iter().map(ToString::to_string).collect(),
+            added_to_steam: added_to_steam.iter().map(ToString::to_string).collect(),
+            settings: None,
+        }
+    }
+
+    fn config() -> LoadedConfig {
+        LoadedConfig::from_default_config().unwrap()
+    }
+
+    fn can_anything(_trick_id: &TrickID, _action_id: &SpecificActionID) -> bool {
+        true
+    }
+
+    #[test]
+    fn plan_adds_what_is_missing() {
+        let current = profile(&["lutris"], &[]);
+        let desired = profile(&["lutris", "protonup-qt"], &["lutris", "not-a-real-trick"]);
+
+        let plan = ProfilePlan::new(&current, &desired, &config(), false, can_anything);
+        assert_eq!(vec!["protonup-qt"], plan.install);
+        assert_eq!(vec!["lutris"], plan.add_to_steam);
+        assert_eq!(vec!["not-a-real-trick"], plan.unknown_tricks);
+        assert!(plan.uninstall.is_empty());
+
+        let display = plan.get_display();
+        assert!(display.contains("+ install            protonup-qt"));
+        assert!(display.contains("skipped: not-a-real-trick"));
+    }
+
+    #[test]
+    fn plan_targets_are_in_step_order() {
+        let current = profile(&["lutris"], &["lutris"]);
+        let desired = profile(&["protonup-qt"], &["protonup-qt"]);
+
+        let plan = ProfilePlan::new(&current, &desired, &config(), true, can_anything);
+        assert_eq!(
+            vec![
+                ("protonup-qt".to_string(), SpecificActionID::Install),
+                ("protonup-qt".to_string(), SpecificActionID::AddToSteam),
+                ("lutris".to_string(), SpecificActionID::RemoveFromSteam),
+                ("lutris".to_string(), SpecificActionID::Uninstall),
+            ],
+            plan.get_targets()
+        );
+    }
+
+    #[test]
+    fn plan_only_removes_with_prune() {
+        let current = profile(&["lutris", "protonup-qt"], &["lutris"]);
+        let desired = profile(&["lutris"], &[]);
+
+        let plan = ProfilePlan::new(&current, &desired, &config(), false, can_anything);
+        assert!(!plan.has_steps());
+
+        let plan = ProfilePlan::new(&current, &desired, &config(), true, can_anything);
+        assert_eq!(vec!["protonup-qt"], plan.uninstall);
+        assert_eq!(vec!["lutris"], plan.remove_from_steam);
+        assert!(plan.unremovable.is_empty());
+    }
+
+    #[test]
+    fn prune_leaves_what_cannot_be_removed() {
+        // Simple commands are always "installed", but can't be uninstalled
+        let current = profile(&["lutris", "protonup-qt"], &["lutris"]);
+        let desired = profile(&[], &[]);
+        let can = |trick_id: &TrickID, action_id: &SpecificActionID| {
+            trick_id == "lutris" && *action_id == SpecificActionID::Uninstall
+        };
+
+        let plan = ProfilePlan::new(&current, &desired, &config(), true, can);
+        assert_eq!(vec!["lutris"], plan.uninstall);
+        assert!(plan.remove_from_steam.is_empty());
+        // Uninstalling it is as close as this gets to taking it out of Steam
+        assert_eq!(vec!["protonup-qt"], plan.unremovable);
+        assert!(
+            plan.get_display()
+                .contains("can't be removed, so left alone: protonup-qt")
+        );
+    }
+
+    #[test]
+    fn newer_profiles_are_refused() {
+        let newer = format!(r#"{{"version": {}, "installed": []}}"#, PROFILE_VERSION + 1);
+        assert!(
+            Profile::parse(&newer)
+                .unwrap_err()
+                .contains("newer version")
+        );
+
+        let minimal = Profile::parse(r#"{"version": 1, "installed": ["lutris"]}"#).unwrap();
+        assert_eq!(profile(&["lutris"], &[]), minimal);
+    }
+
+    #[test]
+    fn settings_differences_are_listed() {
+        let current = DecktricksConfigSettings {
+            controller_layout_id: "1".into(),
+            add_to_steam_on_install_where_applicable: true,
+            ..Default::default()
+        };
+        let desired = DecktricksConfigSettings {
+            controller_layout_id: "2".into(),
+            ..current.clone()
+        };
+        assert_eq!(
+            vec!["controller_layout_id"],
+            get_differing_settings(Some(&current), Some(&desired))
+        );
+        assert!(get_differing_settings(Some(&current), None).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::prelude::*;
+use std::io::{Read, Write};
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, PoisonError};
+
+// Structured progress for long-running work like installs and downloads. Events are sent over
+// the execution context's progress channel, if anyone is listening: the GUI turns them into
+// progress bars, and the CLI draws one on stderr.
+
+pub type ProgressSender = Sender<ProgressEvent>;
+
+// Turns one line of a command's output into progress, if it has any. See SysCommand::parse_progress.
+pub type ProgressParser = fn(&str) -> Option<ProgressUpdate>;
+
+// Downloads send progress every time this many more bytes have arrived
+const DOWNLOAD_PROGRESS_STEP: u64 = 256 * 1024;
+
+const CLI_BAR_WIDTH: usize = 20;
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+// Whether the CLI's progress bar is on screen. This is held while drawing it, and while printing
+// anything else, so that the two never end up on the same line.
+static CLI_PROGRESS_BAR_DRAWN: Mutex<bool> = Mutex::new(false);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressEvent {
+    // None for general actions, like update-all
+    pub trick_id: Option<TrickID>,
+    pub update: ProgressUpdate,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgressUpdate {
+    pub phase: String,
+    pub percent: Option<u8>,
+    pub bytes: Option<ByteProgress>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteProgress {
+    pub done: u64,
+    pub total: Option<u64>,
+}
+
+impl ByteProgress {
+    #[must_use]
+    pub fn percent(&self) -> Option<u8> {
+        let total = self.total.filter(|total| *total > 0)?;
+        u8::try_from((self.done.saturating_mul(100) / total).min(100)).ok()
+    }
+}
+
+impl ProgressUpdate {
+    // Falls back to working the percentage out from the bytes
+    #[must_use]
+    pub fn get_percent(&self) -> Option<u8> {
+        self.percent
+            .or_else(|| self.bytes.and_then(|bytes| bytes.percent()))
+    }
+}
+
+pub(crate) fn send_progress(ctx: &impl ExecCtx, update: ProgressUpdate) {
+    let Some(sender) = ctx.get_progress_sender() else {
+        return;
+    };
+    let trick_id = match ctx.get_log_channel() {
+        LogChannel::TrickID(trick_id) => Some(trick_id.clone()),
+        LogChannel::General | LogChannel::IgnoreCompletelyAlways => None,
+    };
+    // Nobody listening any more is fine
+    let _ = sender.send(ProgressEvent { trick_id, update });
+}
+
+// Flatpak draws progress like "Installing 1/2… ████████▌ 45%  12.3 MB/27.4 MB", redrawing the
+// line with \r (and some color codes) when it thinks it's in a terminal.
+#[must_use]
+pub fn parse_flatpak_progress(line: &str) -> Option<ProgressUpdate> {
+    let last_draw = line.rsplit('\r').find(|draw| !draw.trim().is_empty())?;
+    let line = strip_ansi_codes(last_draw);
+    let (phase, rest) = line.split_once('…').or_else(|| line.split_once("..."))?;
+
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    let percent = words
+        .iter()
+        .find_map(|word| word.strip_suffix('%')?.parse::<u8>().ok())
+        .map(|percent| percent.min(100));
+    // Sizes are given as "12.3 MB/27.4 MB", so the slash is in the middle of a word
+    let bytes = words.windows(3).find_map(|window| {
+        let (done_unit, total_num) = window[1].split_once('/')?;
+        Some(ByteProgress {
+            done: parse_size(window[0], done_unit)?,
+            total: Some(parse_size(total_num, window[2])?),
+        })
+    });
+
+    Some(ProgressUpdate {
+        phase: phase.trim().into(),
+        percent,
+        bytes,
+    })
+}
+
+fn strip_ansi_codes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Everything up to and including the final letter of the escape sequence
+            chars.find(char::is_ascii_alphabetic);
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn parse_size(number: &str, unit: &str) -> Option<u64> {
+    let number: f64 = number.parse().ok()?;
+    let multiplier: f64 = match unit {
+        "B" | "bytes" => 1.0,
+        "kB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    (number >= 0.0).then_some((number * multiplier) as u64)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+// Wraps something being downloaded, and sends progress as it's read
+pub(crate) struct ProgressReader<'a, R: Read, C: ExecCtx> {
+    inner: R,
+    ctx: &'a C,
+    phase: String,
+    bytes: ByteProgress,
+    last_sent: Option<u64>,
+}
+
+impl<'a, R: Read, C: ExecCtx> ProgressReader<'a, R, C> {
+    pub(crate) fn new(ctx: &'a C, inner: R, phase: impl Into<String>, total: Option<u64>) -> Self {
+        Self {
+            inner,
+            ctx,
+            phase: phase.into(),
+            bytes: ByteProgress { done: 0, total },
+            last_sent: None,
+        }
+    }
+
+    // For resumed downloads, where `done` bytes were already there
+    #[must_use]
+    pub(crate) fn starting_at(self, done: u64) -> Self {
+        Self {
+            bytes: ByteProgress { done, ..self.bytes },
+            ..self
+        }
+    }
+
+    fn send(&mut self) {
+        self.last_sent = Some(self.bytes.done);
+        send_progress(
+            self.ctx,
+            ProgressUpdate {
+                phase: self.phase.clone(),
+                percent: None,
+                bytes: Some(self.bytes),
+            },
+        );
+    }
+}
+
+impl<R: Read, C: ExecCtx> Read for ProgressReader<'_, R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_read = self.inner.read(buf)?;
+        self.bytes.done += num_read as u64;
+
+        let is_done = num_read == 0;
+        let is_due = self
+            .last_sent
+            .is_none_or(|last_sent| self.bytes.done - last_sent >= DOWNLOAD_PROGRESS_STEP);
+        if is_due || (is_done && self.last_sent != Some(self.bytes.done)) {
+            self.send();
+        }
+        Ok(num_read)
+    }
+}
+
+pub fn draw_cli_progress_bar(event: &ProgressEvent) {
+    let mut drawn = CLI_PROGRESS_BAR_DRAWN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let mut stderr = std::io::stderr();
+    let _ = write!(stderr, "{CLEAR_LINE}{}", get_progress_line(event));
+    let _ = stderr.flush();
+    *drawn = true;
+}
+
+// Anything else printed takes the bar's place, and the bar is drawn again with the next update
+pub fn print_over_cli_progress_bar(print: impl FnOnce()) {
+    let mut drawn = CLI_PROGRESS_BAR_DRAWN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if *drawn {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "{CLEAR_LINE}");
+        let _ = stderr.flush();
+        *drawn = false;
+    }
+    print();
+}
+
+pub fn clear_cli_progress_bar() {
+    print_over_cli_progress_bar(|| {});
+}
+
+// One line of the CLI's progress bar, like:
+//   [lutris] Installing 1/2 [#########-----------]  45% 12.3 MB / 27.4 MB
+#[must_use]
+pub fn get_progress_line(event: &ProgressEvent) -> String {
+    let update = &event.update;
+    let mut parts = vec![];
+    if let Some(trick_id) = &event.trick_id {
+        parts.push(format!("[{trick_id}]"));
+    }
+    parts.push(update.phase.clone());
+
+    if let Some(percent) = update.get_percent() {
+        let filled = CLI_BAR_WIDTH * usize::from(percent) / 100;
+        parts.push(format!(
+            "[{}{}] {percent:>3}%",
+            "#".repeat(filled),
+            "-".repeat(CLI_BAR_WIDTH - filled)
+        ));
+    }
+
+    if let Some(bytes) = update.bytes {
+        parts.push(match bytes.total {
+            Some(total) => format!("{} / {}", format_size(bytes.done), format_size(total)),
+            None => format_size(bytes.done),
+        });
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn flatpak_progress_is_parsed() {
+        let update = parse_flatpak_progress(
+            "Installing 1/2… \u{1b}[32m████████▌\u{1b}[0m  45%  12.3 MB/27.4 MB",
+        )
+        .unwrap();
+        assert_eq!("Installing 1/2", update.phase);
+        assert_eq!(Some(45), update.percent);
+        assert_eq!(
+            Some(ByteProgress {
+                done: 12_300_000,
+                total: Some(27_400_000)
+            }),
+            update.bytes
+        );
+
+        // Only the last redraw counts, and speeds aren't sizes
+        let update =
+            parse_flatpak_progress("Updating 1/1…  10%\rUpdating 1/1…  80%  1.2 MB/s  00:02\r")
+                .unwrap();
+        assert_eq!(Some(80), update.percent);
+        assert_eq!(None, update.bytes);
+
+        let update = parse_flatpak_progress("Looking for matches…").unwrap();
+        assert_eq!("Looking for matches", update.phase);
+        assert_eq!(None, update.get_percent());
+
+        assert_eq!(None, parse_flatpak_progress("Installation complete."));
+    }
+
+    #[test]
+    fn downloads_send_byte_progress() {
+        let (tx, rx) = mpsc::channel();
+        let ctx = SpecificExecutionContext::test(Trick {
+            id: "some-trick".into(),
+            ..Default::default()
+        })
+        .with_progress_sender(Some(tx));
+
+        let data = vec![0u8; 300 * 1024];
+        let mut reader =
+            ProgressReader::new(&ctx, data.as_slice(), "Downloading", Some(300 * 1024));
+        let mut sink = vec![];
+        std::io::copy(&mut reader, &mut sink).unwrap();
+        drop(ctx);
+
+        let events: Vec<ProgressEvent> = rx.iter().collect();
+        let last = events.last().unwrap();
+        assert_eq!(Some("some-trick".into()), last.trick_id);
+        assert_eq!(Some(100), last.update.get_percent());
+        assert!(events.len() >= 2);
+        assert!(events.windows(2).all(|pair| {
+            pair[0].update.bytes.unwrap().done < pair[1].update.bytes.unwrap().done
+        }));
+    }
+
+    #[test]
+    fn progress_line_has_bar_and_sizes() {
+        let event = ProgressEvent {
+            trick_id: Some("lutris".into()),
+            update: ProgressUpdate {
+                phase: "Downloading".into(),
+                percent: None,
+                bytes: Some(ByteProgress {
+                    done: 5_000_000,
+                    total: Some(20_000_000),
+                }),
+            },
+        };
+        assert_eq!(
+            "[lutris] Downloading [#####---------------]  25% 5.0 MB / 20.0 MB",
+            get_progress_line(&event)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::nested_desktop::is_nested_desktop_available;
+use crate::prelude::*;
+use serde::Serialize;
+use std::fmt;
+
+// Why a particular action can't be taken on a trick right now. The `Display` text is meant to be
+// shown directly to users (in the CLI, and as a tooltip in the GUI).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum UnavailableReason {
+    NotInstalled,
+    AlreadyInstalled,
+    NotRunning,
+    AlreadyRunning,
+    InstallInProgress,
+    NotAddedToSteam,
+    AlreadyAddedToSteam,
+    // The command which was not found in $PATH
+    MissingBinary(String),
+    UnsupportedOnPlatform(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for UnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInstalled => write!(f, "It is not installed."),
+            Self::AlreadyInstalled => write!(f, "It is already installed."),
+            Self::NotRunning => write!(f, "It is not running."),
+            Self::AlreadyRunning => write!(f, "It is already running."),
+            Self::InstallInProgress => write!(f, "An install is already in progress."),
+            Self::NotAddedToSteam => write!(f, "It has not been added to Steam."),
+            Self::AlreadyAddedToSteam => write!(f, "It has already been added to Steam."),
+            Self::MissingBinary(command) => {
+                write!(f, "\"{command}\" was not found in $PATH. Is it installed?")
+            }
+            Self::UnsupportedOnPlatform(reason) | Self::Unsupported(reason) => {
+                write!(f, "{reason}")
+            }
+        }
+    }
+}
+
+// Used when the provider doesn't know anything more specific. This is worked out from the same
+// predicates which gate the action, so it's a best guess rather than gospel.
+pub(super) fn get_default_unavailable_reason<P: ProviderChecks + ?Sized>(
+    provider: &P,
+    action_id: &SpecificActionID,
+) -> UnavailableReason {
+    let is_installed = provider.is_installed();
+    let is_running = provider.is_running();
+
+    match action_id {
+        SpecificActionID::Kill => UnavailableReason::NotRunning,
+        SpecificActionID::RemoveFromSteam => UnavailableReason::NotAddedToSteam,
+        SpecificActionID::Info | SpecificActionID::Logs => unsupported(provider),
+
+        _ if provider.is_installing() => UnavailableReason::InstallInProgress,
+
+        SpecificActionID::Install if is_installed => UnavailableReason::AlreadyInstalled,
+        SpecificActionID::Run
+        | SpecificActionID::RunNested
+        | SpecificActionID::Uninstall
+        | SpecificActionID::Update
+        | SpecificActionID::Reinstall
+            if !is_installed =>
+        {
+            UnavailableReason::NotInstalled
+        }
+        SpecificActionID::Run | SpecificActionID::RunNested | SpecificActionID::Reinstall
+            if is_running =>
+        {
+            UnavailableReason::AlreadyRunning
+        }
+        SpecificActionID::RunNested if !is_nested_desktop_available() => {
+            UnavailableReason::UnsupportedOnPlatform(
+                "The nested desktop is only available on SteamOS.".into(),
+            )
+        }
+
+        SpecificActionID::AddToSteam if provider.is_added_to_steam() => {
+            UnavailableReason::AlreadyAddedToSteam
+        }
+        SpecificActionID::AddToSteam
+            if !is_installed && provider.is_addable_to_steam_once_installed() =>
+        {
+            UnavailableReason::NotInstalled
+        }
+
+        _ => unsupported(provider),
+    }
+}
+
+fn unsupported<P: ProviderChecks + ?Sized>(provider: &P) -> UnavailableReason {
+    UnavailableReason::Unsupported(format!(
+        "Tricks of type \"{}\" can't do this.",
+        provider
+            .get_execution_context()
+            .trick
+            .provider_config
+            .type_tag()
+    ))
+}
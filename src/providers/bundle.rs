@@ -0,0 +1,666 @@
+use crate::prelude::*;
+use crate::providers::registry::{
+    ProviderContextRc, ProviderRegistration, UpdateAllStrategy, check_builtin_config,
+    mismatched_config,
+};
+use crate::providers::system_context::FullSystemContext;
+use crate::utils::get_decktricks_dir;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type MemberResults = Vec<(TrickID, DeckResult<ActionSuccess>)>;
+
+fn get_bundle_state_dir() -> PathBuf {
+    get_decktricks_dir().join("bundles")
+}
+
+#[derive(Debug)]
+pub struct BundleProvider {
+    ctx: SpecificExecutionContext,
+    members: Vec<DynTrickProvider>,
+    // Members which were not installed before the bundle installed them. Only these are removed
+    // when the bundle is uninstalled, so we never take away something the user installed by hand.
+    installed_by_bundle: Vec<TrickID>,
+    state_dir: PathBuf,
+}
+
+impl BundleProvider {
+    pub(super) fn new(
+        ctx: SpecificExecutionContext,
+        members: Vec<DynTrickProvider>,
+        state_dir: PathBuf,
+    ) -> Self {
+        let installed_by_bundle = read_installed_by_bundle(&ctx, &state_dir);
+        Self {
+            ctx,
+            members,
+            installed_by_bundle,
+            state_dir,
+        }
+    }
+
+    fn missing_members(&self) -> Vec<&DynTrickProvider> {
+        self.members
+            .iter()
+            .filter(|member| !member.is_installed())
+            .collect()
+    }
+
+    fn members_to_install(&self) -> Vec<&DynTrickProvider> {
+        self.missing_members()
+            .into_iter()
+            .filter(|member| member.is_installable())
+            .collect()
+    }
+
+    fn members_to_uninstall(&self) -> Vec<&DynTrickProvider> {
+        self.members
+            .iter()
+            .filter(|member| {
+                self.installed_by_bundle.contains(&member.get_trick().id)
+                    && member.is_uninstallable()
+            })
+            .collect()
+    }
+
+    // Members using the same provider are run one at a time, since package managers like flatpak
+    // only allow one transaction at a time. Members using different providers run in parallel.
+    fn for_each_member<F>(
+        &self,
+        verb: &str,
+        members: &[&DynTrickProvider],
+        action: F,
+    ) -> MemberResults
+    where
+        F: Fn(&DynTrickProvider) -> DeckResult<ActionSuccess> + Sync,
+    {
+        let total = members.len();
+        let done = AtomicUsize::new(0);
+        let member_ids: Vec<&str> = members.iter().map(|m| m.get_trick().id.as_str()).collect();
+        info!(
+            &self.ctx,
+            "{verb} {total} member(s): {}",
+            member_ids.join(", ")
+        );
+
+        let mut by_provider: BTreeMap<&str, Vec<&DynTrickProvider>> = BTreeMap::new();
+        for member in members {
+            by_provider
+                .entry(member.get_trick().provider_config.type_tag())
+                .or_default()
+                .push(member);
+        }
+
+        let mut results: MemberResults = by_provider
+            .into_values()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .flat_map_iter(|group| {
+                group
+                    .into_iter()
+                    .map(|member| {
+                        let trick_id = member.get_trick().id.clone();
+                        let res = action(member);
+                        let finished = done.fetch_add(1, Ordering::SeqCst) + 1;
+                        let status = if res.is_ok() { "done" } else { "failed" };
+                        info!(
+                            &self.ctx,
+                            "[{finished}/{total}] {verb} {trick_id}: {status}"
+                        );
+                        (trick_id, res)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Report in the order the bundle lists its members, not the order they finished in
+        results.sort_by_key(|(trick_id, _)| {
+            member_ids
+                .iter()
+                .position(|id| id == trick_id)
+                .unwrap_or_default()
+        });
+        results
+    }
+
+    fn record_installed_by_bundle(&self, installed_by_bundle: &[TrickID]) {
+        if skip_for_dry_run(
+            &self.ctx,
+            format!("record which members \"{}\" installed", self.ctx.trick.id),
+        ) {
+            return;
+        }
+        if let Err(err) =
+            write_installed_by_bundle(&self.state_dir, &self.ctx.trick.id, installed_by_bundle)
+        {
+            warn!(
+                &self.ctx,
+                "Could not record which members of \"{}\" were installed by it: {err:?}",
+                self.ctx.trick.id
+            );
+        }
+    }
+}
+
+// Gives a line for every member, and only succeeds if every member did. The lines are kept in the
+// error otherwise, so the members which did succeed (or were skipped) are still shown.
+fn summarize(results: MemberResults, skipped: &[(&str, &str)]) -> DeckResult<ActionSuccess> {
+    let mut lines = vec![];
+    let mut failures = vec![];
+    for (trick_id, res) in results {
+        match res {
+            Ok(_) => lines.push(format!("{trick_id}: success")),
+            Err(err) => {
+                lines.push(format!("{trick_id}: failed ({err})"));
+                failures.push((trick_id, err));
+            }
+        }
+    }
+    for (trick_id, reason) in skipped {
+        lines.push(format!("{trick_id}: skipped ({reason})"));
+    }
+
+    if failures.is_empty() {
+        success!(lines.join("\n"))
+    } else {
+        Err(KnownError::BundleMemberFailures(lines.join("\n"), failures))
+    }
+}
+
+impl TrickProvider for BundleProvider {}
+
+impl ProviderChecks for BundleProvider {
+    fn get_execution_context(&self) -> &SpecificExecutionContext {
+        &self.ctx
+    }
+
+    fn is_installable(&self) -> bool {
+        !self.members_to_install().is_empty()
+    }
+
+    fn is_uninstallable(&self) -> bool {
+        !self.members_to_uninstall().is_empty()
+    }
+
+    fn is_installed(&self) -> bool {
+        !self.members.is_empty() && self.missing_members().is_empty()
+    }
+
+    fn is_runnable(&self) -> bool {
+        false
+    }
+
+    fn is_running(&self) -> bool {
+        false
+    }
+
+    fn is_killable(&self) -> bool {
+        false
+    }
+
+    fn is_updateable(&self) -> bool {
+        self.members.iter().any(|member| member.is_updateable())
+    }
+
+    fn is_addable_to_steam(&self) -> bool {
+        false
+    }
+
+    fn is_addable_to_steam_once_installed(&self) -> bool {
+        false
+    }
+
+    fn get_unavailable_reason(&self, action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        match action_id {
+            SpecificActionID::Install if self.is_installed() => {
+                Some(UnavailableReason::AlreadyInstalled)
+            }
+            // Only members installed by this bundle are removed, and none of them are installed
+            SpecificActionID::Uninstall => Some(UnavailableReason::NotInstalled),
+            SpecificActionID::Run
+            | SpecificActionID::RunNested
+            | SpecificActionID::Reinstall
+            | SpecificActionID::Kill
+            | SpecificActionID::AddToSteam => {
+                Some(UnavailableReason::Unsupported(
+                    "Bundles can't do this directly, use their members instead.".into(),
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ProviderActions for BundleProvider {
+    fn run(&self) -> DeckResult<ActionSuccess> {
+        not_possible("Bundles cannot be run.")
+    }
+
+    fn kill(&self) -> DeckResult<ActionSuccess> {
+        not_possible("Bundles cannot be killed.")
+    }
+
+    fn install(&self) -> DeckResult<ActionSuccess> {
+        let to_install = self.members_to_install();
+        let results = self.for_each_member("Installing", &to_install, |member| {
+            let res = member.install();
+            if res.is_ok() {
+                add_to_steam_after_install(member.get_ctx(), member);
+            }
+            res
+        });
+
+        let mut installed_by_bundle = self.installed_by_bundle.clone();
+        for (trick_id, res) in &results {
+            if res.is_ok() && !installed_by_bundle.contains(trick_id) {
+                installed_by_bundle.push(trick_id.clone());
+            }
+        }
+        self.record_installed_by_bundle(&installed_by_bundle);
+
+        let skipped: Vec<(&str, &str)> = self
+            .members
+            .iter()
+            .filter_map(|member| {
+                let trick_id = member.get_trick().id.as_str();
+                if member.is_installed() {
+                    Some((trick_id, "already installed"))
+                } else if !member.is_installable() {
+                    Some((trick_id, "cannot be installed"))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        summarize(results, &skipped)
+    }
+
+    fn uninstall(&self) -> DeckResult<ActionSuccess> {
+        let to_uninstall = self.members_to_uninstall();
+        let results =
+            self.for_each_member("Uninstalling", &to_uninstall, |member| member.uninstall());
+
+        let removed: Vec<&TrickID> = results
+            .iter()
+            .filter(|(_, res)| res.is_ok())
+            .map(|(trick_id, _)| trick_id)
+            .collect();
+        let installed_by_bundle: Vec<TrickID> = self
+            .installed_by_bundle
+            .iter()
+            .filter(|trick_id| !removed.contains(trick_id))
+            .cloned()
+            .collect();
+        self.record_installed_by_bundle(&installed_by_bundle);
+
+        let skipped: Vec<(&str, &str)> = self
+            .members
+            .iter()
+            .filter(|member| {
+                member.is_installed() && !self.installed_by_bundle.contains(&member.get_trick().id)
+            })
+            .map(|member| {
+                (
+                    member.get_trick().id.as_str(),
+                    "not installed by this bundle",
+                )
+            })
+            .collect();
+        summarize(results, &skipped)
+    }
+
+    fn update(&self) -> DeckResult<ActionSuccess> {
+        let updateable: Vec<&DynTrickProvider> = self
+            .members
+            .iter()
+            .filter(|member| member.is_updateable())
+            .collect();
+        let results = self.for_each_member("Updating", &updateable, |member| member.update());
+        summarize(results, &[])
+    }
+
+    fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
+        not_possible("Bundles cannot be added to Steam, add their members instead.")
+    }
+}
+
+fn get_state_file(state_dir: &Path, bundle_id: &str) -> PathBuf {
+    state_dir.join(format!("{bundle_id}.json"))
+}
+
+fn read_installed_by_bundle(ctx: &SpecificExecutionContext, state_dir: &Path) -> Vec<TrickID> {
+    let state_file = get_state_file(state_dir, &ctx.trick.id);
+    let Ok(contents) = fs::read_to_string(&state_file) else {
+        // Nothing has been installed by this bundle yet
+        return vec![];
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|err| {
+        warn!(
+            ctx,
+            "Could not parse bundle state file \"{}\", ignoring it: {err}",
+            state_file.display()
+        );
+        vec![]
+    })
+}
+
+fn write_installed_by_bundle(
+    state_dir: &Path,
+    bundle_id: &str,
+    installed_by_bundle: &[TrickID],
+) -> Result<(), DynamicError> {
+    fs::create_dir_all(state_dir)?;
+    fs::write(
+        get_state_file(state_dir, bundle_id),
+        serde_json::to_string_pretty(installed_by_bundle)?,
+    )?;
+    Ok(())
+}
+
+// The configs of every trick which is a member of some bundle, since providers are otherwise
+// only given their own trick.
+#[derive(Debug, Clone, Default)]
+pub struct BundleSystemContext {
+    member_tricks: HashMap<TrickID, Trick>,
+}
+
+impl BundleSystemContext {
+    pub(crate) fn gather_with(tricks_loader: &LoadedConfig) -> Self {
+        let member_tricks = tricks_loader
+            .get_all_tricks()
+            .filter_map(|(_, trick)| match &trick.provider_config {
+                ProviderConfig::Bundle(bundle) => Some(&bundle.members),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|member_id| tricks_loader.get_trick(member_id).ok())
+            .map(|trick| (trick.id.clone(), trick.clone()))
+            .collect();
+        Self { member_tricks }
+    }
+}
+
+// Members can only be looked up once every trick is known, so this is checked when the whole
+// config is loaded rather than in check_config.
+pub(crate) fn check_bundle_members(
+    bundle_id: &str,
+    bundle: &Bundle,
+    tricks: &BTreeMap<TrickID, Trick>,
+) -> DeckResult<()> {
+    for member_id in &bundle.members {
+        let member = tricks.get(member_id).ok_or_else(|| {
+            KnownError::ProviderNotImplemented(format!(
+                "Bundle \"{bundle_id}\" contains \"{member_id}\", which is not a known trick."
+            ))
+        })?;
+        // Keeps us from recursing forever on bundles which contain each other
+        if matches!(member.provider_config, ProviderConfig::Bundle(_)) {
+            return Err(KnownError::ProviderNotImplemented(format!(
+                "Bundle \"{bundle_id}\" contains \"{member_id}\", but bundles cannot contain \
+                 other bundles."
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub(crate) struct BundleRegistration;
+
+impl ProviderRegistration for BundleRegistration {
+    fn type_tag(&self) -> &'static str {
+        "bundle"
+    }
+
+    // Members are tricks in their own right, so they're already updated by themselves
+    fn get_update_all_strategy(&self, _ctx: &GeneralExecutionContext) -> UpdateAllStrategy {
+        UpdateAllStrategy::Skip
+    }
+
+    fn gather_context(
+        &self,
+        _ctx: &ExecutionContext,
+        tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(BundleSystemContext::gather_with(
+            tricks_loader,
+        ))))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<Bundle>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        let ProviderConfig::Bundle(bundle) = &ctx.trick.provider_config else {
+            return Err(mismatched_config(
+                self.type_tag(),
+                &ctx.trick.provider_config,
+            ));
+        };
+        let bundle_ctx: BundleSystemContext = full_ctx.get_provider_context(self.type_tag());
+
+        let mut members = vec![];
+        for member_id in &bundle.members {
+            let member_trick = bundle_ctx
+                .member_tricks
+                .get(member_id)
+                .ok_or_else(|| KnownError::UnknownTrickID(member_id.clone()))?;
+            // Already checked when the config was loaded, see check_bundle_members
+            if matches!(member_trick.provider_config, ProviderConfig::Bundle(_)) {
+                return Err(KnownError::ProviderNotImplemented(format!(
+                    "Bundles cannot contain other bundles (\"{member_id}\")."
+                )));
+            }
+            members.push(DynTrickProvider::new(
+                &get_member_ctx(ctx, member_trick, full_ctx),
+                full_ctx,
+            ));
+        }
+
+        Ok(Box::new(BundleProvider::new(
+            ctx.clone(),
+            members,
+            get_bundle_state_dir(),
+        )))
+    }
+}
+
+// Members log to their own channel, so their output shows up in the same place as it would if
+// they were installed on their own.
+fn get_member_ctx(
+    ctx: &SpecificExecutionContext,
+    member_trick: &Trick,
+    full_ctx: &FullSystemContext,
+) -> SpecificExecutionContext {
+    ctx.for_other_trick(
+        member_trick.clone(),
+        full_ctx.is_installing(&member_trick.id),
+        full_ctx.is_added_to_steam(&member_trick.id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::flatpak::FlatpakSystemContext;
+
+    fn flatpak_trick(id: &str) -> Trick {
+        Trick {
+            id: id.into(),
+            provider_config: ProviderConfig::Flatpak(Flatpak {
+                id: id.into(),
+                env: None,
+            }),
+            ..Trick::default()
+        }
+    }
+
+    fn get_state_dir(test_name: &str) -> PathBuf {
+        let state_dir = std::env::temp_dir().join(format!(
+            "decktricks-bundle-test-{}-{test_name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&state_dir);
+        state_dir
+    }
+
+    // A bundle of two flatpaks, where "org.already.There" was installed by hand
+    fn get_bundle(runner: Arc<MockTestActualRunner>, state_dir: PathBuf) -> BundleProvider {
+        let full_ctx = FullSystemContext::default().with_provider_context(
+            "flatpak",
+            FlatpakSystemContext {
+                installed: vec!["org.already.There".into()],
+                running: vec![],
+                has_user_flathub: false,
+            },
+        );
+        let bundle_trick = Trick {
+            id: "essentials".into(),
+            provider_config: ProviderConfig::Bundle(Bundle {
+                members: vec!["org.already.There".into(), "org.not.There".into()],
+            }),
+            ..Trick::default()
+        };
+        let ctx = SpecificExecutionContext::test_with_runner(bundle_trick, runner);
+        let members = ["org.already.There", "org.not.There"]
+            .into_iter()
+            .map(|id| {
+                let member_ctx = get_member_ctx(&ctx, &flatpak_trick(id), &full_ctx);
+                DynTrickProvider::new(&member_ctx, &full_ctx)
+            })
+            .collect();
+        BundleProvider::new(ctx, members, state_dir)
+    }
+
+    fn config_with_bundle_members(members: &str) -> String {
+        format!(
+            r#"{{
+                "settings": {{
+                    "controller_layout_id": "",
+                    "add_to_steam_on_install_where_applicable": false
+                }},
+                "known_categories": [],
+                "tricks": [
+                    {{
+                        "id": "lutris",
+                        "provider_config": {{ "type": "flatpak", "id": "net.lutris.Lutris" }},
+                        "display_name": "Lutris",
+                        "description": "",
+                        "categories": []
+                    }},
+                    {{
+                        "id": "other-bundle",
+                        "provider_config": {{ "type": "bundle", "members": [] }},
+                        "display_name": "Other Bundle",
+                        "description": "",
+                        "categories": []
+                    }},
+                    {{
+                        "id": "essentials",
+                        "provider_config": {{ "type": "bundle", "members": [{members}] }},
+                        "display_name": "Essentials",
+                        "description": "",
+                        "categories": []
+                    }}
+                ]
+            }}"#
+        )
+    }
+
+    #[test]
+    fn bad_members_fail_the_config_load() {
+        assert!(LoadedConfig::try_from(config_with_bundle_members(r#""lutris""#).as_str()).is_ok());
+
+        for (members, expected) in [
+            (r#""lutris", "not-a-trick""#, "not a known trick"),
+            (r#""lutris", "other-bundle""#, "cannot contain other bundles"),
+        ] {
+            match LoadedConfig::try_from(config_with_bundle_members(members).as_str()) {
+                Err(KnownError::ProviderNotImplemented(msg)) => assert!(msg.contains(expected)),
+                other => panic!("Expected the load to fail, got: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn install_only_installs_missing_members() -> DeckResult<()> {
+        let state_dir = get_state_dir("install");
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .withf(|cmd| cmd.args == ["install", "-y", "org.not.There"])
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let bundle = get_bundle(Arc::new(mock), state_dir.clone());
+        assert!(!bundle.is_installed());
+        assert!(bundle.is_installable());
+        assert!(!bundle.is_uninstallable());
+
+        let message = bundle.install()?.get_message_or_blank();
+        assert!(message.contains("org.not.There: success"));
+        assert!(message.contains("org.already.There: skipped (already installed)"));
+
+        let ctx = bundle.get_execution_context();
+        assert_eq!(
+            vec!["org.not.There".to_string()],
+            read_installed_by_bundle(ctx, &state_dir)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn uninstall_only_removes_members_installed_by_bundle() -> DeckResult<()> {
+        let state_dir = get_state_dir("uninstall");
+        write_installed_by_bundle(&state_dir, "essentials", &["org.already.There".into()])
+            .map_err(|e| KnownError::TestError(e.to_string()))?;
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .withf(|cmd| cmd.args == ["uninstall", "-y", "org.already.There"])
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let bundle = get_bundle(Arc::new(mock), state_dir.clone());
+        assert!(bundle.is_uninstallable());
+        bundle.uninstall()?;
+
+        let ctx = bundle.get_execution_context();
+        assert!(read_installed_by_bundle(ctx, &state_dir).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_members_are_reported_individually() {
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run().returning(|cmd| {
+            Ok(SysCommandResult::fake_for_test(
+                &cmd.cmd,
+                cmd.args.iter().map(String::as_str).collect(),
+                1,
+                "",
+                "no such package",
+            ))
+        });
+
+        let bundle = get_bundle(Arc::new(mock), get_state_dir("failure"));
+        match bundle.install() {
+            Err(KnownError::BundleMemberFailures(summary, failures)) => {
+                assert_eq!(1, failures.len());
+                assert_eq!("org.not.There", failures[0].0);
+                // Members which didn't fail are still in the summary
+                assert!(summary.contains("org.not.There: failed"));
+                assert!(summary.contains("org.already.There: skipped (already installed)"));
+            }
+            other => panic!("Expected per-member failures, got: {other:?}"),
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use crate::download::DownloadVerification;
 use crate::prelude::*;
-use crate::utils::fetch_and_prep_remote_executable;
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+};
+use crate::providers::system_context::FullSystemContext;
+use std::sync::Arc;
+use crate::utils::fetch_and_run_remote_executable;
 
 // TODO: detect if on steam deck or not, and *do not mark as installable if not on steam deck*
 
@@ -10,12 +16,21 @@ const DECKY_INSTALLER_TEMP_FILENAME: &str = "/tmp/decky_installer.sh";
 pub struct DeckyInstallerProvider {
     ctx: SpecificExecutionContext,
     decky_ctx: DeckySystemContext,
+    verification: DownloadVerification,
 }
 
 impl DeckyInstallerProvider {
     #[must_use]
-    pub(super) fn new(ctx: SpecificExecutionContext, decky_ctx: DeckySystemContext) -> Self {
-        Self { ctx, decky_ctx }
+    pub(super) fn new(
+        ctx: SpecificExecutionContext,
+        decky_ctx: DeckySystemContext,
+        verification: DownloadVerification,
+    ) -> Self {
+        Self {
+            ctx,
+            decky_ctx,
+            verification,
+        }
     }
 }
 
@@ -32,10 +47,12 @@ impl DeckySystemContext {
             // default to false if anything goes wrong.
             || ctx
                 .sys_command("/usr/bin/systemctl", ["is-enabled", "plugin_loader"])
+                .read_only()
                 .run()
                 .is_ok_and(|res| res.ran_successfully()),
             || ctx
                 .sys_command("/usr/bin/systemctl", ["is-active", "plugin_loader"])
+                .read_only()
                 .run()
                 .is_ok_and(|res| res.ran_successfully())
         );
@@ -47,6 +64,42 @@ impl DeckySystemContext {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct DeckyInstallerRegistration;
+
+impl ProviderRegistration for DeckyInstallerRegistration {
+    fn type_tag(&self) -> &'static str {
+        "decky-installer"
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(DeckySystemContext::gather_with(ctx))))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<DeckyInstaller>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        match &ctx.trick.provider_config {
+            ProviderConfig::DeckyInstaller(config) => Ok(Box::new(DeckyInstallerProvider::new(
+                ctx.clone(),
+                full_ctx.get_provider_context(self.type_tag()),
+                config.verification.clone(),
+            ))),
+            other => Err(mismatched_config(self.type_tag(), other)),
+        }
+    }
+}
+
 impl TrickProvider for DeckyInstallerProvider {}
 
 impl ProviderChecks for DeckyInstallerProvider {
@@ -74,6 +127,10 @@ impl ProviderChecks for DeckyInstallerProvider {
         self.is_installed()
     }
 
+    fn is_reinstallable(&self) -> bool {
+        self.is_installed()
+    }
+
     fn is_runnable(&self) -> bool {
         false
     }
@@ -104,16 +161,19 @@ impl ProviderActions for DeckyInstallerProvider {
 
     fn install(&self) -> DeckResult<ActionSuccess> {
         let _ = &self.ctx.sys_command("xhost", vec!["+"]).run();
-        fetch_and_prep_remote_executable(
+        fetch_and_run_remote_executable(
             &self.ctx,
             DECKY_DOWNLOAD_URL,
+            &self.verification,
             DECKY_INSTALLER_TEMP_FILENAME,
-        )?
-        .env(INSTALLING_ENV_STRING, self.ctx.trick.id.as_ref())
-        .run()?;
+        )?;
         success!("Decky installed successfully!")
     }
 
+    fn reinstall(&self, delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        rerun_installer(&self.ctx, delete_user_data, || self.install())
+    }
+
     fn run(&self) -> DeckResult<ActionSuccess> {
         not_possible("Decky is not runnable!")
     }
@@ -126,12 +186,3 @@ impl ProviderActions for DeckyInstallerProvider {
         not_possible("Decky is automatically added to Steam.")
     }
 }
-
-#[derive(Debug)]
-pub(crate) struct DeckyInstallerGeneralProvider;
-impl GeneralProvider for DeckyInstallerGeneralProvider {
-    fn update_all(&self) -> DeckResult<ActionSuccess> {
-        // TODO: run the decky update command here (not the installer directly)
-        success!()
-    }
-}
@@ -1,7 +1,13 @@
+use crate::download::DownloadVerification;
 use crate::prelude::*;
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+};
+use crate::providers::system_context::FullSystemContext;
+use std::sync::Arc;
 use crate::utils::get_running_pids_exact;
 use crate::utils::kill_pids;
-use crate::utils::{exists_and_executable, get_homedir, fetch_and_prep_remote_executable};
+use crate::utils::{exists_and_executable, get_homedir, fetch_and_run_remote_executable};
 
 // TODO: determine differences between "running" (games being played) and "running the installer"
 // TODO: "installed" is $HOME/Applications/EmuDeck.AppImage
@@ -20,12 +26,21 @@ pub(crate) fn get_emudeck_binary_path() -> String {
 pub struct EmuDeckInstallerProvider {
     ctx: SpecificExecutionContext,
     emu_ctx: EmuDeckSystemContext,
+    verification: DownloadVerification,
 }
 
 impl EmuDeckInstallerProvider {
     #[must_use]
-    pub(super) fn new(ctx: SpecificExecutionContext, emu_ctx: EmuDeckSystemContext) -> Self {
-        Self { ctx, emu_ctx }
+    pub(super) fn new(
+        ctx: SpecificExecutionContext,
+        emu_ctx: EmuDeckSystemContext,
+        verification: DownloadVerification,
+    ) -> Self {
+        Self {
+            ctx,
+            emu_ctx,
+            verification,
+        }
     }
 }
 
@@ -56,6 +71,42 @@ impl EmuDeckSystemContext {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct EmuDeckInstallerRegistration;
+
+impl ProviderRegistration for EmuDeckInstallerRegistration {
+    fn type_tag(&self) -> &'static str {
+        "emu-deck-installer"
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(EmuDeckSystemContext::gather_with(ctx)?)))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<EmuDeckInstaller>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        match &ctx.trick.provider_config {
+            ProviderConfig::EmuDeckInstaller(config) => Ok(Box::new(EmuDeckInstallerProvider::new(
+                ctx.clone(),
+                full_ctx.get_provider_context(self.type_tag()),
+                config.verification.clone(),
+            ))),
+            other => Err(mismatched_config(self.type_tag(), other)),
+        }
+    }
+}
+
 impl TrickProvider for EmuDeckInstallerProvider {}
 
 impl ProviderChecks for EmuDeckInstallerProvider {
@@ -79,9 +130,13 @@ impl ProviderChecks for EmuDeckInstallerProvider {
         self.is_running()
     }
 
+    // TODO: allow once update() is implemented
     fn is_updateable(&self) -> bool {
-        // TODO: check
-        self.is_installed()
+        false
+    }
+
+    fn is_reinstallable(&self) -> bool {
+        self.is_installed() && !self.is_running()
     }
 
     fn is_runnable(&self) -> bool {
@@ -113,16 +168,19 @@ impl ProviderActions for EmuDeckInstallerProvider {
     }
 
     fn install(&self) -> DeckResult<ActionSuccess> {
-        fetch_and_prep_remote_executable(
+        fetch_and_run_remote_executable(
             &self.ctx,
             EMUDECK_DOWNLOAD_URL,
+            &self.verification,
             EMUDECK_INSTALLER_TEMP_FILENAME,
-        )?
-        .env(INSTALLING_ENV_STRING, self.ctx.trick.id.as_ref())
-        .run()?;
+        )?;
         success!("EmuDeck installer installed successfully! Run now to fully install EmuDeck.")
     }
 
+    fn reinstall(&self, delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        rerun_installer(&self.ctx, delete_user_data, || self.install())
+    }
+
     fn run(&self) -> DeckResult<ActionSuccess> {
         self.ctx.sys_command_no_args(get_emudeck_binary_path())
             .enable_live_logging()
@@ -135,7 +193,7 @@ impl ProviderActions for EmuDeckInstallerProvider {
     }
 
     fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
-        add_to_steam(&AddToSteamTarget::Specific(TrickAddToSteamContext::try_from(
+        add_to_steam(&self.ctx, &AddToSteamTarget::Specific(TrickAddToSteamContext::try_from(
             &self.ctx.trick,
         )?))
     }
@@ -1,7 +1,16 @@
 use super::flatpak_helpers::{
-    get_has_user_flathub, get_installed_flatpak_applications, get_running_flatpak_applications,
+    get_flatpak_applications_with_updates, get_has_user_flathub,
+    get_installed_flatpak_applications, get_running_flatpak_applications,
 };
+use crate::locks::{FLATPAK_PROVIDER_LOCK, LockGuard, LockScope, acquire_lock};
 use crate::prelude::*;
+use crate::progress::parse_flatpak_progress;
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+    UpdateAllStrategy,
+};
+use crate::providers::system_context::FullSystemContext;
+use std::sync::Arc;
 
 const FLATPAK_SYSTEM_COMMAND: &str = "flatpak";
 
@@ -9,6 +18,7 @@ type FlatpakID = String;
 #[derive(Debug)]
 pub(crate) struct FlatpakProvider {
     id: FlatpakID,
+    env: EnvVars,
     flatpak_ctx: FlatpakSystemContext,
     ctx: SpecificExecutionContext,
 }
@@ -20,8 +30,10 @@ impl FlatpakProvider {
         ctx: SpecificExecutionContext,
     ) -> Self {
         let id = flatpak.id.clone();
+        let env = flatpak.env.clone().unwrap_or_default();
         Self {
             id,
+            env,
             flatpak_ctx,
             ctx,
         }
@@ -51,6 +63,46 @@ impl FlatpakSystemContext {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct FlatpakRegistration;
+
+impl ProviderRegistration for FlatpakRegistration {
+    fn type_tag(&self) -> &'static str {
+        "flatpak"
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(FlatpakSystemContext::gather_with(ctx)?)))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<Flatpak>(provider_config)
+    }
+
+    fn get_update_all_strategy(&self, ctx: &GeneralExecutionContext) -> UpdateAllStrategy {
+        UpdateAllStrategy::Global(Box::new(FlatpakGeneralProvider::new(ctx.clone())))
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        match &ctx.trick.provider_config {
+            ProviderConfig::Flatpak(flatpak) => Ok(Box::new(FlatpakProvider::new(
+                flatpak,
+                full_ctx.get_provider_context(self.type_tag()),
+                ctx.clone(),
+            ))),
+            other => Err(mismatched_config(self.type_tag(), other)),
+        }
+    }
+}
+
 impl TrickProvider for FlatpakProvider {}
 
 impl FlatpakProvider {
@@ -67,8 +119,11 @@ impl FlatpakProvider {
     // NOTE: Can handle/track child pid status here, but
     // `flatpak ps` gives us that easily and authoritatively.
     fn flatpak_run(&self) -> DeckResult<ActionSuccess> {
+        // Everything after the app id goes to the app itself
+        let mut args = get_flatpak_run_args(&self.id, &self.env);
+        args.extend_from_slice(self.ctx.get_extra_run_args());
         self.ctx
-            .sys_command(FLATPAK_SYSTEM_COMMAND, ["run", &self.id])
+            .sys_command(FLATPAK_SYSTEM_COMMAND, args)
             // NOTE: We have to force a pty here, since certain flatpak packages (protonup-qt)
             //       will buffer stdout until they end, if they think they're not running
             //       in an interactive terminal session.
@@ -79,17 +134,25 @@ impl FlatpakProvider {
     }
 
     fn flatpak_install(&self) -> DeckResult<ActionSuccess> {
+        self.flatpak_install_with(&[])
+    }
+
+    fn flatpak_install_with(&self, extra_args: &[&str]) -> DeckResult<ActionSuccess> {
+        let mut args = vec!["install", "-y"];
         // Prefer user installation, if available
         // TODO: see how needed this is outside of SteamOS
-        let args = if self.flatpak_ctx.has_user_flathub {
-            vec!["install", "-y", "--user", &self.id]
-        } else {
-            vec!["install", "-y", &self.id]
-        };
+        if self.flatpak_ctx.has_user_flathub {
+            args.push("--user");
+        }
+        args.extend(extra_args);
+        args.push(&self.id);
 
         self.ctx
             .sys_command(FLATPAK_SYSTEM_COMMAND, args)
+            // NOTE: flatpak only draws progress when it thinks it's in a terminal
+            .force_pty()
             .enable_live_logging()
+            .parse_progress(parse_flatpak_progress)
             .env(INSTALLING_ENV_STRING, self.ctx.trick.id.as_ref())
             .run()?
             .as_success()
@@ -103,6 +166,34 @@ impl FlatpakProvider {
             .as_success()
     }
 
+    // `--reinstall` leaves ~/.var/app alone, so wiping user data means uninstalling first.
+    fn flatpak_reinstall(&self, delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        if !delete_user_data {
+            return self.flatpak_install_with(&["--reinstall"]);
+        }
+
+        self.ctx
+            .sys_command(
+                FLATPAK_SYSTEM_COMMAND,
+                ["uninstall", "-y", "--delete-data", &self.id],
+            )
+            .enable_live_logging()
+            .env(INSTALLING_ENV_STRING, self.ctx.trick.id.as_ref())
+            .run()?
+            .as_success()?;
+        self.flatpak_install()
+            .map_err(|e| KnownError::ReinstallFailedAfterUninstall(Box::new(e)))
+    }
+
+    // Flatpak only lets one install or update run at a time anyway, and fails the others
+    fn lock(&self, doing: &str) -> DeckResult<LockGuard> {
+        acquire_lock(
+            &self.ctx,
+            &LockScope::Provider(FLATPAK_PROVIDER_LOCK),
+            &format!("{doing} {}", self.id),
+        )
+    }
+
     fn flatpak_kill(&self) -> DeckResult<ActionSuccess> {
         self.ctx
             .sys_command(FLATPAK_SYSTEM_COMMAND, ["kill", &self.id])
@@ -114,12 +205,26 @@ impl FlatpakProvider {
     fn flatpak_update(&self) -> DeckResult<ActionSuccess> {
         self.ctx
             .sys_command(FLATPAK_SYSTEM_COMMAND, ["update", "-y", &self.id])
+            .force_pty()
             .enable_live_logging()
+            .parse_progress(parse_flatpak_progress)
             .run()?
             .as_success()
     }
 }
 
+// Environment variables are passed with --env= so that they are reliably set inside the sandbox.
+// This is also used for the launch options when adding a flatpak to Steam (see
+// TrickAddToSteamContext), so launches from Steam get the same environment.
+pub(crate) fn get_flatpak_run_args(id: &str, env: &EnvVars) -> Vec<String> {
+    let mut args = vec!["run".to_string()];
+    for (varname, value) in env {
+        args.push(format!("--env={varname}={value}"));
+    }
+    args.push(id.into());
+    args
+}
+
 // TODO: remove all test blocks for checks
 
 impl ProviderChecks for FlatpakProvider {
@@ -155,6 +260,10 @@ impl ProviderChecks for FlatpakProvider {
         self.is_installed()
     }
 
+    fn is_reinstallable(&self) -> bool {
+        self.is_installed() && !self.is_running()
+    }
+
     fn is_addable_to_steam(&self) -> bool {
         self.is_installed()
     }
@@ -166,15 +275,23 @@ impl ProviderChecks for FlatpakProvider {
 
 impl ProviderActions for FlatpakProvider {
     fn uninstall(&self) -> DeckResult<ActionSuccess> {
+        let _lock = self.lock("uninstalling")?;
         self.flatpak_uninstall()?;
         success!("\"{}\" uninstalled successfully.", self.id)
     }
 
     fn install(&self) -> DeckResult<ActionSuccess> {
+        let _lock = self.lock("installing")?;
         self.flatpak_install()?;
         success!("\"{}\" installed successfully.", self.id)
     }
 
+    fn reinstall(&self, delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        let _lock = self.lock("reinstalling")?;
+        self.flatpak_reinstall(delete_user_data)?;
+        success!("\"{}\" reinstalled successfully.", self.id)
+    }
+
     fn run(&self) -> DeckResult<ActionSuccess> {
         self.flatpak_run()
     }
@@ -185,14 +302,15 @@ impl ProviderActions for FlatpakProvider {
     }
 
     fn update(&self) -> DeckResult<ActionSuccess> {
+        let _lock = self.lock("updating")?;
         self.flatpak_update()?;
         success!()
     }
 
     fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
-        add_to_steam(&AddToSteamTarget::Specific(
-            TrickAddToSteamContext::try_from(&self.ctx.trick)?,
-        ))
+        let target = TrickAddToSteamContext::try_from(&self.ctx.trick)?
+            .with_extra_args(self.ctx.get_extra_run_args());
+        add_to_steam(&self.ctx, &AddToSteamTarget::Specific(target))
     }
 }
 
@@ -209,18 +327,39 @@ impl FlatpakGeneralProvider {
 
 impl GeneralProvider for FlatpakGeneralProvider {
     fn update_all(&self) -> DeckResult<ActionSuccess> {
-        // TODO: when running in parallel, collect errors for each portion
-
         // IMPORTANT: for global flatpak update -y, you MUST run it twice to remove unused runtimes.
-        self.ctx
-            .sys_command(FLATPAK_SYSTEM_COMMAND, ["update", "-y"])
-            .run()?;
-        self.ctx
-            .sys_command(FLATPAK_SYSTEM_COMMAND, ["update", "-y"])
-            .run()?;
+        // Both runs are one step as far as anyone else is concerned, so either failing fails it.
+        let _lock = acquire_lock(
+            &self.ctx,
+            &LockScope::Provider(FLATPAK_PROVIDER_LOCK),
+            "updating all flatpaks",
+        )?;
+        for _ in 0..2 {
+            self.ctx
+                .sys_command(FLATPAK_SYSTEM_COMMAND, ["update", "-y"])
+                .force_pty()
+                .enable_live_logging()
+                .parse_progress(parse_flatpak_progress)
+                .run()?
+                .as_success()?;
+        }
 
         success!("Flatpak update run successfully!")
     }
+
+    fn get_tricks_with_updates(&self, tricks: &[&Trick]) -> DeckResult<Option<Vec<TrickID>>> {
+        let with_updates = get_flatpak_applications_with_updates(&self.ctx)?;
+        Ok(Some(
+            tricks
+                .iter()
+                .filter(|trick| match &trick.provider_config {
+                    ProviderConfig::Flatpak(flatpak) => with_updates.contains(&flatpak.id),
+                    _ => false,
+                })
+                .map(|trick| trick.id.clone())
+                .collect(),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -231,7 +370,10 @@ mod tests {
 
     impl Flatpak {
         pub(crate) fn new<S: Into<String>>(id: S) -> Self {
-            Flatpak { id: id.into() }
+            Flatpak {
+                id: id.into(),
+                env: None,
+            }
         }
     }
 
@@ -269,6 +411,37 @@ mod tests {
         assert!(!provider.is_installed());
     }
 
+    #[test]
+    fn test_run_args_include_env() {
+        let env = EnvVars::from([("FOO".into(), "bar baz".into())]);
+        assert_eq!(
+            get_flatpak_run_args("org.test.Package", &env),
+            vec!["run", "--env=FOO=bar baz", "org.test.Package"]
+        );
+    }
+
+    #[test]
+    fn run_appends_extra_args() {
+        let expected = ExecutionContext::specific_for_test().sys_command(
+            FLATPAK_SYSTEM_COMMAND,
+            ["run", "org.test.Package", "/roms/some game.sfc"],
+        );
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let mut ctx =
+            SpecificExecutionContext::test_with_runner(Trick::test(), std::sync::Arc::new(mock));
+        ctx.action = SpecificAction::Run {
+            id: "org.test.Package".into(),
+            extra_args: vec!["/roms/some game.sfc".into()],
+        };
+        assert!(fpak_prov("org.test.Package", ctx).run().is_ok());
+    }
+
     #[test]
     fn test_installable() {
         let ctx = get_execution_context();
@@ -335,6 +508,99 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reinstall_pkg() {
+        let test_ctx = ExecutionContext::specific_for_test();
+        let mut keep_data = test_ctx.sys_command(
+            FLATPAK_SYSTEM_COMMAND,
+            ["install", "-y", "--reinstall", "installed_package"],
+        );
+        keep_data.env(INSTALLING_ENV_STRING, "trick_for_test");
+        let mut delete_data = test_ctx.sys_command(
+            FLATPAK_SYSTEM_COMMAND,
+            ["uninstall", "-y", "--delete-data", "installed_package"],
+        );
+        delete_data.env(INSTALLING_ENV_STRING, "trick_for_test");
+        let mut fresh_install =
+            test_ctx.sys_command(FLATPAK_SYSTEM_COMMAND, ["install", "-y", "installed_package"]);
+        fresh_install.env(INSTALLING_ENV_STRING, "trick_for_test");
+
+        let mut mock = MockTestActualRunner::new();
+        let mut seq = mockall::Sequence::new();
+        for expected in [keep_data, delete_data, fresh_install] {
+            mock.expect_run()
+                .times(1)
+                .in_sequence(&mut seq)
+                .with(predicate::eq(expected))
+                .returning(|_| Ok(SysCommandResult::fake_success()));
+        }
+
+        let ctx =
+            SpecificExecutionContext::test_with_runner(Trick::test(), std::sync::Arc::new(mock));
+        let provider = fpak_prov("installed_package", ctx);
+        assert!(provider.is_reinstallable());
+        assert!(provider.reinstall(false).is_ok());
+        assert!(provider.reinstall(true).is_ok());
+    }
+
+    #[test]
+    fn test_reinstall_failing_after_uninstall() {
+        let test_ctx = ExecutionContext::specific_for_test();
+        let mut delete_data = test_ctx.sys_command(
+            FLATPAK_SYSTEM_COMMAND,
+            ["uninstall", "-y", "--delete-data", "installed_package"],
+        );
+        delete_data.env(INSTALLING_ENV_STRING, "trick_for_test");
+        let install_args = vec!["install", "-y", "installed_package"];
+        let mut fresh_install = test_ctx.sys_command(FLATPAK_SYSTEM_COMMAND, install_args.clone());
+        fresh_install.env(INSTALLING_ENV_STRING, "trick_for_test");
+        let failure = SysCommandResult::fake_for_test(
+            FLATPAK_SYSTEM_COMMAND,
+            install_args,
+            1,
+            "NO NETWORK LOL",
+            "",
+        );
+
+        let mut mock = MockTestActualRunner::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_run()
+            .times(1)
+            .in_sequence(&mut seq)
+            .with(predicate::eq(delete_data))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+        mock.expect_run()
+            .times(1)
+            .in_sequence(&mut seq)
+            .with(predicate::eq(fresh_install))
+            .returning(move |_| Ok(failure.clone()));
+
+        let ctx =
+            SpecificExecutionContext::test_with_runner(Trick::test(), std::sync::Arc::new(mock));
+        let provider = fpak_prov("installed_package", ctx);
+        match provider.reinstall(true) {
+            Err(KnownError::ReinstallFailedAfterUninstall(err)) => {
+                assert!(matches!(*err, KnownError::SystemCommandFailed(_)));
+            }
+            res => panic!("reinstall should have failed after uninstalling: {res:?}"),
+        }
+    }
+
+    #[test]
+    fn installs_and_updates_get_a_pty_for_progress() {
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(2)
+            .withf(|cmd| cmd.pty_needed && cmd.progress_parser.is_some())
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let ctx =
+            SpecificExecutionContext::test_with_runner(Trick::test(), std::sync::Arc::new(mock));
+        let provider = fpak_prov("installed_package", ctx);
+        assert!(provider.install().is_ok());
+        assert!(provider.update().is_ok());
+    }
+
     #[test]
     fn test_failed_to_install_pkg() {
         let cmd = FLATPAK_SYSTEM_COMMAND;
@@ -1,4 +1,10 @@
 use crate::prelude::*;
+use std::time::Duration;
+
+// These only read state, so if they take this long something is stuck (the GUI waits on them)
+const FLATPAK_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+// This one has to talk to the remotes
+const FLATPAK_REMOTE_QUERY_TIMEOUT: Duration = Duration::from_mins(2);
 
 pub(super) fn get_running_flatpak_applications(ctx: &impl ExecCtx) -> DeckResult<Vec<String>> {
     let ps_output = flatpak_ps(ctx);
@@ -30,6 +36,13 @@ pub(super) fn get_installed_flatpak_applications(ctx: &impl ExecCtx) -> DeckResu
     }
 }
 
+pub(super) fn get_flatpak_applications_with_updates(
+    ctx: &impl ExecCtx,
+) -> DeckResult<Vec<String>> {
+    let text = flatpak_remote_ls_updates(ctx)?.get_message_or_blank();
+    Ok(text.lines().map(|line| line.trim().to_string()).collect())
+}
+
 pub(super) fn get_has_user_flathub(ctx: &impl ExecCtx) -> DeckResult<bool> {
     let remotes_output = flatpak_remotes(ctx);
 
@@ -57,13 +70,28 @@ pub(super) fn get_has_user_flathub(ctx: &impl ExecCtx) -> DeckResult<bool> {
 
 fn flatpak_remotes(ctx: &impl ExecCtx) -> DeckResult<ActionSuccess> {
     ctx.sys_command("flatpak", ["remotes", "--columns=name,options"])
+        .read_only()
+        .timeout(FLATPAK_QUERY_TIMEOUT)
         .run()?
         .as_success()
 }
 
+fn flatpak_remote_ls_updates(ctx: &impl ExecCtx) -> DeckResult<ActionSuccess> {
+    ctx.sys_command(
+        "flatpak",
+        ["remote-ls", "--updates", "--app", "--columns=application"],
+    )
+    .read_only()
+    .timeout(FLATPAK_REMOTE_QUERY_TIMEOUT)
+    .run()?
+    .as_success()
+}
+
 fn flatpak_list(ctx: &impl ExecCtx) -> DeckResult<ActionSuccess> {
     // NOTE: when debugging, to see what this actually sees here, pipe flatpak list to cat.
     ctx.sys_command("flatpak", ["list", "--app", "--columns=application"])
+        .read_only()
+        .timeout(FLATPAK_QUERY_TIMEOUT)
         .run()?
         .as_success()
 }
@@ -71,6 +99,8 @@ fn flatpak_list(ctx: &impl ExecCtx) -> DeckResult<ActionSuccess> {
 pub(crate) fn flatpak_ps(ctx: &impl ExecCtx) -> DeckResult<ActionSuccess> {
     // NOTE: when debugging, to see what this actually sees here, pipe flatpak ps to cat.
     ctx.sys_command("flatpak", ["ps", "--columns=application"])
+        .read_only()
+        .timeout(FLATPAK_QUERY_TIMEOUT)
         .run()?
         .as_success()
 }
@@ -1,10 +1,15 @@
 use crate::utils::kill_pids;
 use crate::utils::pgrep;
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
+use crate::download::DownloadVerification;
 use crate::prelude::*;
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+};
+use crate::providers::system_context::FullSystemContext;
 use crate::utils::exists_and_executable;
-use crate::utils::fetch_and_prep_remote_executable;
+use crate::utils::fetch_and_run_remote_executable;
 use crate::utils::get_homedir;
 
 const GEFORCE_INSTALLER_DOWNLOAD_URL: &str =
@@ -27,12 +32,21 @@ static GEFORCE_PGREP_STRING: LazyLock<String> = LazyLock::new(|| {
 pub struct GeForceInstallerProvider {
     ctx: SpecificExecutionContext,
     geforce_ctx: GeForceSystemContext,
+    verification: DownloadVerification,
 }
 
 impl GeForceInstallerProvider {
     #[must_use]
-    pub(super) fn new(ctx: SpecificExecutionContext, geforce_ctx: GeForceSystemContext) -> Self {
-        Self { ctx, geforce_ctx }
+    pub(super) fn new(
+        ctx: SpecificExecutionContext,
+        geforce_ctx: GeForceSystemContext,
+        verification: DownloadVerification,
+    ) -> Self {
+        Self {
+            ctx,
+            geforce_ctx,
+            verification,
+        }
     }
 }
 
@@ -56,6 +70,42 @@ impl GeForceSystemContext {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct GeForceInstallerRegistration;
+
+impl ProviderRegistration for GeForceInstallerRegistration {
+    fn type_tag(&self) -> &'static str {
+        "ge-force-installer"
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(GeForceSystemContext::gather_with(ctx))))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<GeForceInstaller>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        match &ctx.trick.provider_config {
+            ProviderConfig::GeForceInstaller(config) => Ok(Box::new(GeForceInstallerProvider::new(
+                ctx.clone(),
+                full_ctx.get_provider_context(self.type_tag()),
+                config.verification.clone(),
+            ))),
+            other => Err(mismatched_config(self.type_tag(), other)),
+        }
+    }
+}
+
 impl TrickProvider for GeForceInstallerProvider {}
 
 impl ProviderChecks for GeForceInstallerProvider {
@@ -79,8 +129,13 @@ impl ProviderChecks for GeForceInstallerProvider {
         self.is_running()
     }
 
+    // It updates itself on run, see update() below
     fn is_updateable(&self) -> bool {
-        self.is_installed()
+        false
+    }
+
+    fn is_reinstallable(&self) -> bool {
+        self.is_installed() && !self.is_running()
     }
 
     fn is_runnable(&self) -> bool {
@@ -113,16 +168,19 @@ impl ProviderActions for GeForceInstallerProvider {
     fn install(&self) -> DeckResult<ActionSuccess> {
         // Needed for geforce?
         //let _ = &self.ctx.sys_command("xhost", vec!["+"]).run();
-        fetch_and_prep_remote_executable(
+        fetch_and_run_remote_executable(
             &self.ctx,
             GEFORCE_INSTALLER_DOWNLOAD_URL,
+            &self.verification,
             GEFORCE_INSTALLER_TEMP_FILENAME,
-        )?
-        .env(INSTALLING_ENV_STRING, self.ctx.trick.id.as_ref())
-        .run()?;
+        )?;
         success!("GeForce NOW installed successfully!")
     }
 
+    fn reinstall(&self, delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        rerun_installer(&self.ctx, delete_user_data, || self.install())
+    }
+
     fn run(&self) -> DeckResult<ActionSuccess> {
         self.ctx
             .sys_command_no_args(GEFORCE_LOCAL_EXECUTABLE.as_str())
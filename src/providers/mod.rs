@@ -1,24 +1,25 @@
-use crate::providers::geforce_now::GeForceInstallerProvider;
 use crate::prelude::*;
-use crate::providers::decky_installer::DeckyInstallerProvider;
-use crate::providers::emudeck_installer::EmuDeckInstallerProvider;
-use crate::providers::flatpak::FlatpakProvider;
-use crate::providers::simple_command::SimpleCommandProvider;
+use crate::providers::registry::{get_provider_registry, UnavailableProvider};
 use crate::providers::system_context::FullSystemContext;
-use crate::providers::systemd_run::SystemdRunProvider;
 use std::fmt::Debug;
 use std::ops::Deref;
 use std::ops::DerefMut;
 
+pub mod availability;
+pub mod bundle;
 pub mod decky_installer;
 pub mod emudeck_installer;
 pub mod geforce_now;
 pub mod flatpak;
 mod flatpak_helpers;
+pub mod registry;
 pub mod simple_command;
+pub mod ssh_session;
 pub mod system_context;
 pub mod systemd_run;
 
+pub use availability::UnavailableReason;
+
 pub(super) const fn not_possible(reason: &'static str) -> DeckResult<ActionSuccess> {
     Err(KnownError::ActionNotPossible(reason))
 }
@@ -36,74 +37,25 @@ pub struct DynTrickProvider {
 impl DynTrickProvider {
     #[must_use]
     pub fn new(ctx: &SpecificExecutionContext, full_ctx: &FullSystemContext) -> Self {
-        let trick = &ctx.trick;
-        let running_instances = full_ctx
-            .procs_ctx
-            .tricks_to_running_pids
-            .get(&trick.id)
-            .cloned()
-            .unwrap_or_default();
-
-        let trick_id = trick.id.clone();
-
-        match &trick.provider_config {
-            ProviderConfig::Flatpak(flatpak) => DynTrickProvider {
-                prov: Box::new(FlatpakProvider::new(
-                    flatpak,
-                    full_ctx.flatpak_ctx.clone(),
-                    ctx.clone(),
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
-            ProviderConfig::SimpleCommand(simple_command) => DynTrickProvider {
-                prov: Box::new(SimpleCommandProvider::new(
-                    trick_id,
-                    simple_command.command.clone(),
-                    simple_command.args.clone().unwrap_or_default(),
-                    ctx.clone(),
-                    running_instances,
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
-            ProviderConfig::SystemdRun(systemd_run) => DynTrickProvider {
-                prov: Box::new(SystemdRunProvider::new(
-                    trick_id,
-                    ctx.clone(),
-                    full_ctx
-                        .systemd_run_ctx
-                        .running_unit_ids
-                        .contains(&systemd_run.unit_id),
-                    systemd_run.clone(),
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
-            ProviderConfig::DeckyInstaller(_decky_installer) => DynTrickProvider {
-                prov: Box::new(DeckyInstallerProvider::new(
-                    ctx.clone(),
-                    full_ctx.decky_ctx.clone(),
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
-            ProviderConfig::EmuDeckInstaller(_emudeck_installer) => DynTrickProvider {
-                prov: Box::new(EmuDeckInstallerProvider::new(
-                    ctx.clone(),
-                    full_ctx.emudeck_ctx.clone(),
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
-            ProviderConfig::GeForceInstaller(_emudeck_installer) => DynTrickProvider {
-                prov: Box::new(GeForceInstallerProvider::new(
-                    ctx.clone(),
-                    full_ctx.geforce_ctx.clone(),
-                )),
-                ctx: ctx.clone(),
-                full_ctx: full_ctx.clone(),
-            },
+        let type_tag = ctx.trick.provider_config.type_tag();
+        let prov = get_provider_registry()
+            .get(type_tag)
+            .and_then(|registration| registration.construct(ctx, full_ctx))
+            .unwrap_or_else(|err| {
+                // Configs are checked against the registry when loaded, so this should only
+                // happen if a provider registration was replaced afterwards.
+                error!(
+                    ctx,
+                    "Could not create \"{type_tag}\" provider for trick \"{}\": {err}",
+                    ctx.trick.id
+                );
+                Box::new(UnavailableProvider::new(ctx.clone(), err.to_string()))
+            });
+
+        DynTrickProvider {
+            prov,
+            ctx: ctx.clone(),
+            full_ctx: full_ctx.clone(),
         }
     }
 
@@ -157,6 +109,12 @@ pub trait TrickProvider: ProviderChecks + ProviderActions + Debug + Sync {
     fn get_all_actions(&self) -> Vec<SpecificActionID> {
         SpecificActionID::all_variants()
     }
+
+    // Shortcuts are found by their tag, so this is the same no matter how they were added
+    fn remove_from_steam(&self) -> DeckResult<ActionSuccess> {
+        let ctx = self.get_execution_context();
+        crate::add_to_steam::remove_from_steam(ctx, &ctx.trick.id)
+    }
 }
 
 pub trait ProviderChecks {
@@ -165,11 +123,15 @@ pub trait ProviderChecks {
     fn can(&self, action: &SpecificAction) -> bool {
         match action {
             SpecificAction::Run { .. } => self.is_runnable(),
+            SpecificAction::RunNested { .. } => self.is_runnable_nested(),
             SpecificAction::Install { .. } => self.is_installable(),
             SpecificAction::Kill { .. } => self.is_killable(),
             SpecificAction::Uninstall { .. } => self.is_uninstallable(),
             SpecificAction::AddToSteam { .. } => self.is_addable_to_steam(),
+            SpecificAction::RemoveFromSteam { .. } => self.is_removable_from_steam(),
             SpecificAction::Update { .. } => self.is_updateable(),
+            SpecificAction::Reinstall { .. } => self.is_reinstallable(),
+            SpecificAction::Logs { .. } => self.has_logs(),
             SpecificAction::Info { .. } => true,
         }
     }
@@ -177,11 +139,15 @@ pub trait ProviderChecks {
     fn can_id(&self, action_id: &SpecificActionID) -> bool {
         match action_id {
             SpecificActionID::Run => self.is_runnable(),
+            SpecificActionID::RunNested => self.is_runnable_nested(),
             SpecificActionID::Install => self.is_installable(),
             SpecificActionID::Kill => self.is_killable(),
             SpecificActionID::Uninstall => self.is_uninstallable(),
             SpecificActionID::AddToSteam => self.is_addable_to_steam(),
+            SpecificActionID::RemoveFromSteam => self.is_removable_from_steam(),
             SpecificActionID::Update => self.is_updateable(),
+            SpecificActionID::Reinstall => self.is_reinstallable(),
+            SpecificActionID::Logs => self.has_logs(),
             SpecificActionID::Info => true,
         }
     }
@@ -194,10 +160,18 @@ pub trait ProviderChecks {
     }
 
     fn is_runnable(&self) -> bool;
+    // Whether running with the nested desktop's DISPLAY/XAUTHORITY actually puts the trick there.
+    // Anything which hands off to another process manager (systemd, etc) won't pass them along.
+    fn is_runnable_nested(&self) -> bool {
+        self.is_runnable() && crate::nested_desktop::is_nested_desktop_available()
+    }
     fn is_running(&self) -> bool;
     fn is_killable(&self) -> bool;
 
     fn is_updateable(&self) -> bool;
+    fn is_reinstallable(&self) -> bool {
+        false
+    }
 
     // NOTE: the "add to steam on install" functionality is in src/actions/specific.rs
     fn is_addable_to_steam(&self) -> bool;
@@ -205,6 +179,50 @@ pub trait ProviderChecks {
     fn is_added_to_steam(&self) -> bool {
         self.get_execution_context().is_added_to_steam
     }
+    fn is_removable_from_steam(&self) -> bool {
+        self.is_added_to_steam()
+    }
+
+    // Only providers which keep their own logs (e.g. the systemd journal) need to override this
+    fn has_logs(&self) -> bool {
+        false
+    }
+
+    // Providers which know more about why an action is gated than the generic predicates can
+    // tell (e.g. which binary is missing) should override this
+    fn get_unavailable_reason(&self, _action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        None
+    }
+
+    /// # Errors
+    ///
+    /// Returns the reason the action can't be taken right now, if it can't.
+    fn get_availability(&self, action_id: &SpecificActionID) -> Result<(), UnavailableReason> {
+        if self.can_id(action_id) {
+            Ok(())
+        } else {
+            Err(self.get_unavailable_reason(action_id).unwrap_or_else(|| {
+                availability::get_default_unavailable_reason(self, action_id)
+            }))
+        }
+    }
+}
+
+// Installer scripts decide for themselves what happens to user data (usually it's kept), so
+// reinstalling just means running them again.
+pub(super) fn rerun_installer(
+    ctx: &SpecificExecutionContext,
+    delete_user_data: bool,
+    install: impl FnOnce() -> DeckResult<ActionSuccess>,
+) -> DeckResult<ActionSuccess> {
+    if delete_user_data {
+        warn!(
+            ctx,
+            "User data for \"{}\" is managed by its installer, and will not be removed.",
+            ctx.trick.id
+        );
+    }
+    install()
 }
 
 pub trait ProviderActions {
@@ -214,12 +232,28 @@ pub trait ProviderActions {
     fn uninstall(&self) -> DeckResult<ActionSuccess>;
     fn add_to_steam(&self) -> DeckResult<ActionSuccess>;
     fn update(&self) -> DeckResult<ActionSuccess>;
-    //fn force_reinstall(&self) -> DeckResult<ActionSuccess>;
-    //fn remove_from_steam(&self) -> Result<ActionSuccess, DynamicError>>;
+    fn logs(&self) -> DeckResult<ActionSuccess> {
+        not_possible("This trick does not keep logs.")
+    }
+    // Shown after the trick's config by the Info action, for anything a user needs to know
+    // which can't be read from the config (connection instructions, etc)
+    fn get_extra_info(&self) -> Option<String> {
+        None
+    }
+    // Reinstalling never touches Steam shortcuts, which is what makes it better than an
+    // uninstall followed by an install.
+    fn reinstall(&self, _delete_user_data: bool) -> DeckResult<ActionSuccess> {
+        not_possible("This trick cannot be reinstalled.")
+    }
 }
 
 pub trait GeneralProvider: Debug + Sync {
     fn update_all(&self) -> DeckResult<ActionSuccess>;
+    // Used by `update --check`. Which of these tricks have an update waiting, or None if the
+    // provider can't tell without actually updating.
+    fn get_tricks_with_updates(&self, _tricks: &[&Trick]) -> DeckResult<Option<Vec<TrickID>>> {
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
@@ -265,6 +299,7 @@ mod tests {
         mock.expect_is_runnable().times(1).returning(|| true);
         let action = SpecificAction::Run {
             id: "test-id".into(),
+            extra_args: vec![],
         };
         assert!(mock.can(&action));
     }
@@ -317,6 +352,7 @@ mod tests {
             .returning(|| true);
         let action = SpecificAction::AddToSteam {
             id: "test-id".into(),
+            extra_args: vec![],
         };
         assert!(mock.can(&action));
     }
@@ -329,4 +365,37 @@ mod tests {
         };
         assert!(mock.can(&action));
     }
+
+    #[test]
+    fn test_availability_reasons() {
+        let mut mock = MockProviderImpl::new();
+        mock.expect_is_runnable().returning(|| false);
+        mock.expect_is_killable().returning(|| false);
+        mock.expect_is_installable().returning(|| false);
+        mock.expect_is_installed().returning(|| false);
+        mock.expect_is_installing().returning(|| false);
+        mock.expect_is_running().returning(|| false);
+        assert_eq!(
+            Err(UnavailableReason::NotInstalled),
+            mock.get_availability(&SpecificActionID::Run)
+        );
+        assert_eq!(
+            Err(UnavailableReason::NotRunning),
+            mock.get_availability(&SpecificActionID::Kill)
+        );
+        assert_eq!(Ok(()), mock.get_availability(&SpecificActionID::Info));
+    }
+
+    #[test]
+    fn test_availability_while_installing() {
+        let mut mock = MockProviderImpl::new();
+        mock.expect_is_installable().returning(|| false);
+        mock.expect_is_installed().returning(|| false);
+        mock.expect_is_installing().returning(|| true);
+        mock.expect_is_running().returning(|| false);
+        assert_eq!(
+            Err(UnavailableReason::InstallInProgress),
+            mock.get_availability(&SpecificActionID::Install)
+        );
+    }
 }
@@ -0,0 +1,397 @@
+use crate::prelude::*;
+use crate::providers::bundle::BundleRegistration;
+use crate::providers::decky_installer::DeckyInstallerRegistration;
+use crate::providers::emudeck_installer::EmuDeckInstallerRegistration;
+use crate::providers::flatpak::FlatpakRegistration;
+use crate::providers::geforce_now::GeForceInstallerRegistration;
+use crate::providers::simple_command::SimpleCommandRegistration;
+use crate::providers::ssh_session::SshSessionRegistration;
+use crate::providers::system_context::FullSystemContext;
+use crate::providers::systemd_run::SystemdRunRegistration;
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, LazyLock, PoisonError, RwLock};
+
+// Whatever system state a provider gathered up front. This is type-erased so that
+// FullSystemContext doesn't need a field for every provider, including ones defined downstream.
+pub trait ProviderSystemContext: Any + Debug + Send + Sync {}
+impl<T: Any + Debug + Send + Sync> ProviderSystemContext for T {}
+
+pub type ProviderContextRc = Arc<dyn ProviderSystemContext>;
+pub type ProviderRegistrationRc = Arc<dyn ProviderRegistration>;
+
+/// Everything decktricks needs to know about a provider type. Built-in providers are registered
+/// by default, and other crates can add their own with [`register_provider`] before loading
+/// config or gathering system context.
+pub trait ProviderRegistration: Debug + Send + Sync {
+    /// The `"type"` field used for this provider in the tricks config, e.g. `"flatpak"`.
+    fn type_tag(&self) -> &'static str;
+
+    /// Gather the system state shared by all tricks using this provider. This runs once per
+    /// gather, in parallel with the other providers. Providers with nothing to gather can
+    /// return `Ok(None)`.
+    fn gather_context(
+        &self,
+        _ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(None)
+    }
+
+    /// Validate a trick's provider config when the config is loaded.
+    fn check_config(&self, _provider_config: &ProviderConfig) -> DeckResult<()> {
+        Ok(())
+    }
+
+    /// Whether actions on several tricks using this provider can run at the same time, e.g. for
+    /// `install a b c`. Tricks whose provider returns false are worked on one at a time, which is
+    /// the default: most providers change shared state (flatpak's installation, or whatever an
+    /// installer script touches) or log live to the terminal, where several at once would be
+    /// unreadable. Providers whose tricks are independent of each other opt in.
+    fn allows_parallel_actions(&self) -> bool {
+        false
+    }
+
+    /// How `update` with no trick ids deals with installed tricks using this provider.
+    fn get_update_all_strategy(&self, _ctx: &GeneralExecutionContext) -> UpdateAllStrategy {
+        UpdateAllStrategy::PerTrick
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>>;
+}
+
+#[derive(Debug)]
+pub enum UpdateAllStrategy {
+    /// Update every trick using this provider in one go, e.g. with `flatpak update`.
+    Global(Box<dyn GeneralProvider>),
+    /// Run `update` on each installed trick which can be updated.
+    PerTrick,
+    /// Leave these tricks out, because something else already updates them.
+    Skip,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProviderRegistry {
+    registrations: BTreeMap<&'static str, ProviderRegistrationRc>,
+}
+
+impl ProviderRegistry {
+    #[must_use]
+    pub fn with_builtin_providers() -> Self {
+        let mut registry = Self::default();
+        registry.register(FlatpakRegistration);
+        registry.register(DeckyInstallerRegistration);
+        registry.register(EmuDeckInstallerRegistration);
+        registry.register(GeForceInstallerRegistration);
+        registry.register(SimpleCommandRegistration);
+        registry.register(SystemdRunRegistration);
+        registry.register(BundleRegistration);
+        registry.register(SshSessionRegistration);
+        registry
+    }
+
+    // Registering a type tag which is already known replaces the previous registration.
+    pub fn register(
+        &mut self,
+        registration: impl ProviderRegistration + 'static,
+    ) -> Option<ProviderRegistrationRc> {
+        self.registrations
+            .insert(registration.type_tag(), Arc::new(registration))
+    }
+
+    /// # Errors
+    ///
+    /// Returns `ProviderNotImplemented` if nothing is registered for this type tag.
+    pub fn get(&self, type_tag: &str) -> DeckResult<ProviderRegistrationRc> {
+        self.registrations.get(type_tag).cloned().ok_or_else(|| {
+            KnownError::ProviderNotImplemented(format!("Unknown provider type: \"{type_tag}\""))
+        })
+    }
+
+    #[must_use]
+    pub fn get_all(&self) -> Vec<ProviderRegistrationRc> {
+        self.registrations.values().cloned().collect()
+    }
+
+    /// # Errors
+    ///
+    /// Returns errors for unknown provider types, or configs the provider rejects.
+    pub fn check_trick(&self, trick: &Trick) -> DeckResult<()> {
+        let config = &trick.provider_config;
+        self.get(config.type_tag())?
+            .check_config(config)
+            .map_err(|err| match err {
+                KnownError::ConfigParsing(e) => KnownError::ProviderNotImplemented(format!(
+                    "Invalid \"{}\" config for trick \"{}\": {e}",
+                    config.type_tag(),
+                    trick.id
+                )),
+                other => other,
+            })
+    }
+}
+
+static PROVIDER_REGISTRY: LazyLock<RwLock<ProviderRegistry>> =
+    LazyLock::new(|| RwLock::new(ProviderRegistry::with_builtin_providers()));
+
+/// Make a provider type available to all configs loaded and contexts gathered after this call.
+pub fn register_provider(
+    registration: impl ProviderRegistration + 'static,
+) -> Option<ProviderRegistrationRc> {
+    PROVIDER_REGISTRY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .register(registration)
+}
+
+// Registrations are behind Arcs, so handing out a copy is cheap and means nobody holds the lock
+// while gathering or running actions.
+pub fn get_provider_registry() -> ProviderRegistry {
+    PROVIDER_REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+pub(crate) fn downcast_provider_context<T: ProviderSystemContext + Clone>(
+    provider_ctx: &ProviderContextRc,
+) -> Option<T> {
+    let as_any: &dyn Any = provider_ctx.as_ref();
+    as_any.downcast_ref::<T>().cloned()
+}
+
+// Built-in configs which failed to parse fall through to `ProviderConfig::Registered`, so parse
+// them again on their own to surface the actual error.
+pub(crate) fn check_builtin_config<T: DeserializeOwned>(
+    provider_config: &ProviderConfig,
+) -> DeckResult<()> {
+    match provider_config {
+        ProviderConfig::Registered(raw) => raw.parse::<T>().map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn mismatched_config(type_tag: &str, provider_config: &ProviderConfig) -> KnownError {
+    KnownError::ProviderNotImplemented(format!(
+        "The \"{type_tag}\" provider was given a \"{}\" config!",
+        provider_config.type_tag()
+    ))
+}
+
+// Stands in for a trick whose provider could not be constructed, so that the rest of the tricks
+// can still be listed and used.
+#[derive(Debug)]
+pub(crate) struct UnavailableProvider {
+    ctx: SpecificExecutionContext,
+    reason: String,
+}
+
+impl UnavailableProvider {
+    pub(crate) fn new(ctx: SpecificExecutionContext, reason: String) -> Self {
+        Self { ctx, reason }
+    }
+
+    fn unavailable(&self) -> DeckResult<ActionSuccess> {
+        Err(KnownError::ProviderNotImplemented(format!(
+            "This trick's provider is not available: {}",
+            self.reason
+        )))
+    }
+}
+
+impl TrickProvider for UnavailableProvider {}
+
+impl ProviderChecks for UnavailableProvider {
+    fn get_execution_context(&self) -> &SpecificExecutionContext {
+        &self.ctx
+    }
+
+    fn is_installable(&self) -> bool {
+        false
+    }
+    fn is_uninstallable(&self) -> bool {
+        false
+    }
+    fn is_installed(&self) -> bool {
+        false
+    }
+    fn is_runnable(&self) -> bool {
+        false
+    }
+    fn is_running(&self) -> bool {
+        false
+    }
+    fn is_killable(&self) -> bool {
+        false
+    }
+    fn is_updateable(&self) -> bool {
+        false
+    }
+    fn is_addable_to_steam(&self) -> bool {
+        false
+    }
+    fn is_addable_to_steam_once_installed(&self) -> bool {
+        false
+    }
+
+    fn get_unavailable_reason(&self, _action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        Some(UnavailableReason::Unsupported(self.reason.clone()))
+    }
+}
+
+impl ProviderActions for UnavailableProvider {
+    fn run(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+    fn kill(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+    fn install(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+    fn uninstall(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+    fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+    fn update(&self) -> DeckResult<ActionSuccess> {
+        self.unavailable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct CountingContext {
+        tricks_seen: usize,
+    }
+
+    #[derive(Debug)]
+    struct CountingRegistration;
+
+    impl ProviderRegistration for CountingRegistration {
+        fn type_tag(&self) -> &'static str {
+            "test-counting"
+        }
+
+        fn gather_context(
+            &self,
+            _ctx: &ExecutionContext,
+            tricks_loader: &LoadedConfig,
+        ) -> DeckResult<Option<ProviderContextRc>> {
+            let tricks_seen = tricks_loader
+                .get_all_tricks()
+                .filter(|(_, t)| t.provider_config.type_tag() == self.type_tag())
+                .count();
+            Ok(Some(Arc::new(CountingContext { tricks_seen })))
+        }
+
+        fn construct(
+            &self,
+            ctx: &SpecificExecutionContext,
+            full_ctx: &FullSystemContext,
+        ) -> DeckResult<Box<dyn TrickProvider>> {
+            let counting_ctx: CountingContext = full_ctx.get_provider_context(self.type_tag());
+            Ok(Box::new(UnavailableProvider::new(
+                ctx.clone(),
+                format!("seen {}", counting_ctx.tricks_seen),
+            )))
+        }
+    }
+
+    const DOWNSTREAM_CONFIG: &str = r#"{
+        "settings": {
+            "controller_layout_id": "",
+            "add_to_steam_on_install_where_applicable": false
+        },
+        "known_categories": [],
+        "tricks": [
+            {
+                "id": "counted",
+                "provider_config": { "type": "test-counting", "anything": ["goes"] },
+                "display_name": "Counted",
+                "description": "",
+                "categories": []
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn providers_opt_in_to_parallel_actions() -> DeckResult<()> {
+        let registry = get_provider_registry();
+        assert!(registry.get("simple-command")?.allows_parallel_actions());
+        assert!(!registry.get("flatpak")?.allows_parallel_actions());
+        assert!(!CountingRegistration.allows_parallel_actions());
+        Ok(())
+    }
+
+    #[test]
+    fn downstream_provider_is_gathered_and_constructed() -> DeckResult<()> {
+        // A registry of our own, so other tests never see this provider
+        let mut registry = ProviderRegistry::with_builtin_providers();
+        registry.register(CountingRegistration);
+
+        let loader = LoadedConfig::from_str_with_registry(DOWNSTREAM_CONFIG, &registry)?;
+        let trick = loader.get_trick("counted")?;
+        match &trick.provider_config {
+            ProviderConfig::Registered(raw) => {
+                assert_eq!("test-counting", raw.type_tag);
+                assert!(raw.config.contains_key("anything"));
+            }
+            other => panic!("Expected a registered config, got: {other:?}"),
+        }
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+        let ctx = ExecutionContext::general_for_test_with(Arc::new(mock));
+        let full_ctx = FullSystemContext::gather_with_registry(&ctx, &loader, &registry);
+        let specific_ctx = SpecificExecutionContext::test(trick.clone());
+        let provider = registry
+            .get("test-counting")?
+            .construct(&specific_ctx, &full_ctx)?;
+        assert_eq!(
+            Some(UnavailableReason::Unsupported("seen 1".into())),
+            provider.get_unavailable_reason(&SpecificActionID::Run)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unavailable_provider_gives_its_reason() {
+        let ctx = SpecificExecutionContext::test(Trick::test());
+        let provider = UnavailableProvider::new(ctx, "broken".into());
+        match provider.run() {
+            Err(KnownError::ProviderNotImplemented(msg)) => assert!(msg.ends_with(": broken")),
+            other => panic!("Expected the reason, got: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_provider_type_fails_to_load() {
+        let config = DOWNSTREAM_CONFIG.replace("test-counting", "test-never-registered");
+        let res = LoadedConfig::try_from(config.as_str());
+        assert!(matches!(res, Err(KnownError::ProviderNotImplemented(_))));
+    }
+
+    #[test]
+    fn broken_builtin_config_reports_real_error() {
+        let config = DOWNSTREAM_CONFIG.replace(
+            r#"{ "type": "test-counting", "anything": ["goes"] }"#,
+            r#"{ "type": "flatpak" }"#,
+        );
+        match LoadedConfig::try_from(config.as_str()) {
+            Err(KnownError::ProviderNotImplemented(msg)) => assert!(msg.contains("`id`")),
+            other => panic!("Expected a config error, got: {other:?}"),
+        }
+    }
+}
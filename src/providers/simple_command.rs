@@ -1,11 +1,20 @@
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+};
+use crate::providers::system_context::{BinaryPathsSystemContext, FullSystemContext};
 use crate::system_command_runners::{SysCommandResultChecker, SysCommandRunner};
 use crate::{prelude::*, utils::kill_pids};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct SimpleCommandProvider {
     pub trick_id: TrickID,
     pub command: String,
     pub args: Vec<String>,
+    pub execution_dir: Option<String>,
+    pub env: EnvVars,
+    // None if the command could not be found in $PATH
+    pub resolved_binary: Option<String>,
     pub ctx: SpecificExecutionContext,
     pub running_instances: Vec<ProcessID>,
 }
@@ -18,14 +27,93 @@ impl SimpleCommandProvider {
         ctx: SpecificExecutionContext,
         running_instances: Vec<ProcessID>,
     ) -> Self {
+        let command = command.into();
         Self {
             trick_id,
-            command: command.into(),
+            resolved_binary: Some(command.clone()),
+            command,
             args: args.into_iter().map(Into::into).collect(),
+            execution_dir: None,
+            env: EnvVars::default(),
             ctx,
             running_instances,
         }
     }
+
+    #[must_use]
+    pub(crate) fn with_environment(mut self, execution_dir: Option<String>, env: EnvVars) -> Self {
+        self.execution_dir = execution_dir;
+        self.env = env;
+        self
+    }
+
+    #[must_use]
+    pub(crate) fn with_resolved_binary(mut self, resolved_binary: Option<String>) -> Self {
+        self.resolved_binary = resolved_binary;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SimpleCommandRegistration;
+
+impl ProviderRegistration for SimpleCommandRegistration {
+    fn type_tag(&self) -> &'static str {
+        "simple-command"
+    }
+
+    // Each trick is its own command, which doesn't touch any other trick's
+    fn allows_parallel_actions(&self) -> bool {
+        true
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        let commands = tricks_loader
+            .get_all_tricks()
+            .filter_map(|(_, trick)| match &trick.provider_config {
+                ProviderConfig::SimpleCommand(simple_command) => Some(&simple_command.command),
+                _ => None,
+            })
+            .collect();
+        Ok(Some(Arc::new(BinaryPathsSystemContext::gather_for(
+            ctx, commands,
+        ))))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<SimpleCommand>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        let ProviderConfig::SimpleCommand(simple_command) = &ctx.trick.provider_config else {
+            return Err(mismatched_config(self.type_tag(), &ctx.trick.provider_config));
+        };
+        let binary_paths_ctx: BinaryPathsSystemContext =
+            full_ctx.get_provider_context(self.type_tag());
+
+        Ok(Box::new(
+            SimpleCommandProvider::new(
+                ctx.trick.id.clone(),
+                simple_command.command.clone(),
+                simple_command.args.clone().unwrap_or_default(),
+                ctx.clone(),
+                full_ctx.get_running_instances(&ctx.trick.id),
+            )
+            .with_environment(
+                simple_command.execution_dir.clone(),
+                simple_command.env.clone().unwrap_or_default(),
+            )
+            .with_resolved_binary(binary_paths_ctx.get_resolved_path(&simple_command.command)),
+        ))
+    }
 }
 
 impl TrickProvider for SimpleCommandProvider {}
@@ -35,8 +123,8 @@ impl ProviderChecks for SimpleCommandProvider {
         &self.ctx
     }
 
-    // These are meant to be simple system commands which are always known to be installed in
-    // SteamOS. You can gather `which` data in FullSystemContext, if this becomes necessary.
+    // These are meant to be simple system commands which are usually installed on SteamOS,
+    // so "installed" just means that we found the binary in $PATH.
     fn is_installable(&self) -> bool {
         false
     }
@@ -46,11 +134,11 @@ impl ProviderChecks for SimpleCommandProvider {
     }
 
     fn is_installed(&self) -> bool {
-        true
+        self.resolved_binary.is_some()
     }
 
     fn is_runnable(&self) -> bool {
-        true
+        self.is_installed()
     }
 
     fn is_running(&self) -> bool {
@@ -72,6 +160,17 @@ impl ProviderChecks for SimpleCommandProvider {
     fn is_addable_to_steam_once_installed(&self) -> bool {
         true
     }
+
+    fn get_unavailable_reason(&self, action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        match action_id {
+            SpecificActionID::Run | SpecificActionID::RunNested | SpecificActionID::AddToSteam
+                if !self.is_installed() =>
+            {
+                Some(UnavailableReason::MissingBinary(self.command.clone()))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl ProviderActions for SimpleCommandProvider {
@@ -85,8 +184,10 @@ impl ProviderActions for SimpleCommandProvider {
     }
 
     fn run(&self) -> DeckResult<ActionSuccess> {
-        self.ctx.sys_command(&self.command, self.args.iter())
+        let args = self.args.iter().chain(self.ctx.get_extra_run_args());
+        self.ctx.sys_command(&self.command, args)
             .enable_live_logging()
+            .with_environment(self.execution_dir.as_ref(), &self.env)
             .env(PID_ENV_STRING, &self.trick_id)
             .run()?
             .as_success()
@@ -101,9 +202,9 @@ impl ProviderActions for SimpleCommandProvider {
     }
 
     fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
-        add_to_steam(&AddToSteamTarget::Specific(TrickAddToSteamContext::try_from(
-            &self.ctx.trick,
-        )?))
+        let target = TrickAddToSteamContext::try_from(&self.ctx.trick)?
+            .with_extra_args(self.ctx.get_extra_run_args());
+        add_to_steam(&self.ctx, &AddToSteamTarget::Specific(target))
     }
 }
 
@@ -116,6 +217,7 @@ impl GeneralProvider for SimpleCommandProvider {
 #[cfg(test)]
 mod tests {
     use super::SimpleCommandProvider;
+    use crate::nested_desktop::NestedSession;
     use crate::prelude::*;
     use crate::system_command_runners::MockTestActualRunner;
 
@@ -132,6 +234,114 @@ mod tests {
         assert!(sc.is_addable_to_steam());
     }
 
+    #[test]
+    fn missing_binary_is_not_runnable() {
+        let ctx = SpecificExecutionContext::test(Trick::test());
+
+        let sc = SimpleCommandProvider::new(
+            "missing".into(),
+            "kdeconnect-app",
+            vec![],
+            ctx,
+            Vec::default(),
+        )
+        .with_resolved_binary(None);
+        assert!(!sc.is_installed());
+        assert!(!sc.is_runnable());
+        assert!(!sc.is_addable_to_steam());
+        assert_eq!(
+            Err(UnavailableReason::MissingBinary("kdeconnect-app".into())),
+            sc.get_availability(&SpecificActionID::Run)
+        );
+        assert_eq!(
+            Err(UnavailableReason::Unsupported(
+                "Tricks of type \"simple-command\" can't do this.".into()
+            )),
+            sc.get_availability(&SpecificActionID::Install)
+        );
+    }
+
+    #[test]
+    fn run_uses_configured_environment() {
+        let trick = Trick::test();
+        let mut expected = ExecutionContext::specific_for_test().sys_command("env", Vec::<&str>::new());
+        expected
+            .current_dir("/tmp")
+            .env("FOO", "bar")
+            .env(PID_ENV_STRING, "env-test");
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(mockall::predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let ctx = SpecificExecutionContext::test_with_runner(trick, std::sync::Arc::new(mock));
+        let sc = SimpleCommandProvider::new("env-test".into(), "env", vec![], ctx, Vec::default())
+            .with_environment(
+                Some("/tmp".into()),
+                EnvVars::from([("FOO".into(), "bar".into())]),
+            );
+
+        assert!(sc.run().is_ok());
+    }
+
+    #[test]
+    fn run_appends_extra_args() {
+        let expected = ExecutionContext::specific_for_test()
+            .sys_command("echo", ["lol", "--fullscreen"])
+            .env(PID_ENV_STRING, "echo-lol")
+            .clone();
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(mockall::predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let mut ctx =
+            SpecificExecutionContext::test_with_runner(Trick::test(), std::sync::Arc::new(mock));
+        ctx.action = SpecificAction::Run {
+            id: "echo-lol".into(),
+            extra_args: vec!["--fullscreen".into()],
+        };
+        let sc =
+            SimpleCommandProvider::new("echo-lol".into(), "echo", vec!["lol"], ctx, Vec::default());
+
+        assert!(sc.run().is_ok());
+    }
+
+    #[test]
+    fn run_in_nested_session_uses_its_display() {
+        let trick = Trick::test();
+        let mut expected = ExecutionContext::specific_for_test().sys_command("env", ["--fullscreen"]);
+        expected
+            .env("DISPLAY", ":2")
+            .env("XAUTHORITY", "/run/user/1000/nested_plasma/xauth_abcd")
+            .env(PID_ENV_STRING, "env-test");
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(mockall::predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let session = NestedSession {
+            display: ":2".into(),
+            xauthority: "/run/user/1000/nested_plasma/xauth_abcd".into(),
+        };
+        let mut ctx = SpecificExecutionContext::test_with_runner(trick, std::sync::Arc::new(mock));
+        ctx.action = SpecificAction::RunNested {
+            id: "env-test".into(),
+            extra_args: vec!["--fullscreen".into()],
+        };
+        let ctx = ctx.with_extra_env(session.get_env_vars());
+        let sc = SimpleCommandProvider::new("env-test".into(), "env", vec![], ctx, Vec::default());
+
+        assert!(sc.can_id(&SpecificActionID::RunNested));
+        assert!(sc.run().is_ok());
+    }
+
     #[test]
     fn expected_failures() {
         let cmd = "echo";
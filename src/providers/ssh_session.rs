@@ -0,0 +1,443 @@
+use crate::prelude::*;
+use crate::providers::registry::{
+    ProviderContextRc, ProviderRegistration, check_builtin_config, mismatched_config,
+};
+use crate::providers::system_context::FullSystemContext;
+use crate::system_command_runners::{SysCommandResultChecker, SysCommandRunner};
+use crate::network::{DEFAULT_SSH_PORT, NetworkInfo};
+use crate::utils::{get_current_user, get_decktricks_dir, kill_pids, which};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A temporary sshd, run as the current user with its own config and host keys, so that files can
+// be copied to/from the Deck without permanently enabling the system sshd.
+
+const DEFAULT_SSH_SESSION_PORT: u16 = 2222;
+const SSHD_COMMAND: &str = "sshd";
+const SSH_KEYGEN_COMMAND: &str = "ssh-keygen";
+const HOST_KEY_FILENAME: &str = "ssh_host_ed25519_key";
+const SSHD_CONFIG_FILENAME: &str = "sshd_config";
+
+fn get_ssh_session_state_dir() -> PathBuf {
+    get_decktricks_dir().join("ssh-session")
+}
+
+#[derive(Debug)]
+pub struct SshSessionProvider {
+    ctx: SpecificExecutionContext,
+    port: u16,
+    user: String,
+    ssh_ctx: SshSessionSystemContext,
+    running_instances: Vec<ProcessID>,
+    state_dir: PathBuf,
+}
+
+impl SshSessionProvider {
+    pub(super) fn new(
+        ctx: SpecificExecutionContext,
+        ssh_session: &SshSession,
+        ssh_ctx: SshSessionSystemContext,
+        running_instances: Vec<ProcessID>,
+        state_dir: PathBuf,
+    ) -> Self {
+        Self {
+            ctx,
+            port: ssh_session.port.unwrap_or(DEFAULT_SSH_SESSION_PORT),
+            user: get_current_user(),
+            ssh_ctx,
+            running_instances,
+            state_dir,
+        }
+    }
+
+    // Host keys and config live in a directory only we can read, since sshd refuses to use
+    // private keys which are readable by anyone else.
+    fn prepare_state_dir(&self) -> DeckResult<PathBuf> {
+        let config_file = self.state_dir.join(SSHD_CONFIG_FILENAME);
+        if skip_for_dry_run(
+            &self.ctx,
+            format!("write sshd's config and host key to \"{}\"", self.state_dir.display()),
+        ) {
+            return Ok(config_file);
+        }
+
+        let setup_err = |e: std::io::Error| {
+            KnownError::SshSessionError(format!(
+                "Could not prepare \"{}\": {e}",
+                self.state_dir.display()
+            ))
+        };
+        fs::create_dir_all(&self.state_dir).map_err(setup_err)?;
+        fs::set_permissions(&self.state_dir, fs::Permissions::from_mode(0o700))
+            .map_err(setup_err)?;
+
+        let host_key = self.state_dir.join(HOST_KEY_FILENAME);
+        // Keys are kept between sessions, so clients don't see a new host key every time
+        if !host_key.exists() {
+            self.generate_host_key(&host_key)?;
+        }
+
+        fs::write(&config_file, self.get_sshd_config(&host_key)).map_err(setup_err)?;
+        Ok(config_file)
+    }
+
+    fn generate_host_key(&self, host_key: &Path) -> DeckResult<ActionSuccess> {
+        self.ctx
+            .sys_command(
+                SSH_KEYGEN_COMMAND,
+                [
+                    "-q",
+                    "-t",
+                    "ed25519",
+                    "-N",
+                    "",
+                    "-C",
+                    "decktricks-ssh-session",
+                    "-f",
+                    &host_key.to_string_lossy(),
+                ],
+            )
+            .run()?
+            .as_success()
+    }
+
+    // Only the current user may log in. Running unprivileged, sshd can only authenticate the user
+    // it runs as anyway, but we'd rather be explicit about it.
+    fn get_sshd_config(&self, host_key: &Path) -> String {
+        [
+            format!("Port {}", self.port),
+            format!("HostKey {}", host_key.display()),
+            format!("AllowUsers {}", self.user),
+            "PasswordAuthentication yes".into(),
+            "KbdInteractiveAuthentication no".into(),
+            "UsePAM yes".into(),
+            "PidFile none".into(),
+            // Needed for scp, sftp, and sshfs
+            "Subsystem sftp internal-sftp".into(),
+        ]
+        .join("\n")
+            + "\n"
+    }
+
+    fn get_connection_instructions(&self) -> String {
+        let port = self.port;
+        let user = &self.user;
+        let addresses = &self.ssh_ctx.ipv4_addresses;
+        if addresses.is_empty() {
+            return "No network addresses were found. Are you connected to a network?".into();
+        }
+
+        let mut lines = vec!["Connect with:".to_string()];
+        for address in addresses {
+            lines.push(format!("  ssh -p {port} {user}@{address}"));
+        }
+        lines.push("Copy files with:".into());
+        for address in addresses {
+            lines.push(format!("  scp -P {port} <FILE> {user}@{address}:"));
+        }
+        lines.join("\n")
+    }
+}
+
+impl TrickProvider for SshSessionProvider {}
+
+impl ProviderChecks for SshSessionProvider {
+    fn get_execution_context(&self) -> &SpecificExecutionContext {
+        &self.ctx
+    }
+
+    fn is_installable(&self) -> bool {
+        false
+    }
+
+    fn is_uninstallable(&self) -> bool {
+        false
+    }
+
+    fn is_installed(&self) -> bool {
+        self.ssh_ctx.sshd_path.is_some()
+    }
+
+    // If we couldn't tell whether a password is set, let sshd be the judge
+    fn is_runnable(&self) -> bool {
+        self.is_installed() && !self.is_running() && self.ssh_ctx.user_has_password != Some(false)
+    }
+
+    fn is_runnable_nested(&self) -> bool {
+        false
+    }
+
+    fn is_running(&self) -> bool {
+        !self.running_instances.is_empty()
+    }
+
+    fn is_killable(&self) -> bool {
+        self.is_running()
+    }
+
+    fn is_updateable(&self) -> bool {
+        false
+    }
+
+    fn is_addable_to_steam(&self) -> bool {
+        false
+    }
+
+    fn is_addable_to_steam_once_installed(&self) -> bool {
+        false
+    }
+
+    fn get_unavailable_reason(&self, action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        match action_id {
+            SpecificActionID::RunNested => Some(UnavailableReason::Unsupported(
+                "An SSH session has no windows to show in the nested desktop.".into(),
+            )),
+            SpecificActionID::Run if !self.is_installed() => {
+                Some(UnavailableReason::MissingBinary(SSHD_COMMAND.into()))
+            }
+            SpecificActionID::Run if self.is_running() => {
+                Some(UnavailableReason::AlreadyRunning)
+            }
+            SpecificActionID::Run if self.ssh_ctx.user_has_password == Some(false) => {
+                Some(UnavailableReason::Unsupported(format!(
+                    "\"{}\" has no password, so nobody could log in. Set one with `passwd` first.",
+                    self.user
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ProviderActions for SshSessionProvider {
+    fn run(&self) -> DeckResult<ActionSuccess> {
+        let sshd = self
+            .ssh_ctx
+            .sshd_path
+            .clone()
+            .ok_or_else(|| KnownError::SshSessionError(
+                UnavailableReason::MissingBinary(SSHD_COMMAND.into()).to_string(),
+            ))?;
+        let config_file = self.prepare_state_dir()?;
+
+        info!(&self.ctx, "{}", self.get_connection_instructions());
+
+        // sshd needs to be run with an absolute path, so that it can re-exec itself
+        self.ctx
+            .sys_command(sshd, ["-D", "-e", "-f", &config_file.to_string_lossy()])
+            .enable_live_logging()
+            .env(PID_ENV_STRING, &self.ctx.trick.id)
+            .run()?
+            .as_success()
+    }
+
+    // sshd exits cleanly on SIGTERM, and connections made through it are tracked the same way
+    fn kill(&self) -> DeckResult<ActionSuccess> {
+        kill_pids(&self.ctx, &self.running_instances)
+    }
+
+    fn install(&self) -> DeckResult<ActionSuccess> {
+        not_possible("sshd is part of the system, and cannot be installed by decktricks.")
+    }
+
+    fn uninstall(&self) -> DeckResult<ActionSuccess> {
+        not_possible("sshd is part of the system, and cannot be uninstalled by decktricks.")
+    }
+
+    fn update(&self) -> DeckResult<ActionSuccess> {
+        not_possible("sshd is updated along with the system.")
+    }
+
+    fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
+        not_possible("SSH sessions cannot be added to Steam.")
+    }
+
+    fn get_extra_info(&self) -> Option<String> {
+        let status = if self.is_running() {
+            "running"
+        } else {
+            "not running"
+        };
+        Some(format!(
+            "SSH session on port {} ({status})\n{}",
+            self.port,
+            self.get_connection_instructions()
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SshSessionSystemContext {
+    sshd_path: Option<String>,
+    // None if we couldn't tell
+    user_has_password: Option<bool>,
+    ipv4_addresses: Vec<String>,
+}
+
+impl SshSessionSystemContext {
+    pub(crate) fn gather_with(ctx: &impl ExecCtx) -> Self {
+        let (sshd_path, user_has_password, ipv4_addresses) = join_all!(
+            || which(SSHD_COMMAND).ok(),
+            || get_user_has_password(ctx, &get_current_user()),
+            || NetworkInfo::gather(&get_current_user(), DEFAULT_SSH_PORT)
+                .map(|info| info.get_ipv4_addresses())
+                .unwrap_or_default()
+        );
+
+        Self {
+            sshd_path,
+            user_has_password,
+            ipv4_addresses,
+        }
+    }
+}
+
+// `passwd -S` prints e.g. "deck P 01/01/2024 0 99999 7 -1", where the second field is "P" for a
+// usable password, "NP" for no password, and "L" for a locked password.
+fn get_user_has_password(ctx: &impl ExecCtx, user: &str) -> Option<bool> {
+    let res = ctx.sys_command("passwd", ["-S", user]).read_only().run().ok()?;
+    if !res.ran_successfully() {
+        return None;
+    }
+    let output = res.as_success().ok()?.get_message_or_blank();
+    output.split_whitespace().nth(1).map(|status| status == "P")
+}
+
+#[derive(Debug)]
+pub(crate) struct SshSessionRegistration;
+
+impl ProviderRegistration for SshSessionRegistration {
+    fn type_tag(&self) -> &'static str {
+        "ssh-session"
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        _tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(SshSessionSystemContext::gather_with(ctx))))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<SshSession>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        match &ctx.trick.provider_config {
+            ProviderConfig::SshSession(ssh_session) => Ok(Box::new(SshSessionProvider::new(
+                ctx.clone(),
+                ssh_session,
+                full_ctx.get_provider_context(self.type_tag()),
+                full_ctx.get_running_instances(&ctx.trick.id),
+                get_ssh_session_state_dir(),
+            ))),
+            other => Err(mismatched_config(self.type_tag(), other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_provider(
+        runner: Arc<MockTestActualRunner>,
+        state_dir: PathBuf,
+        running_instances: Vec<ProcessID>,
+    ) -> SshSessionProvider {
+        let ctx = SpecificExecutionContext::test_with_runner(Trick::test(), runner);
+        let ssh_ctx = SshSessionSystemContext {
+            sshd_path: Some("/usr/bin/sshd".into()),
+            user_has_password: Some(true),
+            ipv4_addresses: vec!["192.168.1.23".into()],
+        };
+        SshSessionProvider::new(
+            ctx,
+            &SshSession { port: Some(2345) },
+            ssh_ctx,
+            running_instances,
+            state_dir,
+        )
+    }
+
+    #[test]
+    fn run_generates_host_key_and_starts_sshd() -> DeckResult<()> {
+        let tmp = tempfile::tempdir().map_err(|e| KnownError::TestError(e.to_string()))?;
+        let state_dir = tmp.path().join("ssh-session");
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .withf(|cmd| cmd.cmd == SSH_KEYGEN_COMMAND)
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+        let expected_config = state_dir.join(SSHD_CONFIG_FILENAME);
+        mock.expect_run()
+            .times(1)
+            .withf(move |cmd| {
+                cmd.cmd == "/usr/bin/sshd"
+                    && cmd.args == ["-D", "-e", "-f", &expected_config.to_string_lossy()]
+            })
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let provider = get_provider(Arc::new(mock), state_dir.clone(), vec![]);
+        assert!(provider.is_runnable());
+        provider.run()?;
+
+        let config = fs::read_to_string(state_dir.join(SSHD_CONFIG_FILENAME))
+            .map_err(|e| KnownError::TestError(e.to_string()))?;
+        assert!(config.contains("Port 2345\n"));
+        assert!(config.contains("PidFile none\n"));
+        let mode = fs::metadata(&state_dir)
+            .map_err(|e| KnownError::TestError(e.to_string()))?
+            .permissions()
+            .mode();
+        assert_eq!(0o700, mode & 0o777);
+        Ok(())
+    }
+
+    #[test]
+    fn info_shows_connection_command() {
+        let provider = get_provider(
+            Arc::new(MockTestActualRunner::new()),
+            PathBuf::from("/nonexistent"),
+            vec!["1234".into()],
+        );
+        let info = provider.get_extra_info().unwrap_or_default();
+        assert!(info.contains("(running)"));
+        assert!(info.contains(&format!("ssh -p 2345 {}@192.168.1.23", get_current_user())));
+        assert!(provider.is_killable());
+        assert!(!provider.is_runnable());
+    }
+
+    #[test]
+    fn no_password_blocks_run() {
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run().returning(|_| {
+            Ok(SysCommandResult::success_output(
+                "deck NP 01/01/2024 0 99999 7 -1",
+            ))
+        });
+        let ctx = ExecutionContext::general_for_test_with(Arc::new(mock));
+        assert_eq!(Some(false), get_user_has_password(&ctx, "deck"));
+
+        let mut provider = get_provider(
+            Arc::new(MockTestActualRunner::new()),
+            PathBuf::from("/nonexistent"),
+            vec![],
+        );
+        provider.ssh_ctx.user_has_password = Some(false);
+        assert!(!provider.is_runnable());
+        assert!(
+            provider
+                .get_unavailable_reason(&SpecificActionID::Run)
+                .is_some_and(|reason| reason.to_string().contains("passwd"))
+        );
+    }
+}
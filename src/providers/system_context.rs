@@ -1,73 +1,95 @@
-use crate::providers::geforce_now::GeForceSystemContext;
 use crate::prelude::*;
-use crate::providers::emudeck_installer::EmuDeckSystemContext;
-use crate::providers::systemd_run::SystemdRunUnitsContext;
-use crate::utils::running_in_ci_container;
-use decky_installer::DeckySystemContext;
-use flatpak::FlatpakSystemContext;
+use crate::providers::registry::{
+    downcast_provider_context, get_provider_registry, ProviderContextRc, ProviderRegistry,
+    ProviderSystemContext,
+};
+use crate::utils::{running_in_ci_container, which};
+use rayon::prelude::*;
 use std::collections::HashMap;
 
 // TODO: test
 #[derive(Debug, Clone, Default)]
 pub struct FullSystemContext {
-    pub flatpak_ctx: FlatpakSystemContext,
-    pub decky_ctx: DeckySystemContext,
-    pub emudeck_ctx: EmuDeckSystemContext,
-    pub geforce_ctx: GeForceSystemContext,
     pub procs_ctx: RunningProgramSystemContext,
-    pub systemd_run_ctx: SystemdRunUnitsContext,
     pub added_to_steam_ctx: AllKnownSteamShortcutsContext,
+    // Keyed by provider type tag, see providers::registry
+    provider_ctxs: HashMap<&'static str, ProviderContextRc>,
 }
 
 // NOTE: we gather optimistically, don't fail the whole gather if some particular error is encountered.
 impl FullSystemContext {
     pub fn gather_with(ctx: &impl ExecCtx, tricks_loader: &LoadedConfig) -> Self {
-        let (decky_ctx, flatpak_ctx, procs_ctx, emudeck_ctx, geforce_ctx, systemd_run_ctx, added_to_steam_ctx) = join_all!(
-            || DeckySystemContext::gather_with(&ctx.clone()),
-            || FlatpakSystemContext::gather_with(&ctx.clone())
-                .map_err(|e| {
-                    error!(ctx, "Error gathering Flatpak context: {}", e);
-                    e
-                })
-                .unwrap_or_default(),
+        Self::gather_with_registry(ctx, tricks_loader, &get_provider_registry())
+    }
+
+    pub fn gather_with_registry(
+        ctx: &impl ExecCtx,
+        tricks_loader: &LoadedConfig,
+        registry: &ProviderRegistry,
+    ) -> Self {
+        let registrations = registry.get_all();
+        let provider_gather_ctx = ctx.as_ctx();
+
+        let (procs_ctx, added_to_steam_ctx, provider_ctxs) = join_all!(
             || RunningProgramSystemContext::gather_with(&ctx.clone())
                 .map_err(|e| {
                     error!(ctx, "Error gathering running program context: {}", e);
                     e
                 })
                 .unwrap_or_default(),
-            || EmuDeckSystemContext::gather_with(&ctx.clone())
-                .map_err(|e| {
-                    error!(ctx, "Error gathering EmuDeck context: {}", e);
-                    e
-                })
-                .unwrap_or_default(),
-            || GeForceSystemContext::gather_with(&ctx.clone()),
-            || SystemdRunUnitsContext::gather_with(&ctx.clone(), tricks_loader)
-                .map_err(|e| {
-                    error!(ctx, "Error gathering systemd-run context: {}", e);
-                    e
-                })
-                .unwrap_or_default(),
             || AllKnownSteamShortcutsContext::gather_with(&ctx.clone())
                 .map_err(|e| {
                     error!(ctx, "Error gathering Steam shortcuts context: {}", e);
                     e
                 })
-                .unwrap_or_default()
+                .unwrap_or_default(),
+            || registrations
+                .par_iter()
+                .filter_map(|registration| {
+                    let type_tag = registration.type_tag();
+                    registration
+                        .gather_context(&provider_gather_ctx, tricks_loader)
+                        .map_err(|e| {
+                            error!(ctx, "Error gathering {} context: {}", type_tag, e);
+                            e
+                        })
+                        .ok()
+                        .flatten()
+                        .map(|provider_ctx| (type_tag, provider_ctx))
+                })
+                .collect()
         );
 
         Self {
-            flatpak_ctx,
-            decky_ctx,
-            emudeck_ctx,
-            geforce_ctx,
             procs_ctx,
-            systemd_run_ctx,
             added_to_steam_ctx,
+            provider_ctxs,
         }
     }
 
+    // Providers whose gather failed or was skipped get a default context.
+    #[must_use]
+    pub fn get_provider_context<T: ProviderSystemContext + Clone + Default>(
+        &self,
+        type_tag: &str,
+    ) -> T {
+        self.provider_ctxs
+            .get(type_tag)
+            .and_then(downcast_provider_context)
+            .unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_provider_context(
+        mut self,
+        type_tag: &'static str,
+        provider_ctx: impl ProviderSystemContext,
+    ) -> Self {
+        self.provider_ctxs
+            .insert(type_tag, std::sync::Arc::new(provider_ctx));
+        self
+    }
+
     pub fn is_installing(&self, trick_id: &TrickID) -> bool {
         self.procs_ctx
             .tricks_to_installing_pids
@@ -78,6 +100,52 @@ impl FullSystemContext {
         self.added_to_steam_ctx
             .trick_has_existing_shortcut(trick_id)
     }
+
+    #[must_use]
+    pub fn get_running_instances(&self, trick_id: &TrickID) -> Vec<ProcessID> {
+        self.procs_ctx
+            .tricks_to_running_pids
+            .get(trick_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// Where (if anywhere) the binaries for simple-command and systemd-run tricks live in $PATH.
+// These are usually present on SteamOS, but frequently missing on other distros.
+#[derive(Debug, Clone, Default)]
+pub struct BinaryPathsSystemContext {
+    resolved_paths: HashMap<String, Option<String>>,
+}
+
+impl BinaryPathsSystemContext {
+    pub fn gather_for(ctx: &impl ExecCtx, mut commands: Vec<&String>) -> Self {
+        commands.sort();
+        commands.dedup();
+
+        let resolved_paths = commands
+            .into_par_iter()
+            .map(|command| {
+                let maybe_path = which(command).ok();
+                if maybe_path.is_none() {
+                    info!(ctx, "Binary \"{command}\" was not found in $PATH.");
+                }
+                (command.clone(), maybe_path)
+            })
+            .collect();
+
+        Self { resolved_paths }
+    }
+
+    // Commands we never gathered for (e.g. when context gathering was skipped) are
+    // optimistically assumed to be present.
+    #[must_use]
+    pub fn get_resolved_path(&self, command: &str) -> Option<String> {
+        match self.resolved_paths.get(command) {
+            Some(maybe_path) => maybe_path.clone(),
+            None => Some(command.into()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -145,7 +213,7 @@ fn get_procs_with_env(ctx: &impl ExecCtx) -> Option<String> {
         return None;
     }
 
-    let run_res = ctx.sys_command("/bin/ps", ["axeww"]).run();
+    let run_res = ctx.sys_command("/bin/ps", ["axeww"]).read_only().run();
 
     match run_res {
         Ok(res) => match res.as_success() {
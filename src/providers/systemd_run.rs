@@ -1,32 +1,85 @@
 use crate::prelude::*;
+use crate::providers::registry::{
+    check_builtin_config, mismatched_config, ProviderContextRc, ProviderRegistration,
+};
+use crate::providers::system_context::{BinaryPathsSystemContext, FullSystemContext};
 use crate::system_command_runners::{SysCommandResultChecker, SysCommandRunner};
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const SYSTEMD_RUN_COMMAND: &str = "/usr/bin/systemd-run";
+const SYSTEMCTL_COMMAND: &str = "/usr/bin/systemctl";
+const JOURNALCTL_COMMAND: &str = "/usr/bin/journalctl";
+
+// System-scope units can only be started/stopped by root, so we go through polkit
+// (which will prompt for the user's password) rather than requiring decktricks to run as root.
+const PRIVILEGE_ESCALATION_COMMAND: &str = "/usr/bin/pkexec";
+
+const JOURNAL_LINES_TO_SHOW: &str = "200";
 
 #[derive(Debug)]
 pub struct SystemdRunProvider {
     pub trick_id: TrickID,
     pub ctx: SpecificExecutionContext,
-    pub is_unit_running: bool,
+    pub unit_state: SystemdUnitState,
+    // None if the command could not be found in $PATH
+    pub resolved_binary: Option<String>,
     pub systemd_run_data: SystemdRun,
 }
 
-// systemd-run --collect --working-directory=
-
 impl SystemdRunProvider {
     pub(crate) fn new(
         trick_id: TrickID,
         ctx: SpecificExecutionContext,
-        is_unit_running: bool,
+        unit_state: SystemdUnitState,
+        resolved_binary: Option<String>,
         systemd_run_data: SystemdRun,
     ) -> Self {
         Self {
             trick_id,
             ctx,
-            is_unit_running,
+            unit_state,
+            resolved_binary,
             systemd_run_data,
         }
     }
+
+    // Builds a systemctl/systemd-run invocation in the correct scope: `--user` for user units,
+    // and through the privileged path for system units.
+    fn scoped_sys_command(&self, binary: &str, args: Vec<String>) -> SysCommand {
+        scoped_sys_command(&self.ctx, binary, self.systemd_run_data.is_system(), args)
+    }
+
+    fn systemctl(&self, verb: &str) -> DeckResult<ActionSuccess> {
+        self.scoped_sys_command(
+            SYSTEMCTL_COMMAND,
+            vec![verb.into(), self.systemd_run_data.unit_id.clone()],
+        )
+        .enable_live_logging()
+        .run()?
+        .as_success()
+    }
+}
+
+fn scoped_sys_command(
+    ctx: &impl ExecCtx,
+    binary: &str,
+    is_system: bool,
+    args: Vec<String>,
+) -> SysCommand {
+    if is_system {
+        ctx.sys_command(
+            PRIVILEGE_ESCALATION_COMMAND,
+            [vec![binary.to_string()], args].concat(),
+        )
+    } else if binary == SYSTEMD_RUN_COMMAND {
+        // systemd-run gets --user from SystemdRun::get_as_args
+        ctx.sys_command(binary, args)
+    } else {
+        ctx.sys_command(binary, [vec!["--user".to_string()], args].concat())
+    }
 }
 
 impl TrickProvider for SystemdRunProvider {}
@@ -36,8 +89,7 @@ impl ProviderChecks for SystemdRunProvider {
         &self.ctx
     }
 
-    // These are meant to be simple system commands which are always known to be installed in
-    // SteamOS. You can gather `which` data in FullSystemContext, if this becomes necessary.
+    // As with simple commands, "installed" just means that we found the binary in $PATH.
     fn is_installable(&self) -> bool {
         false
     }
@@ -47,19 +99,23 @@ impl ProviderChecks for SystemdRunProvider {
     }
 
     fn is_installed(&self) -> bool {
-        true
+        self.resolved_binary.is_some()
     }
 
     fn is_runnable(&self) -> bool {
-        true
+        self.is_installed() && !self.is_running()
+    }
+
+    fn is_runnable_nested(&self) -> bool {
+        false
     }
 
     fn is_running(&self) -> bool {
-        self.is_unit_running
+        self.unit_state.is_running()
     }
 
     fn is_killable(&self) -> bool {
-        self.is_running()
+        self.is_running() || matches!(self.unit_state, SystemdUnitState::Deactivating)
     }
 
     fn is_updateable(&self) -> bool {
@@ -73,6 +129,24 @@ impl ProviderChecks for SystemdRunProvider {
     fn is_addable_to_steam_once_installed(&self) -> bool {
         true
     }
+
+    fn has_logs(&self) -> bool {
+        true
+    }
+
+    fn get_unavailable_reason(&self, action_id: &SpecificActionID) -> Option<UnavailableReason> {
+        match action_id {
+            SpecificActionID::Run | SpecificActionID::AddToSteam if !self.is_installed() => {
+                Some(UnavailableReason::MissingBinary(
+                    self.systemd_run_data.command.clone(),
+                ))
+            }
+            SpecificActionID::RunNested => Some(UnavailableReason::Unsupported(
+                "Units started by systemd don't inherit our environment, so can't be moved into the nested desktop.".into(),
+            )),
+            _ => None,
+        }
+    }
 }
 
 impl ProviderActions for SystemdRunProvider {
@@ -86,25 +160,29 @@ impl ProviderActions for SystemdRunProvider {
     }
 
     fn run(&self) -> DeckResult<ActionSuccess> {
-        let args = self.systemd_run_data.get_as_args();
+        // A failed unit keeps its name reserved until it is reset, which would make
+        // systemd-run refuse to start it again.
+        if matches!(self.unit_state, SystemdUnitState::Failed)
+            && let Err(err) = self.systemctl("reset-failed")
+        {
+            warn!(
+                &self.ctx,
+                "Failed to reset failed unit '{}', will attempt to run anyway: {err}",
+                self.systemd_run_data.unit_id
+            );
+        }
 
-        self.ctx
-            .sys_command("/usr/bin/systemd-run", args)
+        let mut args = self.systemd_run_data.get_as_args();
+        args.extend_from_slice(self.ctx.get_extra_run_args());
+
+        self.scoped_sys_command(SYSTEMD_RUN_COMMAND, args)
             .enable_live_logging()
-            //.env(PID_ENV_STRING, &self.trick_id)
             .run()?
             .as_success()
     }
 
     fn kill(&self) -> DeckResult<ActionSuccess> {
-        self.ctx
-            .sys_command(
-                "/usr/bin/systemctl",
-                ["stop", self.systemd_run_data.unit_id.as_ref()],
-            )
-            .enable_live_logging()
-            .run()?
-            .as_success()
+        self.systemctl("stop")
     }
 
     fn update(&self) -> DeckResult<ActionSuccess> {
@@ -112,9 +190,35 @@ impl ProviderActions for SystemdRunProvider {
     }
 
     fn add_to_steam(&self) -> DeckResult<ActionSuccess> {
-        add_to_steam(&AddToSteamTarget::Specific(
-            TrickAddToSteamContext::try_from(&self.ctx.trick)?,
-        ))
+        let target = TrickAddToSteamContext::try_from(&self.ctx.trick)?
+            .with_extra_args(self.ctx.get_extra_run_args());
+        add_to_steam(&self.ctx, &AddToSteamTarget::Specific(target))
+    }
+
+    fn logs(&self) -> DeckResult<ActionSuccess> {
+        let unit_id = &self.systemd_run_data.unit_id;
+
+        // The journal is readable without escalation, so we never go through pkexec here.
+        let unit_arg = if self.systemd_run_data.is_system() {
+            format!("--unit={unit_id}")
+        } else {
+            format!("--user-unit={unit_id}")
+        };
+
+        self.ctx
+            .sys_command(
+                JOURNALCTL_COMMAND,
+                [
+                    unit_arg.as_str(),
+                    "--no-pager",
+                    "--output=short-iso",
+                    "--lines",
+                    JOURNAL_LINES_TO_SHOW,
+                ],
+            )
+            .read_only()
+            .run()?
+            .as_success()
     }
 }
 
@@ -124,33 +228,263 @@ impl GeneralProvider for SystemdRun {
     }
 }
 
+// Mirrors systemd's ActiveState property. See `systemctl show -p ActiveState`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum SystemdUnitState {
+    Active,
+    Reloading,
+    Activating,
+    Deactivating,
+    Failed,
+    #[default]
+    Inactive,
+    Unknown(String),
+}
+
+impl SystemdUnitState {
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        matches!(self, Self::Active | Self::Reloading | Self::Activating)
+    }
+}
+
+impl From<&str> for SystemdUnitState {
+    fn from(active_state: &str) -> Self {
+        match active_state.trim() {
+            "active" => Self::Active,
+            "reloading" => Self::Reloading,
+            "activating" => Self::Activating,
+            "deactivating" => Self::Deactivating,
+            "failed" => Self::Failed,
+            // Units which have been collected are reported as inactive by systemd
+            "inactive" | "" => Self::Inactive,
+            other => Self::Unknown(other.into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SystemdRunRegistration;
+
+impl ProviderRegistration for SystemdRunRegistration {
+    fn type_tag(&self) -> &'static str {
+        "systemd-run"
+    }
+
+    // Each trick is its own command, which doesn't touch any other trick's
+    fn allows_parallel_actions(&self) -> bool {
+        true
+    }
+
+    fn gather_context(
+        &self,
+        ctx: &ExecutionContext,
+        tricks_loader: &LoadedConfig,
+    ) -> DeckResult<Option<ProviderContextRc>> {
+        Ok(Some(Arc::new(SystemdRunUnitsContext::gather_with(
+            ctx,
+            tricks_loader,
+        )?)))
+    }
+
+    fn check_config(&self, provider_config: &ProviderConfig) -> DeckResult<()> {
+        check_builtin_config::<SystemdRun>(provider_config)
+    }
+
+    fn construct(
+        &self,
+        ctx: &SpecificExecutionContext,
+        full_ctx: &FullSystemContext,
+    ) -> DeckResult<Box<dyn TrickProvider>> {
+        let ProviderConfig::SystemdRun(systemd_run) = &ctx.trick.provider_config else {
+            return Err(mismatched_config(self.type_tag(), &ctx.trick.provider_config));
+        };
+        let units_ctx: SystemdRunUnitsContext = full_ctx.get_provider_context(self.type_tag());
+
+        Ok(Box::new(SystemdRunProvider::new(
+            ctx.trick.id.clone(),
+            ctx.clone(),
+            units_ctx.get_state(&systemd_run.unit_id),
+            units_ctx
+                .binary_paths_ctx
+                .get_resolved_path(&systemd_run.command),
+            systemd_run.clone(),
+        )))
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct SystemdRunUnitsContext {
-    pub(crate) running_unit_ids: Vec<String>,
+    pub(crate) unit_states: HashMap<String, SystemdUnitState>,
+    pub(crate) binary_paths_ctx: BinaryPathsSystemContext,
 }
 
 impl SystemdRunUnitsContext {
     pub fn gather_with(ctx: &impl ExecCtx, tricks_loader: &LoadedConfig) -> DeckResult<Self> {
         // Find all of our detached commands
-        let all_unit_ids: Vec<String> = tricks_loader
+        let all_systemd_runs: Vec<&SystemdRun> = tricks_loader
             .get_all_tricks()
             .filter_map(|t| match &t.1.provider_config {
-                ProviderConfig::SystemdRun(systemd_run) => Some(systemd_run.unit_id.clone()),
+                ProviderConfig::SystemdRun(systemd_run) => Some(systemd_run),
                 _ => None,
             })
             .collect();
+        let all_units: Vec<(String, bool)> = all_systemd_runs
+            .iter()
+            .map(|systemd_run| (systemd_run.unit_id.clone(), systemd_run.is_system()))
+            .collect();
+        let binary_paths_ctx = BinaryPathsSystemContext::gather_for(
+            ctx,
+            all_systemd_runs.iter().map(|d| &d.command).collect(),
+        );
 
-        // Check with systemd if any of them are running
-        let running_unit_ids = all_unit_ids
+        // Ask systemd for the state of each of them. Querying state never needs privileges,
+        // so system units are queried directly instead of through pkexec.
+        let unit_states = all_units
             .into_par_iter()
-            .filter(|id| {
-                ctx.sys_command("/usr/bin/systemctl", ["is-running", id.as_ref()])
+            .map(|(id, is_system)| {
+                let scope_arg = if is_system { "--system" } else { "--user" };
+                let state = ctx
+                    .sys_command(
+                        SYSTEMCTL_COMMAND,
+                        [scope_arg, "show", "--property=ActiveState", "--value", &id],
+                    )
+                    .read_only()
                     .run()
-                    .is_ok_and(|res| res.ran_successfully())
+                    .ok()
+                    .filter(SysCommandResultChecker::ran_successfully)
+                    .and_then(|res| res.as_success().ok())
+                    .map(|succ| SystemdUnitState::from(succ.get_message_or_blank().as_str()))
+                    .unwrap_or_default();
+                (id, state)
             })
-            .map(|id| id)
             .collect();
 
-        Ok(Self { running_unit_ids })
+        Ok(Self {
+            unit_states,
+            binary_paths_ctx,
+        })
+    }
+
+    #[must_use]
+    pub fn get_state(&self, unit_id: &str) -> SystemdUnitState {
+        self.unit_states.get(unit_id).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system_command_runners::MockTestActualRunner;
+    use mockall::predicate;
+    use std::sync::Arc;
+
+    fn systemd_run(is_system: bool) -> SystemdRun {
+        SystemdRun {
+            unit_id: "decktricks-test-unit".into(),
+            command: "sleep".into(),
+            args: None,
+            execution_dir: None,
+            is_system: Some(is_system),
+            properties: None,
+            env: None,
+        }
+    }
+
+    #[test]
+    fn unit_states_are_parsed() {
+        assert_eq!(SystemdUnitState::from("active\n"), SystemdUnitState::Active);
+        assert_eq!(SystemdUnitState::from("failed"), SystemdUnitState::Failed);
+        assert_eq!(SystemdUnitState::from(""), SystemdUnitState::Inactive);
+        assert!(SystemdUnitState::from("activating").is_running());
+        assert!(!SystemdUnitState::from("failed").is_running());
+    }
+
+    #[test]
+    fn system_units_are_stopped_through_privileged_path() {
+        let expected = ExecutionContext::specific_for_test().sys_command(
+            PRIVILEGE_ESCALATION_COMMAND,
+            [SYSTEMCTL_COMMAND, "stop", "decktricks-test-unit"],
+        );
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let ctx = SpecificExecutionContext::test_with_runner(Trick::test(), Arc::new(mock));
+        let provider = SystemdRunProvider::new(
+            "test".into(),
+            ctx,
+            SystemdUnitState::Active,
+            Some("sleep".into()),
+            systemd_run(true),
+        );
+
+        assert!(provider.is_killable());
+        assert!(provider.kill().is_ok());
+    }
+
+    #[test]
+    fn user_units_are_stopped_with_user_flag() {
+        let expected = ExecutionContext::specific_for_test().sys_command(
+            SYSTEMCTL_COMMAND,
+            ["--user", "stop", "decktricks-test-unit"],
+        );
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::fake_success()));
+
+        let ctx = SpecificExecutionContext::test_with_runner(Trick::test(), Arc::new(mock));
+        let provider = SystemdRunProvider::new(
+            "test".into(),
+            ctx,
+            SystemdUnitState::Activating,
+            Some("sleep".into()),
+            systemd_run(false),
+        );
+
+        assert!(!provider.is_runnable());
+        assert!(provider.kill().is_ok());
+    }
+
+    #[test]
+    fn logs_read_from_user_journal() {
+        let expected = ExecutionContext::specific_for_test().sys_command(
+            JOURNALCTL_COMMAND,
+            [
+                "--user-unit=decktricks-test-unit",
+                "--no-pager",
+                "--output=short-iso",
+                "--lines",
+                JOURNAL_LINES_TO_SHOW,
+            ],
+        );
+
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .with(predicate::eq(expected))
+            .returning(|_| Ok(SysCommandResult::success_output("some log line")));
+
+        let ctx = SpecificExecutionContext::test_with_runner(Trick::test(), Arc::new(mock));
+        let provider = SystemdRunProvider::new(
+            "test".into(),
+            ctx,
+            SystemdUnitState::Inactive,
+            None,
+            systemd_run(false),
+        );
+
+        assert!(provider.has_logs());
+        assert!(!provider.is_runnable());
+        assert_eq!(
+            provider.logs().unwrap().get_message_or_blank(),
+            "some log line"
+        );
     }
 }
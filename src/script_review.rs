@@ -0,0 +1,340 @@
+use crate::download::get_sha256;
+use crate::prelude::*;
+use crate::utils::get_decktricks_dir;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Remote installer scripts change upstream, checksums or not. The last copy of each one which was
+// run is kept, and a new version is only run once someone has seen how it differs and accepted
+// that (with --accept-changes, or through the GUI). Everything run is recorded in an audit log,
+// along with whether it succeeded. Only a run which succeeded replaces the kept copy, so a new
+// version which fails (or is cancelled, or never gets to start) still has to be accepted next time.
+//
+// Kept copies are named after the file the script was downloaded to (e.g. decky_installer.sh),
+// which is different for every installer.
+
+// Where kept copies and the audit log live, instead of ~/.local/share/decktricks/remote-scripts/
+pub const REMOTE_SCRIPTS_DIR_ENV_VAR: &str = "DECKTRICKS_REMOTE_SCRIPTS_DIR";
+const REMOTE_SCRIPTS_DIR_NAME: &str = "remote-scripts";
+const AUDIT_LOG_FILENAME: &str = "audit.jsonl";
+
+// Binaries (like the GeForce NOW installer) aren't worth diffing line by line
+const MAX_DIFFABLE_SIZE: u64 = 1024 * 1024;
+
+// What changed in a remote script since it was last run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptReview {
+    pub url: String,
+    pub previous_sha256: String,
+    pub new_sha256: String,
+    // Unified, or a note saying why there isn't one
+    pub diff: String,
+}
+
+impl Display for ScriptReview {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} has changed since it was last run (SHA-256 {} is now {}):\n\n{}",
+            self.url, self.previous_sha256, self.new_sha256, self.diff
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    // None for general actions
+    pub trick_id: Option<TrickID>,
+    pub url: String,
+    pub sha256: String,
+    // None the first time a script is run
+    pub previous_sha256: Option<String>,
+    pub succeeded: bool,
+}
+
+// A script which has passed review, to be recorded once it has been run
+struct ReviewedScript {
+    dir: PathBuf,
+    kept: PathBuf,
+    // A copy of the script as it was reviewed, which becomes the kept copy if the run succeeds
+    copy: tempfile::NamedTempFile,
+    url: String,
+    sha256: String,
+    previous_sha256: Option<String>,
+}
+
+#[must_use]
+pub fn get_remote_scripts_dir() -> PathBuf {
+    std::env::var(REMOTE_SCRIPTS_DIR_ENV_VAR).map_or_else(
+        |_| get_decktricks_dir().join(REMOTE_SCRIPTS_DIR_NAME),
+        PathBuf::from,
+    )
+}
+
+#[must_use]
+pub fn get_audit_log_file() -> PathBuf {
+    get_remote_scripts_dir().join(AUDIT_LOG_FILENAME)
+}
+
+// Runs `script` (downloaded from `url`) with `run`, as long as it hasn't changed since it was last
+// run, or the changes were accepted. Otherwise, fails with KnownError::RemoteScriptChanged.
+pub(crate) fn review_before_running<T>(
+    ctx: &impl ExecCtx,
+    url: &str,
+    script: &Path,
+    run: impl FnOnce() -> DeckResult<T>,
+) -> DeckResult<T> {
+    // Nothing was downloaded, and nothing will be run
+    if ctx.get_runner().is_dry_run() {
+        return run();
+    }
+    run_reviewed_in(ctx, &get_remote_scripts_dir(), url, script, run)
+}
+
+fn run_reviewed_in<T>(
+    ctx: &impl ExecCtx,
+    dir: &Path,
+    url: &str,
+    script: &Path,
+    run: impl FnOnce() -> DeckResult<T>,
+) -> DeckResult<T> {
+    let reviewed = review_in(ctx, dir, url, script)?;
+    let res = run();
+    let record_res = reviewed.record_run(ctx, res.is_ok());
+    match (res, record_res) {
+        (Ok(_), Err(e)) => Err(e),
+        (res, Err(e)) => {
+            warn!(ctx, "{e}");
+            res
+        }
+        (res, Ok(())) => res,
+    }
+}
+
+fn review_err(path: &Path, e: &dyn Display) -> KnownError {
+    KnownError::RemoteScriptError(format!("Could not review \"{}\": {e}", path.display()))
+}
+
+fn review_in(
+    ctx: &impl ExecCtx,
+    dir: &Path,
+    url: &str,
+    script: &Path,
+) -> DeckResult<ReviewedScript> {
+    let Some(name) = script.file_name() else {
+        return Err(review_err(script, &"not a file"));
+    };
+    let kept = dir.join(name);
+
+    let new_sha256 = get_sha256(script).map_err(|e| review_err(script, &e))?;
+    let previous_sha256 = if kept.exists() {
+        Some(get_sha256(&kept).map_err(|e| review_err(&kept, &e))?)
+    } else {
+        None
+    };
+
+    if let Some(previous_sha256) = &previous_sha256
+        && *previous_sha256 != new_sha256
+    {
+        if !ctx.accepts_script_changes() {
+            return Err(KnownError::RemoteScriptChanged(Box::new(ScriptReview {
+                url: url.into(),
+                previous_sha256: previous_sha256.clone(),
+                new_sha256,
+                diff: get_diff(&kept, script),
+            })));
+        }
+        warn!(ctx, "Running the changed version of {url}, as accepted.");
+    }
+
+    std::fs::create_dir_all(dir).map_err(|e| review_err(dir, &e))?;
+    let mut copy = tempfile::NamedTempFile::new_in(dir).map_err(|e| review_err(dir, &e))?;
+    let mut source = std::fs::File::open(script).map_err(|e| review_err(script, &e))?;
+    std::io::copy(&mut source, copy.as_file_mut()).map_err(|e| review_err(script, &e))?;
+
+    Ok(ReviewedScript {
+        dir: dir.into(),
+        kept,
+        copy,
+        url: url.into(),
+        sha256: new_sha256,
+        previous_sha256,
+    })
+}
+
+impl ReviewedScript {
+    fn record_run(self, ctx: &impl ExecCtx, succeeded: bool) -> DeckResult<()> {
+        if succeeded {
+            self.copy
+                .persist(&self.kept)
+                .map_err(|e| review_err(&self.kept, &e.error))?;
+        }
+
+        let trick_id = match ctx.get_log_channel() {
+            LogChannel::TrickID(trick_id) => Some(trick_id.clone()),
+            LogChannel::General | LogChannel::IgnoreCompletelyAlways => None,
+        };
+        append_to_audit_log(
+            &self.dir.join(AUDIT_LOG_FILENAME),
+            &AuditEntry {
+                time: Utc::now(),
+                trick_id,
+                url: self.url,
+                sha256: self.sha256,
+                previous_sha256: self.previous_sha256,
+                succeeded,
+            },
+        )
+    }
+}
+
+fn get_diff(old: &Path, new: &Path) -> String {
+    let read_text = |path: &Path| {
+        let too_big = std::fs::metadata(path).is_ok_and(|m| m.len() > MAX_DIFFABLE_SIZE);
+        if too_big {
+            return None;
+        }
+        String::from_utf8(std::fs::read(path).ok()?).ok()
+    };
+    let (Some(old_text), Some(new_text)) = (read_text(old), read_text(new)) else {
+        return "(This is a binary file, so there's no diff to show.)".into();
+    };
+
+    TextDiff::from_lines(&old_text, &new_text)
+        .unified_diff()
+        .header("last run", "new")
+        .to_string()
+}
+
+fn append_to_audit_log(path: &Path, entry: &AuditEntry) -> DeckResult<()> {
+    let audit_err = |e: &dyn Display| {
+        KnownError::RemoteScriptError(format!(
+            "Could not write to audit log \"{}\": {e}",
+            path.display()
+        ))
+    };
+    let mut line = serde_json::to_string(entry).map_err(|e| audit_err(&e))?;
+    line.push('\n');
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| audit_err(&e))?;
+    file.write_all(line.as_bytes()).map_err(|e| audit_err(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_audit_log(dir: &Path) -> Vec<AuditEntry> {
+        std::fs::read_to_string(dir.join(AUDIT_LOG_FILENAME))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn run_ok() -> DeckResult<()> {
+        Ok(())
+    }
+
+    fn run_failing() -> DeckResult<()> {
+        Err(KnownError::TestError("installer failed".into()))
+    }
+
+    #[test]
+    fn changed_scripts_need_accepting() {
+        let scripts_dir = tempfile::tempdir().unwrap();
+        let download_dir = tempfile::tempdir().unwrap();
+        let script = download_dir.path().join("installer.sh");
+        let url = "https://example.com/installer.sh";
+        let ctx = SpecificExecutionContext::test(Trick {
+            id: "some-installer".into(),
+            ..Default::default()
+        });
+        let run = |ctx: &SpecificExecutionContext| {
+            run_reviewed_in(ctx, scripts_dir.path(), url, &script, run_ok)
+        };
+
+        // Nothing to compare against the first time
+        std::fs::write(&script, "echo one\necho two\n").unwrap();
+        run(&ctx).unwrap();
+        // Nor when nothing has changed
+        run(&ctx).unwrap();
+
+        std::fs::write(&script, "echo one\necho three\n").unwrap();
+        let Err(KnownError::RemoteScriptChanged(review)) = run(&ctx) else {
+            panic!("Changed script was not caught!");
+        };
+        assert!(review.diff.contains("-echo two\n+echo three\n"));
+        // The last copy which was run is still the one kept
+        assert_eq!(
+            "echo one\necho two\n",
+            std::fs::read_to_string(scripts_dir.path().join("installer.sh")).unwrap()
+        );
+
+        let accepting = ctx.clone().with_accept_script_changes(true);
+        run(&accepting).unwrap();
+        run(&ctx).unwrap();
+
+        let audit_log = read_audit_log(scripts_dir.path());
+        assert_eq!(4, audit_log.len());
+        assert_eq!(Some("some-installer".into()), audit_log[0].trick_id);
+        assert_eq!(None, audit_log[0].previous_sha256);
+        assert_eq!(
+            Some(audit_log[0].sha256.clone()),
+            audit_log[2].previous_sha256
+        );
+        assert_ne!(audit_log[0].sha256, audit_log[2].sha256);
+        assert_eq!(audit_log[2].sha256, audit_log[3].sha256);
+        assert!(audit_log.iter().all(|entry| entry.succeeded));
+    }
+
+    #[test]
+    fn failed_runs_are_not_kept() {
+        let scripts_dir = tempfile::tempdir().unwrap();
+        let download_dir = tempfile::tempdir().unwrap();
+        let script = download_dir.path().join("installer.sh");
+        let url = "https://example.com/installer.sh";
+        let ctx = SpecificExecutionContext::test(Trick::test());
+        let accepting = ctx.clone().with_accept_script_changes(true);
+
+        std::fs::write(&script, "echo one\n").unwrap();
+        run_reviewed_in(&ctx, scripts_dir.path(), url, &script, run_ok).unwrap();
+
+        std::fs::write(&script, "echo two\n").unwrap();
+        let res = run_reviewed_in(&accepting, scripts_dir.path(), url, &script, run_failing);
+        assert!(matches!(res, Err(KnownError::TestError(_))));
+
+        // The version which failed still needs accepting
+        assert_eq!(
+            "echo one\n",
+            std::fs::read_to_string(scripts_dir.path().join("installer.sh")).unwrap()
+        );
+        let res = run_reviewed_in(&ctx, scripts_dir.path(), url, &script, run_ok);
+        assert!(matches!(res, Err(KnownError::RemoteScriptChanged(_))));
+
+        // ...but the attempt was still recorded
+        let audit_log = read_audit_log(scripts_dir.path());
+        assert_eq!(2, audit_log.len());
+        assert!(audit_log[0].succeeded);
+        assert!(!audit_log[1].succeeded);
+        assert_ne!(audit_log[0].sha256, audit_log[1].sha256);
+    }
+
+    #[test]
+    fn binaries_are_not_diffed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        std::fs::write(&old, [0xff, 0xfe, 0x00]).unwrap();
+        std::fs::write(&new, [0xff, 0xfe, 0x01]).unwrap();
+        assert!(get_diff(&old, &new).contains("binary file"));
+    }
+}
@@ -7,4 +7,17 @@ pub enum SteamSubcommand {
         full_game_id: String,
     },
     WaitForDecktricksShortcutRelaunch,
+    Shortcuts {
+        #[clap(subcommand)]
+        shortcuts_subcommand: SteamShortcutsSubcommand,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum SteamShortcutsSubcommand {
+    // Shortcuts added by decktricks, for all Steam users
+    List {
+        #[clap(long)]
+        json: bool,
+    },
 }
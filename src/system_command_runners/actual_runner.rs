@@ -1,7 +1,20 @@
-use crate::system_command_runners::merge_stdouterr::live_log_child_and_wait_with_output;
+use crate::system_command_runners::merge_stdouterr::{live_log_merged_output, live_log_output};
+use super::pty::{PtyMaster, attach_pty};
 use super::system_command::*;
 use crate::prelude::*;
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use std::io::{IsTerminal, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ExitStatus};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// How often a running command is checked for cancellation or timeout
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// How long a cancelled command has to exit after SIGTERM, before its process group is SIGKILLed
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(3);
 
 // This file contains the actual logic for running commands.
 
@@ -17,6 +30,11 @@ pub type RunnerRc = Arc<dyn ActualRunner>;
 
 pub trait ActualRunner: Send + Sync + std::fmt::Debug {
     fn run(&self, sys_command: &SysCommand) -> DeckResult<SysCommandResult>;
+
+    // Whether side effects which don't go through `run` (file writes, downloads) should be skipped
+    fn is_dry_run(&self) -> bool {
+        false
+    }
 }
 
 use mockall::mock;
@@ -51,45 +69,75 @@ impl ActualRunner for RealWorldActualRunner {
         }
 
         let ctx = sys_command.get_ctx();
+        if ctx.get_cancel_handle().is_cancelled() {
+            return Err(KnownError::SystemCommandCancelled(Box::new(
+                sys_command.clone(),
+            )));
+        }
         let cmd = &sys_command.cmd;
         let args = &sys_command.args;
 
         // NOTE: Here is where we finally create the actual Command to be run.
 
-        let mut command;
-        if sys_command.pty_needed {
-            // Because many commands will buffer stdout if they thing they're not running in a
-            // terminal, we force a pty with the script command. This is a hacky-hacky-hack,
-            // but it fixes the problem for now.
-            //
-            // TODO: fix this hack, preferably using the portable_pty crate
-            command = std::process::Command::new("/usr/bin/script");
-            command.args(&["-q", "-e", "--command", format!("{} {}", cmd, args.join(" ")).as_ref(), "/dev/null"]);
-        } else {
-            command = std::process::Command::new(cmd);
-            command.args(args);
-        }
+        let mut command = std::process::Command::new(cmd);
+        command.args(args);
 
-        command.stderr(std::process::Stdio::piped());
-        command.stdout(std::process::Stdio::piped());
+        // Each command gets a process group of its own, so that cancelling it or timing it out
+        // also takes down anything it started.
+        let pty_master = if sys_command.pty_needed {
+            // Because many commands will buffer stdout if they think they're not running in a
+            // terminal, these get a pty instead. They also get a session of their own (with
+            // the pty as its terminal), which makes them the leader of a new process group.
+            Some(
+                attach_pty(&mut command)
+                    .map_err(|e| sys_command_error_to_known_error(sys_command, e))?,
+            )
+        } else {
+            // Since the command's group isn't in the foreground of our terminal, it would be
+            // stopped as soon as it read from it. Commands which need input from a terminal
+            // should use force_pty. Any other stdin is passed on as usual.
+            if std::io::stdin().is_terminal() {
+                command.stdin(std::process::Stdio::null());
+            }
+            command.stderr(std::process::Stdio::piped());
+            command.stdout(std::process::Stdio::piped());
+            command.process_group(0);
+            None
+        };
+        // Kept until the command is done
+        let _input_forwarder = pty_master
+            .as_ref()
+            .map(PtyMaster::forward_stdin)
+            .transpose()
+            .map_err(|e| sys_command_error_to_known_error(sys_command, e))?;
 
         for (var, val) in &sys_command.desired_env_vars {
             command.env(var, val);
         }
 
+        if let Some(dir) = &sys_command.execution_dir {
+            command.current_dir(dir);
+        }
+
         let mut child_handle = command
             .spawn()
             .map_err(|e| sys_command_error_to_known_error(sys_command, e))?;
 
-        // TODO: determine if this log level check is needed - should be redundant?
-        if sys_command.live_logging_desired {
-            let cmdline_for_logging = format!("{} {}", cmd, args.join(" "));
-            live_log_child_and_wait_with_output(ctx, cmdline_for_logging, &mut child_handle)
-                .map_err(|e| sys_command_error_to_known_error(sys_command, e))?;
-        }
+        // Our copies of the pty are closed here, so that reading from it ends once the command
+        // (and anything it started) is done with it
+        drop(command);
 
-        let output = child_handle.wait_with_output()
-            .map_err(|e| sys_command_error_to_known_error(sys_command, e))?;
+        let output_readers = match pty_master {
+            Some(pty_master) => OutputReaders::start_pty(sys_command, pty_master),
+            None => OutputReaders::start(sys_command, &mut child_handle),
+        };
+        let status = wait_for_exit(sys_command, &mut child_handle)?;
+        let (stdout, stderr) = output_readers.finish();
+        let output = std::process::Output {
+            status,
+            stdout,
+            stderr,
+        };
 
         if output.status.success() {
             info!(
@@ -117,6 +165,133 @@ impl ActualRunner for RealWorldActualRunner {
     }
 }
 
+// Reads the output of a command on other threads, so that we can keep an eye on the command itself
+enum OutputReaders {
+    // Output is sent to the logs as it arrives, and isn't kept
+    Live(Option<JoinHandle<()>>),
+    Collected(Option<JoinHandle<Vec<u8>>>, Option<JoinHandle<Vec<u8>>>),
+}
+
+impl OutputReaders {
+    fn start(sys_command: &SysCommand, child: &mut Child) -> Self {
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        if !sys_command.live_logging_desired {
+            return Self::Collected(stdout.map(read_all), stderr.map(read_all));
+        }
+
+        let Some((stdout, stderr)) = stdout.zip(stderr) else {
+            warn!(
+                sys_command.get_ctx(),
+                "Failed to get stdout/err for child process! Cmdline: {}",
+                sys_command.get_cmdline()
+            );
+            return Self::Live(None);
+        };
+        let ctx = sys_command.get_ctx().clone();
+        let progress_parser = sys_command.progress_parser;
+        Self::Live(Some(std::thread::spawn(move || {
+            live_log_output(&ctx, stdout, stderr, progress_parser);
+        })))
+    }
+
+    // Everything arrives merged through the pty, so it's all treated as stdout
+    fn start_pty(sys_command: &SysCommand, pty_master: PtyMaster) -> Self {
+        if !sys_command.live_logging_desired {
+            return Self::Collected(Some(read_all(pty_master)), None);
+        }
+
+        let ctx = sys_command.get_ctx().clone();
+        let progress_parser = sys_command.progress_parser;
+        Self::Live(Some(std::thread::spawn(move || {
+            live_log_merged_output(&ctx, pty_master, progress_parser);
+        })))
+    }
+
+    fn finish(self) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Self::Live(handle) => {
+                if let Some(handle) = handle {
+                    let _ = handle.join();
+                }
+                (vec![], vec![])
+            }
+            Self::Collected(stdout, stderr) => (join_output(stdout), join_output(stderr)),
+        }
+    }
+}
+
+fn read_all(mut reader: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn join_output(handle: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
+fn wait_for_exit(sys_command: &SysCommand, child: &mut Child) -> DeckResult<ExitStatus> {
+    let cancel_handle = sys_command.get_ctx().get_cancel_handle();
+    let deadline = sys_command.timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(KnownError::LiveSystemCommandStatusCheckError)?
+        {
+            return Ok(status);
+        }
+
+        let err = if cancel_handle.is_cancelled() {
+            KnownError::SystemCommandCancelled(Box::new(sys_command.clone()))
+        } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            KnownError::SystemCommandTimedOut(Box::new(sys_command.clone()))
+        } else {
+            std::thread::sleep(WAIT_POLL_INTERVAL);
+            continue;
+        };
+
+        warn!(sys_command.get_ctx(), "{err}");
+        kill_process_group(child)?;
+        return Err(err);
+    }
+}
+
+// The command was started as the leader of its own process group, so the group shares its pid.
+// (With a pty it leads a session of its own too, so anything left in that is sent SIGHUP once
+// it's gone.)
+fn kill_process_group(child: &mut Child) -> DeckResult<()> {
+    let pgid = Pid::from_raw(child.id().cast_signed());
+
+    // Give everything a chance to clean up after itself first
+    let _ = killpg(pgid, Signal::SIGTERM);
+    let grace_deadline = Instant::now() + KILL_GRACE_PERIOD;
+    while Instant::now() < grace_deadline {
+        let exited = child
+            .try_wait()
+            .map_err(KnownError::LiveSystemCommandStatusCheckError)?
+            .is_some();
+        if exited {
+            break;
+        }
+        std::thread::sleep(WAIT_POLL_INTERVAL);
+    }
+
+    // The leader may be gone while the rest of the group lives on, so this happens regardless
+    match killpg(pgid, Signal::SIGKILL) {
+        Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+        Err(errno) => return Err(KnownError::LiveSystemCommandKillError(errno.into())),
+    }
+    child
+        .wait()
+        .map_err(KnownError::LiveSystemCommandStatusCheckError)?;
+    Ok(())
+}
+
 fn sys_command_error_to_known_error(sys_command: &SysCommand, e: std::io::Error) -> KnownError {
     let args = sys_command.args.join(" ");
     error!(
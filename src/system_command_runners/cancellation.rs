@@ -0,0 +1,134 @@
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Shared by everything run on behalf of a single action. RealWorldActualRunner checks this while
+// waiting on a command, and kills the command's whole process group once it has been cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+// The actions currently being run by an executor (and all of its clones), so that they can be
+// cancelled from elsewhere: the GUI's cancel button, or Ctrl-C in the CLI.
+#[derive(Debug, Clone, Default)]
+pub struct RunningActions {
+    running: Arc<Mutex<Vec<(LogChannel, CancelHandle)>>>,
+}
+
+impl RunningActions {
+    // The action is tracked until the returned guard is dropped
+    #[must_use]
+    pub(crate) fn track(&self, ctx: &impl ExecCtx) -> RunningActionGuard {
+        let channel = ctx.get_log_channel().clone();
+        let handle = ctx.get_cancel_handle().clone();
+        if let Ok(mut running) = self.running.lock() {
+            running.push((channel, handle.clone()));
+        }
+        RunningActionGuard {
+            running: self.clone(),
+            handle,
+        }
+    }
+
+    // Returns the number of actions which were cancelled
+    #[must_use]
+    pub fn cancel(&self, trick_id: &str) -> usize {
+        self.cancel_matching(|channel| matches!(channel, LogChannel::TrickID(id) if id == trick_id))
+    }
+
+    #[must_use]
+    pub fn cancel_all(&self) -> usize {
+        self.cancel_matching(|_| true)
+    }
+
+    #[must_use]
+    pub fn is_running(&self, trick_id: &str) -> bool {
+        self.running.lock().is_ok_and(|running| {
+            running
+                .iter()
+                .any(|(channel, _)| matches!(channel, LogChannel::TrickID(id) if id == trick_id))
+        })
+    }
+
+    fn cancel_matching(&self, should_cancel: impl Fn(&LogChannel) -> bool) -> usize {
+        let Ok(running) = self.running.lock() else {
+            return 0;
+        };
+        let mut num_cancelled = 0;
+        for (channel, handle) in running.iter() {
+            if should_cancel(channel) && !handle.is_cancelled() {
+                handle.cancel();
+                num_cancelled += 1;
+            }
+        }
+        num_cancelled
+    }
+}
+
+pub(crate) struct RunningActionGuard {
+    running: RunningActions,
+    handle: CancelHandle,
+}
+
+impl Drop for RunningActionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.running.running.lock() {
+            running.retain(|(_, handle)| !handle.is_same(&self.handle));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_only_reaches_the_named_trick() {
+        let running = RunningActions::default();
+        let ctx1 = SpecificExecutionContext::test(Trick {
+            id: "trick1".into(),
+            ..Default::default()
+        });
+        let ctx2 = SpecificExecutionContext::test(Trick {
+            id: "trick2".into(),
+            ..Default::default()
+        });
+
+        let guard1 = running.track(&ctx1);
+        let _guard2 = running.track(&ctx2);
+        assert!(running.is_running("trick1"));
+
+        assert_eq!(1, running.cancel("trick1"));
+        assert!(ctx1.get_cancel_handle().is_cancelled());
+        assert!(!ctx2.get_cancel_handle().is_cancelled());
+
+        // Already cancelled, so there's nothing new to cancel
+        assert_eq!(0, running.cancel("trick1"));
+
+        drop(guard1);
+        assert!(!running.is_running("trick1"));
+        assert_eq!(1, running.cancel_all());
+        assert!(ctx2.get_cancel_handle().is_cancelled());
+    }
+}
@@ -0,0 +1,172 @@
+use super::system_command::{SysCommand, SysCommandResult};
+use crate::prelude::*;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+// Stands in for RealWorldActualRunner with `--dry-run`: every command is printed and recorded
+// instead of being run, and is treated as having succeeded with no output.
+//
+// The system context is still gathered with the real runner before this is swapped in, and
+// commands marked read-only are passed on to it (they only read state, and actions decide what to
+// do from their output), so the actions which would be taken are the same ones a real run would
+// take. With `--no-gather`, nothing at all is run.
+#[derive(Debug, Default)]
+pub struct DryRunActualRunner {
+    real_runner: Option<RunnerRc>,
+    recorded: Mutex<Vec<SysCommand>>,
+}
+
+impl DryRunActualRunner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_real_runner(real_runner: RunnerRc) -> Self {
+        Self {
+            real_runner: Some(real_runner),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn get_recorded(&self) -> Vec<SysCommand> {
+        self.recorded
+            .lock()
+            .map(|recorded| recorded.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl ActualRunner for DryRunActualRunner {
+    fn run(&self, sys_command: &SysCommand) -> DeckResult<SysCommandResult> {
+        if sys_command.read_only
+            && let Some(real_runner) = &self.real_runner
+        {
+            return real_runner.run(sys_command);
+        }
+
+        print_dry_run(
+            sys_command.get_ctx(),
+            format!("run: {}", get_shell_line(sys_command)),
+        );
+        if let Ok(mut recorded) = self.recorded.lock() {
+            recorded.push(sys_command.clone());
+        }
+        Ok(SysCommandResult::success_without_output(
+            sys_command.clone(),
+        ))
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+// For side effects which don't go through a runner, like writing files or downloading. Returns
+// true if the caller should skip doing `description`.
+pub(crate) fn skip_for_dry_run(ctx: &impl ExecCtx, description: impl Display) -> bool {
+    let is_dry_run = ctx.get_runner().is_dry_run();
+    if is_dry_run {
+        print_dry_run(ctx, description);
+    }
+    is_dry_run
+}
+
+// This is the whole point of dry runs, so it's shown whatever the log level is
+fn print_dry_run(ctx: &impl ExecCtx, description: impl Display) {
+    let line = format!("[DRY RUN] Would {description}");
+    stdout_println!(ctx, line);
+}
+
+// Something which could be pasted into a shell to do the same thing
+fn get_shell_line(sys_command: &SysCommand) -> String {
+    let mut words = vec![];
+    if let Some(dir) = &sys_command.execution_dir {
+        words.push(format!("cd {} &&", shell_quote(dir)));
+    }
+    for (var, val) in &sys_command.desired_env_vars {
+        words.push(format!("{var}={}", shell_quote(val)));
+    }
+    words.push(shell_quote(&sys_command.cmd));
+    words.extend(sys_command.args.iter().map(|arg| shell_quote(arg)));
+    words.join(" ")
+}
+
+fn shell_quote(word: &str) -> String {
+    let is_safe = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    if is_safe {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn records_instead_of_running() {
+        let runner = Arc::new(DryRunActualRunner::new());
+        let ctx = GeneralExecutionContext::test_with_runner(runner.clone());
+
+        let res = ctx
+            .sys_command("flatpak", ["install", "-y", "com.example.App"])
+            .run()
+            .unwrap();
+        assert!(res.ran_successfully());
+        assert!(skip_for_dry_run(&ctx, "write a file"));
+
+        let recorded = runner.get_recorded();
+        assert_eq!(1, recorded.len());
+        assert_eq!("flatpak", recorded[0].cmd);
+    }
+
+    #[test]
+    fn read_only_commands_still_run() {
+        let mut mock = MockTestActualRunner::new();
+        mock.expect_run()
+            .times(1)
+            .withf(|sys_command| sys_command.cmd == "flatpak" && sys_command.args[0] == "list")
+            .returning(|_| Ok(SysCommandResult::success_output("com.example.App")));
+        let runner = Arc::new(DryRunActualRunner::with_real_runner(Arc::new(mock)));
+        let ctx = GeneralExecutionContext::test_with_runner(runner.clone());
+
+        let listed = ctx
+            .sys_command("flatpak", ["list", "--app"])
+            .read_only()
+            .run()
+            .unwrap();
+        assert_eq!("com.example.App", listed.as_success().unwrap().get_message_or_blank());
+        ctx.sys_command("flatpak", ["uninstall", "-y", "com.example.App"])
+            .run()
+            .unwrap();
+
+        let recorded = runner.get_recorded();
+        assert_eq!(1, recorded.len());
+        assert_eq!("uninstall", recorded[0].args[0]);
+    }
+
+    #[test]
+    fn shell_line_is_quoted() {
+        let mut sys_command =
+            GeneralExecutionContext::test().sys_command("echo", ["it's", "plain", "two words"]);
+        sys_command.current_dir("/tmp").env("FOO", "bar baz");
+        assert_eq!(
+            "cd /tmp && FOO='bar baz' echo 'it'\\''s' plain 'two words'",
+            get_shell_line(&sys_command)
+        );
+    }
+
+    #[test]
+    fn real_runners_are_not_dry_runs() {
+        let ctx = GeneralExecutionContext::test();
+        assert!(!skip_for_dry_run(&ctx, "write a file"));
+    }
+}
@@ -1,9 +1,9 @@
 use std::io::Write;
 use crate::prelude::*;
+use crate::progress::{ProgressParser, send_progress};
 
-use std::io::{BufRead, BufReader};
-use std::process::Child;
-use std::sync::mpsc::{self, Sender};
+use std::process::{ChildStderr, ChildStdout};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
 
 pub(super) enum StreamLine {
@@ -11,50 +11,116 @@ pub(super) enum StreamLine {
     Stderr(String),
 }
 
+// How a piece of output ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LineEnding {
+    // A newline, so it's a line of its own
+    Newline,
+    // A carriage return, so it's about to be drawn over (progress bars do this)
+    Redraw,
+    // Nothing yet, it's just as much as has been written so far
+    Unfinished,
+}
+
+// Progress is redrawn with \r rather than printed on new lines, and often never finishes a line
+// until it's done, so output is sent on as soon as it arrives rather than a line at a time.
 fn forward_lines<R: std::io::Read + Send + 'static>(
-    reader: R,
-    tx: Sender<StreamLine>,
+    mut reader: R,
+    tx: Sender<(StreamLine, LineEnding)>,
     which: fn(String) -> StreamLine,
 ) {
     thread::spawn(move || {
-        let buf = BufReader::new(reader);
-        for line in buf.lines() {
-            match line {
-                Ok(line) => {
-                    // send, ignore if receiver hung up
-                    let _ = tx.send(which(line));
-                }
+        let send = |text: &[u8], ending| {
+            // send, ignore if receiver hung up
+            let _ = tx.send((which(String::from_utf8_lossy(text).into_owned()), ending));
+        };
+        let mut buf = [0u8; 4096];
+        let mut line = vec![];
+        // A \r is only a redraw if it isn't followed by \n (which ptys end lines with)
+        let mut after_cr = false;
+        loop {
+            let num_read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(num_read) => num_read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(err) => {
                     eprintln!("error reading stream: {}", err);
                     break;
                 }
+            };
+            for &byte in &buf[..num_read] {
+                match byte {
+                    b'\n' => {
+                        send(&line, LineEnding::Newline);
+                        line.clear();
+                        after_cr = false;
+                    }
+                    b'\r' => after_cr = true,
+                    _ => {
+                        if after_cr {
+                            if !line.is_empty() {
+                                send(&line, LineEnding::Redraw);
+                            }
+                            line.clear();
+                            after_cr = false;
+                        }
+                        line.push(byte);
+                    }
+                }
+            }
+            if !line.is_empty() && !after_cr {
+                send(&line, LineEnding::Unfinished);
             }
         }
+        if !line.is_empty() {
+            send(&line, LineEnding::Newline);
+        }
     });
 }
 
-pub(super) fn live_log_child_and_wait_with_output(
+pub(super) fn live_log_output(
     ctx: &impl ExecCtx,
-    cmdline_for_logging: String,
-    child: &mut Child,
-) -> std::io::Result<()> {
-    let (stdout, stderr) = match child.stdout.take().zip(child.stderr.take()) {
-        Some((out, err)) => (out, err),
-        None => {
-            warn!(
-                ctx,
-                "Failed to get stdout/err for child process! Cmdline: {}", cmdline_for_logging
-            );
-            return Ok(());
-        }
-    };
-
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    progress_parser: Option<ProgressParser>,
+) {
     let (tx, rx) = mpsc::channel();
 
     forward_lines(stdout, tx.clone(), StreamLine::Stdout);
     forward_lines(stderr, tx, StreamLine::Stderr);
 
-    for msg in rx {
+    log_lines(ctx, rx, progress_parser);
+}
+
+// For output which has already been merged, like a pty's
+pub(super) fn live_log_merged_output<R: std::io::Read + Send + 'static>(
+    ctx: &impl ExecCtx,
+    output: R,
+    progress_parser: Option<ProgressParser>,
+) {
+    let (tx, rx) = mpsc::channel();
+
+    forward_lines(output, tx, StreamLine::Stdout);
+
+    log_lines(ctx, rx, progress_parser);
+}
+
+fn log_lines(
+    ctx: &impl ExecCtx,
+    rx: Receiver<(StreamLine, LineEnding)>,
+    progress_parser: Option<ProgressParser>,
+) {
+    for (msg, ending) in rx {
+        let (StreamLine::Stdout(line) | StreamLine::Stderr(line)) = &msg;
+        if let Some(update) = progress_parser.and_then(|parse| parse(line)) {
+            send_progress(ctx, update);
+        }
+        // Anything drawn over (or not yet finished) would only have been seen for a moment in a
+        // terminal, so it isn't kept in the logs
+        if ending != LineEnding::Newline {
+            continue;
+        }
+
         match msg {
             StreamLine::Stdout(line) => {
                 stdout_println!(ctx.as_ctx(), line)
@@ -66,7 +132,66 @@ pub(super) fn live_log_child_and_wait_with_output(
         std::io::stdout().flush().unwrap();
         std::io::stderr().flush().unwrap();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Hands out its chunks one read at a time, like a command writing them out over time
+    struct ChunkedReader(VecDeque<&'static [u8]>);
+
+    impl std::io::Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Ok(0);
+            };
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
 
-    // We don't need to wait here, as we'll do wait_with_output later outside
-    Ok(())
+    fn forward_chunks(chunks: &[&'static [u8]]) -> Vec<(String, LineEnding)> {
+        let (tx, rx) = mpsc::channel();
+        forward_lines(
+            ChunkedReader(chunks.iter().copied().collect()),
+            tx,
+            StreamLine::Stdout,
+        );
+        rx.into_iter()
+            .map(|(line, ending)| match line {
+                StreamLine::Stdout(text) | StreamLine::Stderr(text) => (text, ending),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn redrawn_progress_arrives_as_it_is_drawn() {
+        let forwarded = forward_chunks(&[
+            b"Looking for matches\r\n",
+            b"\rInstalling 1/1\xe2\x80\xa6  10%",
+            b"\rInstalling 1/1\xe2\x80\xa6  80%",
+            b"\rInstalling 1/1\xe2\x80\xa6 100%\r",
+            b"\nDone\n",
+        ]);
+        assert_eq!(
+            vec![
+                ("Looking for matches".into(), LineEnding::Newline),
+                ("Installing 1/1…  10%".into(), LineEnding::Unfinished),
+                ("Installing 1/1…  10%".into(), LineEnding::Redraw),
+                ("Installing 1/1…  80%".into(), LineEnding::Unfinished),
+                ("Installing 1/1…  80%".into(), LineEnding::Redraw),
+                ("Installing 1/1… 100%".into(), LineEnding::Newline),
+                ("Done".into(), LineEnding::Newline),
+            ],
+            forwarded
+        );
+
+        let percents: Vec<_> = forwarded
+            .iter()
+            .filter_map(|(line, _)| crate::progress::parse_flatpak_progress(line)?.percent)
+            .collect();
+        assert_eq!(vec![10, 10, 80, 80, 100], percents);
+    }
 }
@@ -1,7 +1,13 @@
 mod actual_runner;
+mod cancellation;
+mod dry_run_runner;
 mod system_command;
 mod merge_stdouterr;
+mod pty;
 
 // TODO: clean up defs and imports
 pub use actual_runner::*;
+pub use cancellation::*;
+pub use dry_run_runner::DryRunActualRunner;
+pub(crate) use dry_run_runner::skip_for_dry_run;
 pub use system_command::*;
@@ -0,0 +1,266 @@
+use nix::errno::Errno;
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+use nix::libc;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::pty::{OpenptyResult, Winsize, openpty};
+use nix::sys::termios::{
+    LocalFlags, SetArg, SpecialCharacterIndices, Termios, tcgetattr, tcsetattr,
+};
+use nix::unistd::{getpgrp, tcgetpgrp};
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+// Many commands buffer their output (or hide progress entirely) when they aren't writing to a
+// terminal, so some are given a pty to write to instead. The command's stdout and stderr both go
+// to the pty, so their output arrives merged, through the master side of it.
+//
+// Our own input is passed on to the pty while the command runs, so that it can still ask for
+// things (passwords for sudo, installer questions).
+
+// Used when we aren't running in a terminal ourselves (in the GUI, for example)
+const DEFAULT_TERMINAL_SIZE: (u16, u16) = (24, 80);
+
+// What a terminal sends when asked for EOF (VEOF, ^D)
+const EOF_CHAR: u8 = 0x04;
+// How often the input forwarder checks whether the command is done
+const INPUT_POLL_INTERVAL_MS: u16 = 100;
+
+// Only one command at a time gets our input, any others just see EOF
+static INPUT_IN_USE: AtomicBool = AtomicBool::new(false);
+
+// Connects the command's stdin, stdout and stderr to a new pty, which is also made its
+// controlling terminal. Returns the master side, to read the command's output from.
+//
+// NOTE: The command becomes the leader of a new session (and so of a new process group), so this
+//       can't be combined with CommandExt::process_group.
+pub(super) fn attach_pty(command: &mut Command) -> std::io::Result<PtyMaster> {
+    let OpenptyResult { master, slave } = openpty(&get_terminal_size(), None)?;
+    // Neither side should be inherited by the command as anything other than stdin/out/err
+    set_cloexec(&master)?;
+    set_cloexec(&slave)?;
+
+    command.stdin(slave.try_clone()?);
+    command.stdout(slave.try_clone()?);
+    command.stderr(slave);
+
+    // SAFETY: Only async-signal-safe functions are called between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            // stdin is the pty by now
+            Errno::result(libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0))?;
+            Ok(())
+        });
+    }
+
+    Ok(PtyMaster(File::from(master)))
+}
+
+fn set_cloexec(fd: &OwnedFd) -> std::io::Result<()> {
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    Ok(())
+}
+
+// The same size as our own terminal, if we're in one
+fn get_terminal_size() -> Winsize {
+    let (ws_row, ws_col) = DEFAULT_TERMINAL_SIZE;
+    let mut size = Winsize {
+        ws_row,
+        ws_col,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let mut ours = size;
+    // SAFETY: TIOCGWINSZ only writes a winsize to the pointer it's given
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &raw mut ours) };
+    if res == 0 && ours.ws_row > 0 && ours.ws_col > 0 {
+        size = ours;
+    }
+    size
+}
+
+// Once the command (and anything else using the pty) has exited, reading from the master side
+// fails with EIO instead of returning EOF, so that's treated as EOF here.
+pub(super) struct PtyMaster(File);
+
+impl PtyMaster {
+    // Passes our stdin on to the command until the returned forwarder is dropped. If we can't
+    // (another command has it, or we're in the background of our terminal), the command gets EOF.
+    pub(super) fn forward_stdin(&self) -> std::io::Result<InputForwarder> {
+        let mut writer = self.0.try_clone()?;
+        let stdin = std::io::stdin();
+        let is_terminal = stdin.is_terminal();
+        // Reading from our terminal from the background would get us stopped
+        let is_usable = !is_terminal || tcgetpgrp(&stdin).is_ok_and(|pgrp| pgrp == getpgrp());
+        let got_input = is_usable
+            && INPUT_IN_USE
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+        if !got_input {
+            writer.write_all(&[EOF_CHAR])?;
+            return Ok(InputForwarder::default());
+        }
+
+        // From here on, dropping the forwarder gives the input back
+        let mut forwarder = InputForwarder::default();
+        forwarder.holds_input = true;
+        if is_terminal {
+            forwarder.saved_termios = Some(set_noncanonical(&stdin)?);
+        }
+        let input = stdin.as_fd().try_clone_to_owned()?;
+        forwarder.start(input, writer);
+        Ok(forwarder)
+    }
+}
+
+#[derive(Default)]
+pub(super) struct InputForwarder {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    // Our terminal's settings from before the command started, to be put back afterwards
+    saved_termios: Option<Termios>,
+    holds_input: bool,
+}
+
+impl InputForwarder {
+    fn start(&mut self, input: OwnedFd, mut writer: File) {
+        let stop = self.stop.clone();
+        self.handle = Some(std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while !stop.load(Ordering::Relaxed) {
+                let mut fds = [PollFd::new(input.as_fd(), PollFlags::POLLIN)];
+                match poll(&mut fds, PollTimeout::from(INPUT_POLL_INTERVAL_MS)) {
+                    Ok(0) | Err(Errno::EINTR) => continue,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+                let res = match nix::unistd::read(input.as_raw_fd(), &mut buf) {
+                    Ok(0) => {
+                        let _ = writer.write_all(&[EOF_CHAR]);
+                        break;
+                    }
+                    Ok(num_read) => writer.write_all(&buf[..num_read]),
+                    Err(Errno::EINTR | Errno::EAGAIN) => continue,
+                    Err(_) => break,
+                };
+                // The command is gone
+                if res.is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+}
+
+impl Drop for InputForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        if let Some(termios) = &self.saved_termios {
+            let _ = tcsetattr(std::io::stdin(), SetArg::TCSANOW, termios);
+        }
+        if self.holds_input {
+            INPUT_IN_USE.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// Keypresses are sent on as they happen, and the pty does the echoing (or not, for passwords).
+// Signals are left alone, so ^C still cancels the command the usual way.
+fn set_noncanonical(terminal: impl AsFd + Copy) -> nix::Result<Termios> {
+    let saved = tcgetattr(terminal)?;
+    let mut termios = saved.clone();
+    termios
+        .local_flags
+        .remove(LocalFlags::ICANON | LocalFlags::ECHO);
+    termios.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+    termios.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+    tcsetattr(terminal, SetArg::TCSANOW, &termios)?;
+    Ok(saved)
+}
+
+impl Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_in_pty_with_input(args: &[&str], input: Option<&str>) -> String {
+        let mut command = Command::new("sh");
+        command.args(args);
+        let mut master = attach_pty(&mut command).unwrap();
+
+        let mut forwarder = InputForwarder::default();
+        if let Some(input) = input {
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            writer.write_all(input.as_bytes()).unwrap();
+            drop(writer);
+            forwarder.start(reader.into(), master.0.try_clone().unwrap());
+        }
+
+        let mut child = command.spawn().unwrap();
+        // Our copies of the slave side have to be closed for EOF to ever arrive
+        drop(command);
+
+        let mut output = String::new();
+        master.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        output
+    }
+
+    fn run_in_pty(args: &[&str]) -> String {
+        run_in_pty_with_input(args, None)
+    }
+
+    #[test]
+    fn output_is_a_terminal() {
+        let output = run_in_pty(&["-c", "test -t 1 && test -t 2 && echo out && echo err >&2"]);
+        // The pty turns newlines into CRLF
+        assert_eq!("out\r\nerr\r\n", output);
+    }
+
+    #[test]
+    fn args_are_passed_as_is() {
+        let arg = "two  spaces; $HOME \"quoted\" 'single' `backticks`";
+        let output = run_in_pty(&["-c", "printf '%s' \"$1\"", "sh", arg]);
+        assert_eq!(arg, output);
+    }
+
+    #[test]
+    fn input_is_forwarded() {
+        let output = run_in_pty_with_input(
+            &[
+                "-c",
+                "read -r line && echo \"got $line\" && cat && echo done",
+            ],
+            Some("hello\n"),
+        );
+        // The pty echoes what it's given, and cat gets EOF once the input has run out
+        assert_eq!("hello\r\ngot hello\r\ndone\r\n", output);
+    }
+
+    #[test]
+    fn pty_is_the_controlling_terminal() {
+        let output = run_in_pty(&["-c", "stty size < /dev/tty"]);
+        let mut size = output.split_whitespace().map(|n| n.parse::<u16>().unwrap());
+        let expected = get_terminal_size();
+        assert_eq!(Some(expected.ws_row), size.next());
+        assert_eq!(Some(expected.ws_col), size.next());
+    }
+}
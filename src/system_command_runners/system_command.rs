@@ -1,6 +1,8 @@
 use crate::prelude::*;
+use crate::progress::ProgressParser;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 // This is not the logic for actually running system commands, just the representation of what
 // those commands are and the context within which they run.
@@ -17,8 +19,15 @@ pub struct SysCommand {
     pub cmd: String,
     pub args: Vec<String>,
     pub desired_env_vars: Vec<(String, String)>,
+    pub execution_dir: Option<String>,
     pub live_logging_desired: bool,
     pub pty_needed: bool,
+    // Still run under --dry-run, see read_only()
+    pub read_only: bool,
+    // The command's process group is killed if it runs for longer than this
+    pub timeout: Option<Duration>,
+    // Only used along with live logging, since that's when output is read as it arrives
+    pub progress_parser: Option<ProgressParser>,
 }
 
 impl PartialEq for SysCommand {
@@ -26,6 +35,7 @@ impl PartialEq for SysCommand {
         self.cmd == other.cmd
             && self.args == other.args
             && self.desired_env_vars == other.desired_env_vars
+            && self.execution_dir == other.execution_dir
     }
 }
 
@@ -37,13 +47,26 @@ impl SysCommand {
         S: StringType,
         SS: StringType,
     {
+        let ctx = ctx.as_ctx();
+        let desired_env_vars = match &ctx {
+            ExecutionContext::Specific(specific_ctx) => specific_ctx
+                .extra_env
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            ExecutionContext::General(_) => Vec::default(),
+        };
         Self {
-            ctx: Arc::new(ctx.as_ctx()),
+            ctx: Arc::new(ctx),
             cmd: cmd.to_string(),
             args: args.into_iter().map(|x| x.to_string()).collect(),
-            desired_env_vars: Vec::default(),
+            desired_env_vars,
+            execution_dir: None,
             live_logging_desired: false,
             pty_needed: false,
+            read_only: false,
+            timeout: None,
+            progress_parser: None,
         }
     }
 
@@ -56,6 +79,46 @@ impl SysCommand {
         self.live_logging_desired = true;
         self
     }
+
+    // For commands which only look at system state. These still run under --dry-run, so that
+    // whatever is decided from their output is the same as for a real run.
+    pub fn read_only(&mut self) -> &mut Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Lines of output which the parser understands are sent on as progress events
+    pub fn parse_progress(&mut self, parser: ProgressParser) -> &mut Self {
+        self.progress_parser = Some(parser);
+        self
+    }
+
+    pub fn current_dir(&mut self, dir: &str) -> &mut Self {
+        self.execution_dir = Some(dir.into());
+        self
+    }
+
+    #[must_use]
+    pub fn get_cmdline(&self) -> String {
+        std::iter::once(&self.cmd)
+            .chain(&self.args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // Applies the working directory and environment configured for a trick, if any
+    pub fn with_environment(&mut self, execution_dir: Option<&String>, env: &EnvVars) -> &mut Self {
+        if let Some(dir) = execution_dir {
+            self.current_dir(dir);
+        }
+        self.envs(env)
+    }
 }
 
 pub trait SysCommandRunner {
@@ -64,6 +127,13 @@ pub trait SysCommandRunner {
 
     fn env(&mut self, varname: &str, value: &str) -> &mut Self;
 
+    fn envs(&mut self, vars: &EnvVars) -> &mut Self {
+        for (varname, value) in vars {
+            self.env(varname, value);
+        }
+        self
+    }
+
     fn run(&self) -> DeckResult<SysCommandResult> {
         let sys_command = self.get_cmd();
         self.get_ctx().get_runner().run(sys_command)
@@ -98,6 +168,19 @@ impl SysCommandResult {
             raw_output: output,
         }
     }
+
+    pub(super) fn success_without_output(sys_command: SysCommand) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+
+        Self::new(
+            sys_command,
+            std::process::Output {
+                status: std::process::ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            },
+        )
+    }
 }
 
 impl std::fmt::Debug for SysCommandResult {
@@ -1,5 +1,16 @@
 use std::sync::Arc;
+use crate::download::{DownloadSettings, DownloadVerification};
 use crate::prelude::*;
+use crate::providers::bundle::{BundleRegistration, check_bundle_members};
+use crate::providers::decky_installer::DeckyInstallerRegistration;
+use crate::providers::emudeck_installer::EmuDeckInstallerRegistration;
+use crate::providers::flatpak::FlatpakRegistration;
+use crate::providers::geforce_now::GeForceInstallerRegistration;
+use crate::providers::registry::{ProviderRegistration, ProviderRegistry, get_provider_registry};
+use crate::providers::simple_command::SimpleCommandRegistration;
+use crate::providers::ssh_session::SshSessionRegistration;
+use crate::providers::systemd_run::SystemdRunRegistration;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::collections::btree_map::Iter;
@@ -32,14 +43,19 @@ impl From<serde_json::Error> for KnownError {
     }
 }
 
-#[derive(Debug, Clone, Default, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DecktricksConfigSettings {
     pub controller_layout_id: String,
     pub add_to_steam_on_install_where_applicable: bool,
+    #[serde(default)]
+    pub downloads: DownloadSettings,
 }
 
 pub type SettingsRc = Arc<DecktricksConfigSettings>;
 
+// Environment variables to set for a trick, as configured in the config file
+pub type EnvVars = BTreeMap<String, String>;
+
 #[derive(Debug, Clone)]
 pub struct LoadedConfig {
     tricks: BTreeMap<TrickID, Trick>,
@@ -51,6 +67,16 @@ impl TryFrom<&str> for LoadedConfig {
     type Error = KnownError;
 
     fn try_from(text: &str) -> DeckResult<Self> {
+        Self::from_str_with_registry(text, &get_provider_registry())
+    }
+}
+
+impl LoadedConfig {
+    /// # Errors
+    ///
+    /// Returns errors relating to config parsing, providers `registry` doesn't know about, or
+    /// bundles with members which aren't tricks of their own.
+    pub fn from_str_with_registry(text: &str, registry: &ProviderRegistry) -> DeckResult<Self> {
         let mut config = TricksConfig::try_from(text)?;
 
         // Since we will almost always be sorting by display name
@@ -59,8 +85,14 @@ impl TryFrom<&str> for LoadedConfig {
 
         let mut tricks = BTreeMap::new();
         for trick in config.tricks {
+            registry.check_trick(&trick)?;
             tricks.insert(trick.id.clone(), trick);
         }
+        for trick in tricks.values() {
+            if let ProviderConfig::Bundle(bundle) = &trick.provider_config {
+                check_bundle_members(&trick.id, bundle, &tricks)?;
+            }
+        }
 
         let categories = config.known_categories;
 
@@ -68,9 +100,7 @@ impl TryFrom<&str> for LoadedConfig {
 
         Ok(Self { tricks, categories, settings })
     }
-}
 
-impl LoadedConfig {
     // NOTE: Currently, this does *not* read from the config file at runtime!
     //       The config is read at compile time, so you need to cargo build/run
     //       to see changes to the config.
@@ -145,6 +175,8 @@ pub struct Trick {
     pub always_present_on_steamdeck: Option<bool>,
     pub icon: Option<String>,
     pub categories: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ActionWarning>,
     //download: Option<String>,
     //command_before: Option<String>,
     //command_after: Option<String>,
@@ -160,12 +192,89 @@ impl Default for Trick {
                 command: Default::default(),
                 args: Default::default(),
                 execution_dir: Default::default(),
+                env: None,
             }),
             categories: Default::default(),
             display_name: Default::default(),
             description: Default::default(),
             always_present_on_steamdeck: Default::default(),
             icon: Default::default(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Trick {
+    // The warnings to show before taking this action right now
+    #[must_use]
+    pub fn get_warnings(&self, action_id: &SpecificActionID) -> Vec<&ActionWarning> {
+        let session_mode = SessionMode::current();
+        self.warnings
+            .iter()
+            .filter(|warning| warning.applies_to(action_id, session_mode))
+            .collect()
+    }
+}
+
+// Shown before an action is taken on a trick, and the user has to confirm before it goes ahead
+// (with --yes on the commandline, or a dialog in the GUI)
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ActionWarning {
+    pub action: SpecificActionID,
+    #[serde(default)]
+    pub severity: WarningSeverity,
+    pub text: String,
+    // Only warn in this mode, or in both if not given
+    pub only_in: Option<SessionMode>,
+}
+
+impl ActionWarning {
+    #[must_use]
+    pub fn applies_to(&self, action_id: &SpecificActionID, session_mode: SessionMode) -> bool {
+        self.action == *action_id && self.only_in.is_none_or(|mode| mode == session_mode)
+    }
+}
+
+impl std::fmt::Display for ActionWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.severity, self.text)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningSeverity {
+    #[default]
+    Note,
+    Warning,
+    Danger,
+}
+
+impl std::fmt::Display for WarningSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Note => write!(f, "NOTE!"),
+            Self::Warning => write!(f, "WARNING!"),
+            Self::Danger => write!(f, "DANGER!"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionMode {
+    GameMode,
+    DesktopMode,
+}
+
+impl SessionMode {
+    #[must_use]
+    pub fn current() -> Self {
+        if crate::utils::is_running_under_deck_game_mode() {
+            Self::GameMode
+        } else {
+            Self::DesktopMode
         }
     }
 }
@@ -187,7 +296,30 @@ pub enum ProviderConfig {
     GeForceInstaller(GeForceInstaller),
     SimpleCommand(SimpleCommand),
     SystemdRun(SystemdRun),
+    Bundle(Bundle),
+    SshSession(SshSession),
     //SystemPackage(SystemPackage)
+
+    // Anything else is handled by a provider registered at runtime (see providers::registry)
+    #[serde(untagged)]
+    Registered(RegisteredProviderConfig),
+}
+
+impl ProviderConfig {
+    #[must_use]
+    pub fn type_tag(&self) -> &str {
+        match self {
+            ProviderConfig::Flatpak(_) => FlatpakRegistration.type_tag(),
+            ProviderConfig::DeckyInstaller(_) => DeckyInstallerRegistration.type_tag(),
+            ProviderConfig::EmuDeckInstaller(_) => EmuDeckInstallerRegistration.type_tag(),
+            ProviderConfig::GeForceInstaller(_) => GeForceInstallerRegistration.type_tag(),
+            ProviderConfig::SimpleCommand(_) => SimpleCommandRegistration.type_tag(),
+            ProviderConfig::SystemdRun(_) => SystemdRunRegistration.type_tag(),
+            ProviderConfig::Bundle(_) => BundleRegistration.type_tag(),
+            ProviderConfig::SshSession(_) => SshSessionRegistration.type_tag(),
+            ProviderConfig::Registered(raw) => &raw.type_tag,
+        }
+    }
 }
 
 impl std::fmt::Display for ProviderConfig {
@@ -199,22 +331,51 @@ impl std::fmt::Display for ProviderConfig {
             ProviderConfig::GeForceInstaller(_) => write!(f, "GeForceInstaller"),
             ProviderConfig::SimpleCommand(_) => write!(f, "SimpleCommand"),
             ProviderConfig::SystemdRun(_) => write!(f, "SystemdRun"),
+            ProviderConfig::Bundle(_) => write!(f, "Bundle"),
+            ProviderConfig::SshSession(_) => write!(f, "SshSession"),
+            ProviderConfig::Registered(raw) => write!(f, "{}", raw.type_tag),
         }
     }
 }
 
+// The raw config for a provider type that decktricks itself doesn't know about. The provider's
+// registration is responsible for parsing the rest of the fields.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegisteredProviderConfig {
+    #[serde(rename = "type")]
+    pub type_tag: String,
+    #[serde(flatten)]
+    pub config: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RegisteredProviderConfig {
+    /// # Errors
+    ///
+    /// Returns `ConfigParsing` if the fields don't match what the provider expects.
+    pub fn parse<T: DeserializeOwned>(&self) -> DeckResult<T> {
+        serde_json::from_value(serde_json::Value::Object(self.config.clone()))
+            .map_err(KnownError::from)
+    }
+}
+
+#[skip_serializing_none]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Flatpak {
     pub id: String,
+    // Passed into the sandbox as `flatpak run --env=KEY=VALUE`
+    pub env: Option<EnvVars>,
 }
 
+#[skip_serializing_none]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SimpleCommand {
     pub command: String,
     pub args: Option<Vec<String>>,
     pub execution_dir: Option<String>,
+    pub env: Option<EnvVars>,
 }
 
+#[skip_serializing_none]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SystemdRun {
     pub unit_id: String,
@@ -222,39 +383,82 @@ pub struct SystemdRun {
     pub args: Option<Vec<String>>,
     pub execution_dir: Option<String>,
     pub is_system: Option<bool>,
+    // Passed through as `--property=KEY=VALUE`, e.g. "MemoryMax": "2G" or "CPUQuota": "50%"
+    pub properties: Option<BTreeMap<String, String>>,
+    // Passed through as `--setenv=KEY=VALUE`
+    pub env: Option<EnvVars>,
 }
 
 impl SystemdRun {
+    pub(crate) fn is_system(&self) -> bool {
+        self.is_system.unwrap_or(false)
+    }
+
     pub(crate) fn get_as_args(&self) -> Vec<String> {
-        let mut args = vec!["--user", "--collect"]
-            .iter()
-            .map(ToString::to_string)
-            .collect::<Vec<_>>();
+        let mut args = vec![];
+
+        // System units are run through a privileged path by the provider, see
+        // providers/systemd_run.rs
+        if !self.is_system() {
+            args.push("--user".to_string());
+        }
+        args.push("--collect".to_string());
 
         if let Some(ex) = &self.execution_dir {
-            let dir_arg = format!("--working-directory={}", ex);
-            args.push(dir_arg)
-        };
+            args.push(format!("--working-directory={ex}"));
+        }
+
+        for (key, value) in self.properties.iter().flatten() {
+            args.push(format!("--property={key}={value}"));
+        }
+
+        for (key, value) in self.env.iter().flatten() {
+            args.push(format!("--setenv={key}={value}"));
+        }
 
         args.push(format!("--unit={}", self.unit_id));
         args.push(self.command.clone());
 
         if let Some(cmd_args) = &self.args {
             args.extend_from_slice(cmd_args);
-        };
+        }
 
         args
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct DeckyInstaller;
+// The installers below download and run a script or binary. Its "sha256" and "signature" are
+// given alongside "type" in the provider config.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DeckyInstaller {
+    #[serde(flatten)]
+    pub verification: DownloadVerification,
+}
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct EmuDeckInstaller;
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct EmuDeckInstaller {
+    #[serde(flatten)]
+    pub verification: DownloadVerification,
+}
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GeForceInstaller {
+    #[serde(flatten)]
+    pub verification: DownloadVerification,
+}
+
+// A curated set of other tricks, installed together
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct GeForceInstaller;
+pub struct Bundle {
+    pub members: Vec<TrickID>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SshSession {
+    // Defaults to 2222, so that it never collides with a system sshd
+    pub port: Option<u16>,
+}
 
 //#[derive(Debug, Deserialize, Serialize)]
 //struct SystemPackage {
@@ -268,12 +472,16 @@ fn reconvert_providerconfig() -> DeckResult<()> {
     let id = "net.davidotek.pupgui2";
     let trick = Trick {
         id: id.into(),
-        provider_config: ProviderConfig::Flatpak(Flatpak { id: id.into() }),
+        provider_config: ProviderConfig::Flatpak(Flatpak {
+            id: id.into(),
+            env: Some(EnvVars::from([("FOO".into(), "bar".into())])),
+        }),
         description: "lol".into(),
         categories: vec![],
         display_name: "ProtonUp-Qt".into(),
         always_present_on_steamdeck: None,
         icon: None,
+        warnings: vec![],
     };
 
     let after_first_serialization =
@@ -289,6 +497,98 @@ fn reconvert_providerconfig() -> DeckResult<()> {
     Ok(())
 }
 
+#[test]
+fn warnings_survive_reserialization() -> DeckResult<()> {
+    let trick = Trick {
+        warnings: vec![ActionWarning {
+            action: SpecificActionID::Run,
+            severity: WarningSeverity::Warning,
+            text: "lol".into(),
+            only_in: Some(SessionMode::GameMode),
+        }],
+        ..Trick::test()
+    };
+
+    let serialized = serde_json::to_string(&trick).map_err(KnownError::from)?;
+    let trick_again = serde_json::from_str::<Trick>(&serialized).map_err(KnownError::from)?;
+    assert_eq!(trick.warnings, trick_again.warnings);
+    Ok(())
+}
+
+#[test]
+fn warnings_only_apply_to_their_action_and_mode() {
+    let warning: ActionWarning = serde_json::from_str(
+        r#"{"action": "install", "text": "lol", "only_in": "game-mode"}"#,
+    )
+    .unwrap();
+    assert_eq!(warning.severity, WarningSeverity::Note);
+    assert_eq!(warning.to_string(), "NOTE! lol");
+
+    assert!(warning.applies_to(&SpecificActionID::Install, SessionMode::GameMode));
+    assert!(!warning.applies_to(&SpecificActionID::Install, SessionMode::DesktopMode));
+    assert!(!warning.applies_to(&SpecificActionID::Run, SessionMode::GameMode));
+
+    let anywhere = ActionWarning {
+        only_in: None,
+        ..warning
+    };
+    assert!(anywhere.applies_to(&SpecificActionID::Install, SessionMode::DesktopMode));
+}
+
+#[test]
+fn installers_take_verification_next_to_type() {
+    let config: ProviderConfig = serde_json::from_str(
+        r#"{
+            "type": "decky-installer",
+            "sha256": "abc123",
+            "signature": {"url": "https://example.com/x.sig", "keyring": "/etc/keys.gpg"}
+        }"#,
+    )
+    .unwrap();
+    let ProviderConfig::DeckyInstaller(decky) = config else {
+        panic!("Parsed as the wrong provider config: {config:?}");
+    };
+    assert_eq!(Some("abc123".into()), decky.verification.sha256);
+    assert_eq!(
+        "/etc/keys.gpg",
+        decky.verification.signature.unwrap().keyring
+    );
+
+    let config: ProviderConfig = serde_json::from_str(r#"{"type": "ge-force-installer"}"#).unwrap();
+    assert!(
+        matches!(config, ProviderConfig::GeForceInstaller(geforce) if geforce.verification.is_empty())
+    );
+}
+
+#[test]
+fn systemd_run_args_respect_scope_and_properties() {
+    let mut systemd_run = SystemdRun {
+        unit_id: "decktricks-test".into(),
+        command: "/usr/bin/sleep".into(),
+        args: Some(vec!["10".into()]),
+        execution_dir: None,
+        is_system: None,
+        properties: Some(BTreeMap::from([("MemoryMax".into(), "2G".into())])),
+        env: Some(BTreeMap::from([("FOO".into(), "bar".into())])),
+    };
+
+    assert_eq!(
+        systemd_run.get_as_args(),
+        vec![
+            "--user",
+            "--collect",
+            "--property=MemoryMax=2G",
+            "--setenv=FOO=bar",
+            "--unit=decktricks-test",
+            "/usr/bin/sleep",
+            "10"
+        ]
+    );
+
+    systemd_run.is_system = Some(true);
+    assert!(!systemd_run.get_as_args().contains(&"--user".to_string()));
+}
+
 // Integration test of the actual config
 #[test]
 fn integration_check_default_config() -> DeckResult<()> {
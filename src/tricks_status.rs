@@ -20,6 +20,10 @@ pub struct ActionDisplayStatus {
     pub is_available: bool,
     pub is_ongoing: bool,
     pub is_completed: bool,
+    // Shown as a tooltip in the GUI, None if the action is available
+    pub unavailable_reason: Option<String>,
+    // From the config, to be confirmed before the action is taken
+    pub warnings: Vec<String>,
 }
 
 pub struct AllTricksStatus(BTreeMap<TrickID, TrickStatus>);
@@ -40,7 +44,7 @@ impl AllTricksStatus {
             let mut actions = vec![];
             for action_id in all_actions {
                 let is_ongoing = match action_id {
-                    SpecificActionID::Install => is_installing,
+                    SpecificActionID::Install | SpecificActionID::Reinstall => is_installing,
                     SpecificActionID::Run => is_running,
                     _ => false,
                 };
@@ -49,12 +53,24 @@ impl AllTricksStatus {
                     _ => false,
                 };
                 let is_available = available_actions.contains(&action_id);
+                let unavailable_reason = if is_available {
+                    None
+                } else {
+                    provider.get_availability(&action_id).err().map(|r| r.to_string())
+                };
+                let warnings = trick
+                    .get_warnings(&action_id)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
                 actions.push(ActionDisplayStatus {
                     trick: trick.clone(),
                     action_id,
                     is_available,
                     is_ongoing,
                     is_completed,
+                    unavailable_reason,
+                    warnings,
                 });
             }
 
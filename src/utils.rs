@@ -1,3 +1,4 @@
+use crate::download::DownloadVerification;
 use crate::prelude::*;
 use std::fmt::Display;
 use std::path::Path;
@@ -28,69 +29,23 @@ pub fn running_in_ci_container() -> bool {
     false
 }
 
-#[allow(clippy::unnecessary_wraps)]
-#[cfg(test)]
-#[must_use]
-pub(crate) fn fetch_and_prep_remote_executable(
-    ctx: &impl ExecCtx,
-    url: &str,
-    local_filename: &str,
-) -> DeckResult<SysCommand> {
-    warn!(
-        ExecutionContext::general_for_test(),
-        "Not running run_remote_executable({url}, {local_filename}) from test..."
-    );
-    Ok(ctx.sys_command_no_args("echo"))
-}
-
-#[cfg(not(test))]
-#[must_use]
-pub(crate) fn fetch_and_prep_remote_executable(
-    ctx: &impl ExecCtx,
+// Downloads the installer at `url` to `local_filename`, and runs it once it has been reviewed
+pub(crate) fn fetch_and_run_remote_executable(
+    ctx: &SpecificExecutionContext,
     url: &str,
+    verification: &DownloadVerification,
     local_filename: &str,
-) -> DeckResult<SysCommand> {
-    use std::fs::File;
-    use std::os::unix::fs::PermissionsExt;
-    use ureq;
-
-    // TODO: make this and the operations below test-safe
-    let data = ureq::get(url).call().map_err(|e| {
-        KnownError::RemoteScriptError(format!("Failed downloading local script file: {e:#?}"))
-    })?;
-    // let response = reqwest::blocking::get(url).map_err(KnownError::from)?;
-    // let data = response.bytes()?.as_ref();
-
-    // These are in blocks to ensure that files are closed out
-    // before attempting to do further changes
-    {
-        let mut dest = File::create(local_filename).map_err(|e| {
-            KnownError::RemoteScriptError(format!("Failed to create local script file: {e:#?}"))
-        })?;
-
-        std::io::copy(&mut data.into_reader(), &mut dest).map_err(|e| {
-            KnownError::RemoteScriptError(format!("Failed to write local script file: {e:#?}"))
-        })?;
-        //copy(&mut response.into_reader().take(10_000_000).?, &mut dest)?;
-    };
-
-    {
-        std::fs::set_permissions(local_filename, std::fs::Permissions::from_mode(0o755)).map_err(
-            |e| {
-                KnownError::RemoteScriptError(format!(
-                    "Failed to set permissions for local script file: {e:#?}"
-                ))
-            },
-        )?;
-    }
-
-    let mut sys_command = ctx.sys_command_no_args(local_filename);
-    sys_command
-        // TODO: is force_pty needed for geforce now to have live logs?
-        .force_pty()
-        .enable_live_logging();
-
-    Ok(sys_command)
+) -> DeckResult<SysCommandResult> {
+    let local_path = Path::new(local_filename);
+    crate::download::download_executable(ctx, url, verification, local_path)?;
+    crate::script_review::review_before_running(ctx, url, local_path, || {
+        ctx.sys_command_no_args(local_filename)
+            .env(INSTALLING_ENV_STRING, ctx.trick.id.as_ref())
+            // TODO: is force_pty needed for geforce now to have live logs?
+            .force_pty()
+            .enable_live_logging()
+            .run()
+    })
 }
 
 pub fn get_homedir() -> &'static str {
@@ -107,7 +62,7 @@ pub fn get_decktricks_update_log_file_location() -> PathBuf {
 
 pub fn exists_and_executable(ctx: &impl ExecCtx, path: &str) -> bool {
     // Using this instead of rust-native code to piggyback on the test-friendliness of SysCommand
-    let res = ctx.sys_command("/bin/test", ["-x", path]).run();
+    let res = ctx.sys_command("/bin/test", ["-x", path]).read_only().run();
 
     match res {
         Ok(cmdres) => cmdres.ran_successfully(),
@@ -122,6 +77,7 @@ pub(crate) fn get_running_pids_exact(
 ) -> DeckResult<Vec<String>> {
     Ok(ctx
         .sys_command("ps", ["-C", binary_name, "-o", "pid="])
+        .read_only()
         .run()?
         .as_success()?
         .get_message_or_blank()
@@ -133,6 +89,7 @@ pub(crate) fn get_running_pids_exact(
 pub(crate) fn pgrep(ctx: &impl ExecCtx, pattern: &str) -> DeckResult<Vec<String>> {
     Ok(ctx
         .sys_command("pgrep", ["-f", pattern])
+        .read_only()
         .run()?
         .as_success()?
         .get_message_or_blank()
@@ -174,6 +131,11 @@ pub fn which<T: AsRef<std::ffi::OsStr> + Display>(binary_name: T) -> DeckResult<
         .to_string())
 }
 
+#[must_use]
+pub fn get_current_user() -> String {
+    std::env::var("USER").unwrap_or_else(|_| DEFAULT_USER.into())
+}
+
 pub fn is_running_under_steam() -> bool {
     std::env::var("SteamEnv").is_ok_and(|v| v == "1")
 }
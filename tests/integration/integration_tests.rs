@@ -1,11 +1,11 @@
 use steam_shortcuts_util::Shortcut;
 use crate::decktricks_cli;
+use crate::integration::utils::cli_command;
 use crate::integration::utils::run_cli_with_args;
-use crate::integration::utils::BINARY_NAME;
 use decktricks::prelude::DynamicError;
 use std::collections::HashMap;
 use std::{
-    process::Command,
+    io::{BufRead, BufReader},
     time::{Duration, Instant},
 };
 
@@ -55,7 +55,8 @@ fn time_see_all_available_actions() -> Result<(), DynamicError> {
 // it returns successfully and doesn't have any chatty stderr
 #[test]
 fn simple_command_harblgarbl() -> Result<(), DynamicError> {
-    let output = Command::new(BINARY_NAME)
+    let state_dir = tempfile::tempdir()?;
+    let output = cli_command(state_dir.path())
         .args(vec![
             "-c",
             "tests/integration/test_config.json",
@@ -77,6 +78,159 @@ fn simple_command_harblgarbl() -> Result<(), DynamicError> {
     Ok(())
 }
 
+#[test]
+fn run_passes_extra_args_through() -> Result<(), DynamicError> {
+    let output = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "run",
+        "print-HARBLGARBL",
+        "--",
+        "--fullscreen",
+        "/path/to/rom"
+    ]?;
+    assert_eq!("HARBLGARBL --fullscreen /path/to/rom", output.trim());
+    Ok(())
+}
+
+#[test]
+fn warnings_need_confirmation() -> Result<(), DynamicError> {
+    // stdin is not a terminal here, so we should refuse without --yes
+    let res = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "run",
+        "print-HARBLGARBL2"
+    ];
+    let err_text = res.unwrap_err().to_string();
+    assert!(err_text.contains("DANGER! HARBLGARBL2 is very loud."));
+    assert!(err_text.contains("Pass --yes to go ahead anyway."));
+
+    let output = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "--yes",
+        "run",
+        "print-HARBLGARBL2"
+    ]?;
+    assert_eq!("HARBLGARBL2", output.trim());
+    Ok(())
+}
+
+#[test]
+fn ctrl_c_cancels_running_command() -> Result<(), DynamicError> {
+    let state_dir = tempfile::tempdir()?;
+    let mut child = cli_command(state_dir.path())
+        .args(["-c", "tests/integration/test_config.json", "run", "sleep-a-while"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    // The command says when it has started, so that it's the one interrupted
+    let mut stdout = BufReader::new(child.stdout.take().ok_or("no stdout")?);
+    let mut line = String::new();
+    while line.trim() != "ready" {
+        line.clear();
+        if stdout.read_line(&mut line)? == 0 {
+            return Err("command never started".into());
+        }
+    }
+    let start = Instant::now();
+    nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(child.id().cast_signed()),
+        nix::sys::signal::Signal::SIGINT,
+    )?;
+    let output = child.wait_with_output()?;
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Cancelled system command: `/usr/bin/sh -c"));
+    Ok(())
+}
+
+#[test]
+fn no_wait_fails_on_held_lock() -> Result<(), DynamicError> {
+    let shortcuts_file = tempfile::NamedTempFile::new()?;
+    let lock_dir = tempfile::tempdir()?;
+    let envs = HashMap::from([
+        (
+            "DECKTRICKS_OVERRIDE_STEAM_SHORTCUTS_FILE".into(),
+            shortcuts_file.path().to_str().unwrap().into(),
+        ),
+        (
+            "DECKTRICKS_LOCK_DIR".into(),
+            lock_dir.path().to_str().unwrap().into(),
+        ),
+    ]);
+    let args = vec![
+        "--no-wait",
+        "-c",
+        "tests/integration/test_config.json",
+        "add-to-steam",
+        "print-HARBLGARBL",
+    ];
+
+    // Another decktricks, which is busy with the shortcuts as far as anyone can tell
+    let state_dir = tempfile::tempdir()?;
+    let holder = cli_command(state_dir.path())
+        .args(["-c", "tests/integration/test_config.json", "run", "sleep-a-while"])
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    let cmdline_path = format!("/proc/{}/cmdline", holder.id());
+    while !std::fs::read_to_string(&cmdline_path)?.contains("decktricks") {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let lock_file = lock_dir.path().join("resource-steam-shortcuts.lock");
+    std::fs::write(
+        &lock_file,
+        format!(
+            r#"{{"pid":{},"description":"adding to Steam","started":"2024-01-01T00:00:00Z"}}"#,
+            holder.id()
+        ),
+    )?;
+
+    let res = run_cli_with_args(args.clone(), Some(envs.clone()));
+    nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(holder.id().cast_signed()),
+        nix::sys::signal::Signal::SIGINT,
+    )?;
+    holder.wait_with_output()?;
+    let err = res.unwrap_err().to_string();
+    assert!(err.contains("Could not get the steam-shortcuts lock"));
+    assert!(err.contains("adding to Steam"));
+
+    // Now that it's gone, the lock is stale and is taken over
+    run_cli_with_args(args, Some(envs))?;
+    assert!(!lock_file.exists());
+    Ok(())
+}
+
+#[test]
+fn network_uses_fixture() -> Result<(), DynamicError> {
+    let state_dir = tempfile::tempdir()?;
+    let output = cli_command(state_dir.path())
+        .env(
+            "DECKTRICKS_NETWORK_FIXTURE",
+            "tests/integration/network_fixture.json",
+        )
+        .env("USER", "deck")
+        .args(vec!["network", "--port", "2222"])
+        .output()?;
+
+    if !output.status.success() {
+        panic!("{:#?}", output);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("192.168.1.23 (primary)"));
+    assert!(stdout.contains("172.17.0.1"));
+    assert!(!stdout.contains("127.0.0.1"));
+    assert!(stdout.contains("ssh -p 2222 deck@192.168.1.23"));
+    assert!(stdout.contains("sshfs -p 2222 deck@192.168.1.23: <MOUNTPOINT>"));
+    Ok(())
+}
+
 #[test]
 fn test_config_exclusivity() -> Result<(), DynamicError> {
     let output = decktricks_cli!["-c", "tests/integration/test_config.json", "actions"]?;
@@ -88,6 +242,33 @@ fn test_config_exclusivity() -> Result<(), DynamicError> {
     Ok(())
 }
 
+#[test]
+fn actions_explain_gives_reasons() -> Result<(), DynamicError> {
+    let output = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "actions",
+        "print-HARBLGARBL",
+        "--explain",
+        "--json"
+    ]?;
+    let state: serde_json::Value = serde_json::from_str(&output)?;
+    let all_actions = state["all_actions"].as_array().unwrap();
+    let kill = all_actions
+        .iter()
+        .find(|action| action["action"] == "kill")
+        .unwrap();
+    assert_eq!(false, kill["is_available"]);
+    assert_eq!("not_running", kill["reason"]["kind"]);
+    assert!(all_actions
+        .iter()
+        .any(|action| action["action"] == "run" && action["is_available"] == true));
+
+    let gated = decktricks_cli!["-c", "tests/integration/test_config.json", "kill", "print-HARBLGARBL"];
+    assert!(gated.unwrap_err().to_string().contains("It is not running."));
+    Ok(())
+}
+
 // TODO: check stderr / log call on broken_config_falls_back_to_default and missing_config_gives_error
 #[test]
 fn broken_config_falls_back_to_default() -> Result<(), DynamicError> {
@@ -136,6 +317,166 @@ fn bad_arg() -> Result<(), DynamicError> {
     Ok(())
 }
 
+#[test]
+fn batch_selectors_skip_impossible_actions() -> Result<(), DynamicError> {
+    // Nothing in the category is running, so there's nothing to kill
+    let output = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "kill",
+        "--category",
+        "poop"
+    ]?;
+    assert!(output.contains("No tricks matched"));
+
+    // ...but tricks named directly are always attempted
+    let res = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "kill",
+        "print-HARBLGARBL",
+        "print-HARBLGARBL2"
+    ];
+    let err_text = res.unwrap_err().to_string();
+    assert!(err_text.contains("kill: 0 succeeded, 2 failed."));
+    Ok(())
+}
+
+#[test]
+fn dry_run_prints_instead_of_doing() -> Result<(), DynamicError> {
+    let file = tempfile::NamedTempFile::new()?;
+    let filename = file.path().to_str().unwrap();
+    let envs = HashMap::from([(
+        "DECKTRICKS_OVERRIDE_STEAM_SHORTCUTS_FILE".into(),
+        filename.into(),
+    )]);
+
+    let output = run_cli_with_args(
+        vec![
+            "--dry-run",
+            "-c",
+            "tests/integration/test_config.json",
+            "add-to-steam",
+            "print-HARBLGARBL",
+        ],
+        Some(envs),
+    )?;
+    assert!(output.contains(&format!(
+        "[DRY RUN] Would update the Steam shortcuts in \"{filename}\""
+    )));
+    assert!(std::fs::read(filename)?.is_empty());
+
+    let output = decktricks_cli![
+        "-c",
+        "tests/integration/test_config.json",
+        "run",
+        "print-HARBLGARBL",
+        "--dry-run"
+    ]?;
+    assert!(output.contains(
+        "[DRY RUN] Would run: DECKTRICKS_TRICK_ID=print-HARBLGARBL /usr/bin/echo HARBLGARBL"
+    ));
+    assert!(!output.lines().any(|line| line == "HARBLGARBL"));
+    Ok(())
+}
+
+#[test]
+fn history_records_actions() -> Result<(), DynamicError> {
+    let shortcuts_file = tempfile::NamedTempFile::new()?;
+    let history_dir = tempfile::tempdir()?;
+    let history_file = history_dir.path().join("history.jsonl");
+    let envs = HashMap::from([
+        (
+            "DECKTRICKS_OVERRIDE_STEAM_SHORTCUTS_FILE".into(),
+            shortcuts_file.path().to_str().unwrap().into(),
+        ),
+        (
+            "DECKTRICKS_HISTORY_FILE".into(),
+            history_file.to_str().unwrap().into(),
+        ),
+    ]);
+
+    run_cli_with_args(
+        vec![
+            "-c",
+            "tests/integration/test_config.json",
+            "add-to-steam",
+            "print-HARBLGARBL",
+        ],
+        Some(envs.clone()),
+    )?;
+
+    let output = run_cli_with_args(
+        vec!["history", "--json", "--trick", "print-HARBLGARBL"],
+        Some(envs.clone()),
+    )?;
+    let entries: serde_json::Value = serde_json::from_str(output.trim())?;
+    let entries = entries.as_array().unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!("add-to-steam", entries[0]["action"]);
+    assert_eq!("success", entries[0]["outcome"]);
+
+    let output = run_cli_with_args(vec!["history", "--failed"], Some(envs))?;
+    assert!(output.contains("No matching actions in history."));
+    Ok(())
+}
+
+#[test]
+fn profile_apply_reconciles_shortcuts() -> Result<(), DynamicError> {
+    let shortcuts_file = tempfile::NamedTempFile::new()?;
+    let profile_dir = tempfile::tempdir()?;
+    let profile_file = profile_dir.path().join("profile.json");
+    let profile_filename = profile_file.to_str().unwrap();
+    let envs = HashMap::from([(
+        "DECKTRICKS_OVERRIDE_STEAM_SHORTCUTS_FILE".into(),
+        shortcuts_file.path().to_str().unwrap().into(),
+    )]);
+
+    run_cli_with_args(
+        vec![
+            "-c",
+            "tests/integration/test_config.json",
+            "profile",
+            "export",
+            "--output",
+            profile_filename,
+        ],
+        Some(envs.clone()),
+    )?;
+    let mut profile: serde_json::Value = serde_json::from_slice(&std::fs::read(&profile_file)?)?;
+    assert_eq!(serde_json::json!([]), profile["added_to_steam"]);
+
+    profile["added_to_steam"] = serde_json::json!(["print-HARBLGARBL"]);
+    std::fs::write(&profile_file, profile.to_string())?;
+    let apply = |extra_args: &[&str]| {
+        let mut args = vec![
+            "-c",
+            "tests/integration/test_config.json",
+            "profile",
+            "apply",
+            profile_filename,
+        ];
+        args.extend(extra_args);
+        run_cli_with_args(args, Some(envs.clone()))
+    };
+
+    let output = apply(&["--dry-run"])?;
+    assert!(output.contains("+ add-to-steam       print-HARBLGARBL"));
+    assert!(std::fs::read(shortcuts_file.path())?.is_empty());
+
+    let output = apply(&[])?;
+    assert!(output.contains("profile apply: 1 succeeded, 0 failed."));
+    assert!(apply(&[])?.contains("This machine already matches the profile."));
+
+    profile["added_to_steam"] = serde_json::json!([]);
+    std::fs::write(&profile_file, profile.to_string())?;
+    assert!(apply(&[])?.contains("This machine already matches the profile."));
+    let output = apply(&["--prune"])?;
+    assert!(output.contains("- remove-from-steam  print-HARBLGARBL"));
+    assert!(output.contains("profile apply: 1 succeeded, 0 failed."));
+    Ok(())
+}
+
 #[test]
 fn can_add_to_steam() -> Result<(), DynamicError> {
     let file = tempfile::NamedTempFile::new()?;
@@ -290,6 +631,60 @@ fn add_decktricks_to_steam() -> Result<(), DynamicError> {
     Ok(())
 }
 
+#[test]
+fn list_and_remove_steam_shortcuts() -> Result<(), DynamicError> {
+    let file = tempfile::NamedTempFile::new()?;
+    let filename = file.path().to_str().unwrap();
+
+    let envs = HashMap::from([(
+        "DECKTRICKS_OVERRIDE_STEAM_SHORTCUTS_FILE".into(),
+        filename.into(),
+    )]);
+    for trick_id in ["print-HARBLGARBL", "print-HARBLGARBL2"] {
+        run_cli_with_args(
+            vec!["-c", "tests/integration/test_config.json", "add-to-steam", trick_id],
+            Some(envs.clone()),
+        )?;
+    }
+
+    let listed = run_cli_with_args(
+        vec!["-c", "tests/integration/test_config.json", "steam", "shortcuts", "list"],
+        Some(envs.clone()),
+    )?;
+    assert!(listed.contains("print-HARBLGARBL "));
+    assert!(listed.contains("print-HARBLGARBL2"));
+
+    run_cli_with_args(
+        vec![
+            "-c",
+            "tests/integration/test_config.json",
+            "remove-from-steam",
+            "print-HARBLGARBL",
+        ],
+        Some(envs.clone()),
+    )?;
+
+    let remaining_content = std::fs::read(filename)?;
+    let remaining = steam_shortcuts_util::parse_shortcuts(&remaining_content)?;
+    assert_eq!(remaining.len(), 1);
+    assert!(remaining[0].tags.contains(&"decktricks-print-HARBLGARBL2"));
+
+    // Nothing left to remove, so the action isn't available
+    let res = run_cli_with_args(
+        vec![
+            "-c",
+            "tests/integration/test_config.json",
+            "remove-from-steam",
+            "print-HARBLGARBL",
+        ],
+        Some(envs),
+    );
+    assert!(res.is_err());
+
+    file.close()?;
+    Ok(())
+}
+
 #[cfg(test)]
 fn shortcut_eq(input: &Shortcut, expected: &Shortcut) {
     assert_eq!(input.order, expected.order);
@@ -1,15 +1,30 @@
+use decktricks::history::HISTORY_FILE_ENV_VAR;
+use decktricks::locks::LOCK_DIR_ENV_VAR;
 use decktricks::{errors::DecktricksError, prelude::DynamicError};
 use std::collections::HashMap;
+use std::path::Path;
 
 use std::process::Command;
 
 pub const BINARY_NAME: &str = env!("CARGO_BIN_EXE_decktricks");
 type CliResult = Result<String, DynamicError>;
+
+// Whatever decktricks keeps between runs (history, locks) goes in `state_dir`, instead of in the
+// developer's own ~/.local/share/decktricks (and tests don't share it with each other either)
+pub(crate) fn cli_command(state_dir: &Path) -> Command {
+    let mut cmd = Command::new(BINARY_NAME);
+    cmd.env(HISTORY_FILE_ENV_VAR, state_dir.join("history.jsonl"))
+        .env(LOCK_DIR_ENV_VAR, state_dir.join("locks"));
+    cmd
+}
+
+// Anything in `envs` wins over the defaults from cli_command
 pub(crate) fn run_cli_with_args(
     args: Vec<&str>,
     envs: Option<HashMap<String, String>>,
 ) -> CliResult {
-    let mut cmd = Command::new(BINARY_NAME);
+    let state_dir = tempfile::tempdir()?;
+    let mut cmd = cli_command(state_dir.path());
     cmd.args(args.clone());
 
     if let Some(envs) = envs {